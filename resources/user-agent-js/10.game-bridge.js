@@ -0,0 +1,83 @@
+// Message bridge between UI pages and the game, see src/servo_ui/bridge.rs.
+//
+// game.send(name, payload)     send a message to Rust
+// game.on(name, callback)      receive messages sent from Rust
+// game.call(method, args)      call a registered Rust handler, returns a promise
+(function () {
+    if (window.game) {
+        return;
+    }
+
+    var outgoing = [];
+    var flushQueued = false;
+    var listeners = {};
+    var nextCallId = 1;
+    var pendingCalls = {};
+
+    // Everything sent in one tick goes out in a single navigation, since a second navigation
+    // would cancel the first before Rust saw it.
+    function flush() {
+        flushQueued = false;
+        if (outgoing.length === 0) {
+            return;
+        }
+        var batch = outgoing;
+        outgoing = [];
+        window.location.href = "game://messages?" + encodeURIComponent(JSON.stringify(batch));
+    }
+
+    var game = {
+        send: function (name, payload) {
+            outgoing.push({ name: name, payload: payload === undefined ? null : payload });
+            if (!flushQueued) {
+                flushQueued = true;
+                setTimeout(flush, 0);
+            }
+        },
+
+        on: function (name, callback) {
+            (listeners[name] = listeners[name] || []).push(callback);
+        },
+
+        off: function (name, callback) {
+            var callbacks = listeners[name] || [];
+            var index = callbacks.indexOf(callback);
+            if (index >= 0) {
+                callbacks.splice(index, 1);
+            }
+        },
+
+        call: function (method, args) {
+            var id = nextCallId++;
+            return new Promise(function (resolve, reject) {
+                pendingCalls[id] = { resolve: resolve, reject: reject };
+                game.send("rpc.call", { id: id, method: method, args: args === undefined ? null : args });
+            });
+        },
+
+        __receive: function (name, payload) {
+            (listeners[name] || []).slice().forEach(function (callback) {
+                try {
+                    callback(payload);
+                } catch (e) {
+                    console.error("Error in '" + name + "' handler: " + e);
+                }
+            });
+        }
+    };
+
+    game.on("rpc.reply", function (reply) {
+        var call = pendingCalls[reply.id];
+        if (!call) {
+            return;
+        }
+        delete pendingCalls[reply.id];
+        if (reply.error !== undefined) {
+            call.reject(new Error(reply.error));
+        } else {
+            call.resolve(reply.result);
+        }
+    });
+
+    window.game = game;
+})();
@@ -0,0 +1,74 @@
+// Reactive store of game data, see src/servo_ui/binding.rs.
+//
+// var unsubscribe = game.store.subscribe("player", function (player) { ... });
+// game.store.get("player")
+(function () {
+    if (!window.game || game.store) {
+        return;
+    }
+
+    var state = {};
+    var callbacks = {};
+
+    // Applies a JSON merge patch (RFC 7386)
+    function applyPatch(target, patch) {
+        if (patch === null || typeof patch !== "object" || Array.isArray(patch)) {
+            return patch;
+        }
+        if (target === null || typeof target !== "object" || Array.isArray(target)) {
+            target = {};
+        }
+        Object.keys(patch).forEach(function (key) {
+            if (patch[key] === null) {
+                delete target[key];
+            } else {
+                target[key] = applyPatch(target[key], patch[key]);
+            }
+        });
+        return target;
+    }
+
+    function notify(name) {
+        (callbacks[name] || []).slice().forEach(function (callback) {
+            callback(state[name]);
+        });
+    }
+
+    game.store = {
+        get: function (name) {
+            return state[name];
+        },
+
+        subscribe: function (name, callback) {
+            if (!callbacks[name]) {
+                callbacks[name] = [];
+                game.send("store.subscribe", { names: [name] });
+            } else if (state.hasOwnProperty(name)) {
+                callback(state[name]);
+            }
+            callbacks[name].push(callback);
+            return function () {
+                var list = callbacks[name] || [];
+                var index = list.indexOf(callback);
+                if (index >= 0) {
+                    list.splice(index, 1);
+                }
+                if (list.length === 0) {
+                    delete callbacks[name];
+                    delete state[name];
+                    game.send("store.unsubscribe", { names: [name] });
+                }
+            };
+        }
+    };
+
+    game.on("store.snapshot", function (message) {
+        state[message.name] = message.value;
+        notify(message.name);
+    });
+
+    game.on("store.patch", function (message) {
+        state[message.name] = applyPatch(state[message.name], message.patch);
+        notify(message.name);
+    });
+})();
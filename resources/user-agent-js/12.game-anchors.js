@@ -0,0 +1,61 @@
+// Places elements over anchored entities, see src/servo_ui/anchor.rs.
+//
+// Anchors naming an element id move that element. Anchors naming a <template> get a clone of the
+// template's first element per entity, marked with data-anchor-entity, which is removed again
+// once the entity is no longer anchored. An entity reusing a deleted entity's id gets a fresh
+// clone, since its generation differs.
+(function () {
+    if (!window.game) {
+        return;
+    }
+
+    var clones = {};
+
+    function cloneKey(templateId, anchor) {
+        return templateId + ":" + anchor.entity + ":" + anchor.generation;
+    }
+
+    function templateClone(templateId, anchor) {
+        var key = cloneKey(templateId, anchor);
+        if (!clones[key]) {
+            var template = document.getElementById(templateId);
+            if (!template || !template.content || !template.content.firstElementChild) {
+                return null;
+            }
+            var element = template.content.firstElementChild.cloneNode(true);
+            element.setAttribute("data-anchor-entity", anchor.entity);
+            (template.parentNode || document.body).appendChild(element);
+            clones[key] = element;
+        }
+        return clones[key];
+    }
+
+    game.on("anchors.update", function (update) {
+        var scale = window.devicePixelRatio || 1;
+        var live = {};
+        update.anchors.forEach(function (anchor) {
+            var element;
+            if (anchor.element.id !== undefined) {
+                element = document.getElementById(anchor.element.id);
+            } else {
+                element = templateClone(anchor.element.template, anchor);
+                live[cloneKey(anchor.element.template, anchor)] = true;
+            }
+            if (!element) {
+                return;
+            }
+            element.style.position = "absolute";
+            element.style.left = "0";
+            element.style.top = "0";
+            element.style.transform = "translate(" + anchor.x / scale + "px, " + anchor.y / scale + "px)";
+            element.style.zIndex = Math.round((1 - anchor.depth) * 10000);
+            element.style.display = anchor.visible ? "" : "none";
+        });
+        Object.keys(clones).forEach(function (key) {
+            if (!live[key]) {
+                clones[key].parentNode.removeChild(clones[key]);
+                delete clones[key];
+            }
+        });
+    });
+})();
@@ -0,0 +1,48 @@
+// Reports what the game's input router needs to know about the page, see
+// src/servo_ui/input.rs.
+//
+// input.pointer says whether the element under the pointer accepts pointer events. Elements with
+// `pointer-events: none` are skipped by elementFromPoint, so a HUD can set it on its body and only
+// its panels will take clicks. input.focus says whether an element other than the body has focus,
+// in which case keyboard input goes to the page instead of the game.
+(function () {
+    if (!window.game || window.top !== window) {
+        return;
+    }
+
+    var pointer = true;
+    var focused = false;
+
+    document.addEventListener("mousemove", function (event) {
+        var element = document.elementFromPoint(event.clientX, event.clientY);
+        var ui = element !== null && element !== document.documentElement;
+        if (ui !== pointer) {
+            pointer = ui;
+            game.send("input.pointer", { ui: ui });
+        }
+    }, true);
+
+    function reportFocus() {
+        var element = document.activeElement;
+        var now = element !== null && element !== document.body &&
+            element !== document.documentElement;
+        if (now !== focused) {
+            focused = now;
+            game.send("input.focus", { focused: now });
+        }
+    }
+
+    document.addEventListener("focusin", reportFocus, true);
+    document.addEventListener("focusout", function () {
+        // activeElement only changes once focusout has been dispatched
+        setTimeout(reportFocus, 0);
+    }, true);
+
+    // The game took a click outside the page's opaque areas
+    game.on("input.blur", function () {
+        if (document.activeElement && document.activeElement.blur) {
+            document.activeElement.blur();
+        }
+        focused = false;
+    });
+})();
@@ -0,0 +1,136 @@
+// Gamepad focus navigation, see src/servo_ui/navigation.rs.
+//
+// nav.move focuses the nearest focusable element in a direction, nav.confirm clicks the focused
+// element and nav.cancel backs out. Pages can handle cancel themselves by listening for the
+// "game-cancel" event and calling preventDefault, or mark the element to click with
+// data-nav-cancel. Otherwise cancel pops the current state. A ring follows the focused element
+// while the gamepad is in use.
+(function () {
+    if (!window.game || window.top !== window) {
+        return;
+    }
+
+    var FOCUSABLE = "a[href], button, input, select, textarea, [tabindex], [data-nav]";
+
+    var ring = null;
+
+    function visible(element) {
+        var rect = element.getBoundingClientRect();
+        return rect.width > 0 && rect.height > 0 && !element.disabled &&
+            element.getAttribute("tabindex") !== "-1";
+    }
+
+    function candidates() {
+        return Array.prototype.filter.call(document.querySelectorAll(FOCUSABLE), visible);
+    }
+
+    function center(rect) {
+        return { x: rect.left + rect.width / 2, y: rect.top + rect.height / 2 };
+    }
+
+    // Distance along the direction, with sideways distance counting double so elements in line
+    // with the current one win
+    function score(from, to, direction) {
+        var dx = to.x - from.x;
+        var dy = to.y - from.y;
+        var along = { up: -dy, down: dy, left: -dx, right: dx }[direction];
+        var across = direction === "up" || direction === "down" ? Math.abs(dx) : Math.abs(dy);
+        return along > 0 ? along + across * 2 : Infinity;
+    }
+
+    function move(direction) {
+        var elements = candidates();
+        var current = document.activeElement;
+        if (!current || elements.indexOf(current) < 0) {
+            if (elements.length > 0) {
+                focus(elements[0]);
+            }
+            return;
+        }
+        var from = center(current.getBoundingClientRect());
+        var best = null;
+        var bestScore = Infinity;
+        elements.forEach(function (element) {
+            if (element === current) {
+                return;
+            }
+            var value = score(from, center(element.getBoundingClientRect()), direction);
+            if (value < bestScore) {
+                best = element;
+                bestScore = value;
+            }
+        });
+        if (best) {
+            focus(best);
+        }
+    }
+
+    function focus(element) {
+        if (!element.hasAttribute("tabindex") && element.tabIndex < 0) {
+            element.setAttribute("tabindex", "0");
+        }
+        element.focus();
+        if (element.scrollIntoView) {
+            element.scrollIntoView({ block: "nearest" });
+        }
+        showRing(element);
+    }
+
+    function showRing(element) {
+        if (!ring) {
+            ring = document.createElement("div");
+            ring.id = "game-focus-ring";
+            ring.style.cssText = "position: fixed; pointer-events: none; z-index: 2147483645;" +
+                "border: 2px solid #8cf; border-radius: 4px; box-shadow: 0 0 6px #8cf;" +
+                "transition: left 0.1s, top 0.1s, width 0.1s, height 0.1s;";
+            document.body.appendChild(ring);
+        }
+        var rect = element.getBoundingClientRect();
+        ring.style.display = "block";
+        ring.style.left = (rect.left - 4) + "px";
+        ring.style.top = (rect.top - 4) + "px";
+        ring.style.width = rect.width + "px";
+        ring.style.height = rect.height + "px";
+    }
+
+    function hideRing() {
+        if (ring) {
+            ring.style.display = "none";
+        }
+    }
+
+    game.on("nav.move", function (payload) {
+        move(payload.direction);
+    });
+
+    game.on("nav.confirm", function () {
+        var element = document.activeElement;
+        if (element && element !== document.body) {
+            element.click();
+        }
+    });
+
+    game.on("nav.cancel", function () {
+        var target = document.activeElement || document.body;
+        var event = new CustomEvent("game-cancel", { bubbles: true, cancelable: true });
+        if (!target.dispatchEvent(event)) {
+            return;
+        }
+        var cancel = document.querySelector("[data-nav-cancel]");
+        if (cancel) {
+            cancel.click();
+        } else {
+            // Does nothing on the root state, so cancelling there doesn't quit
+            game.call("state.pop");
+        }
+    });
+
+    // The ring only makes sense for gamepad focus, so the mouse hides it
+    document.addEventListener("mousedown", hideRing, true);
+    document.addEventListener("focusout", hideRing, true);
+    window.addEventListener("scroll", function () {
+        if (ring && ring.style.display === "block" && document.activeElement) {
+            showRing(document.activeElement);
+        }
+    }, true);
+})();
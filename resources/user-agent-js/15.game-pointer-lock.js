@@ -0,0 +1,46 @@
+// Pointer Lock API for pages, see src/servo_ui/cursor.rs.
+//
+// Servo doesn't implement pointer lock, so requestPointerLock and exitPointerLock ask the game to
+// grab the cursor instead. While locked the game sends relative motion, which is dispatched to the
+// locked element as mousemove events carrying movementX and movementY.
+(function () {
+    if (!window.game || window.top !== window || Element.prototype.requestPointerLock) {
+        return;
+    }
+
+    var lockElement = null;
+    var requested = null;
+
+    Element.prototype.requestPointerLock = function () {
+        requested = this;
+        game.send("pointer.lock", {});
+    };
+
+    document.exitPointerLock = function () {
+        game.send("pointer.unlock", {});
+    };
+
+    Object.defineProperty(document, "pointerLockElement", {
+        get: function () {
+            return lockElement;
+        }
+    });
+
+    game.on("pointer.lockchange", function (payload) {
+        lockElement = payload.locked ? requested : null;
+        if (!payload.locked) {
+            requested = null;
+        }
+        document.dispatchEvent(new Event("pointerlockchange"));
+    });
+
+    game.on("pointer.motion", function (payload) {
+        if (!lockElement) {
+            return;
+        }
+        var event = new MouseEvent("mousemove", { bubbles: true, cancelable: true });
+        Object.defineProperty(event, "movementX", { value: payload.dx });
+        Object.defineProperty(event, "movementY", { value: payload.dy });
+        lockElement.dispatchEvent(event);
+    });
+})();
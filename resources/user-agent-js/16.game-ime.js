@@ -0,0 +1,162 @@
+// Input method composition for text fields, see src/servo_ui/ime.rs.
+//
+// Servo can't raise composition events itself, so the preedit text from ime.preedit is written
+// into the focused field here, with compositionstart, compositionupdate and compositionend fired
+// around it, and ime.commit replaces it with the final text. The caret of the focused field,
+// measured on a hidden copy of the field, is reported back as ime.caret so the game can place the
+// candidate window.
+(function () {
+    if (!window.game || window.top !== window) {
+        return;
+    }
+
+    // Focused field and the range of its value holding the preedit text
+    var composition = null;
+    var lastCaret = null;
+
+    // Computed styles which decide where the text of a field is laid out
+    var MIRRORED = [
+        "boxSizing", "width", "height", "overflowX", "overflowY",
+        "borderTopWidth", "borderRightWidth", "borderBottomWidth", "borderLeftWidth",
+        "borderTopStyle", "borderRightStyle", "borderBottomStyle", "borderLeftStyle",
+        "paddingTop", "paddingRight", "paddingBottom", "paddingLeft",
+        "fontStyle", "fontVariant", "fontWeight", "fontStretch", "fontSize", "fontFamily",
+        "lineHeight", "textAlign", "textTransform", "textIndent", "letterSpacing", "wordSpacing"
+    ];
+
+    function textField(element) {
+        if (!element) {
+            return false;
+        }
+        var tag = element.tagName;
+        return tag === "TEXTAREA" || (tag === "INPUT" &&
+            /^(text|search|email|url|tel|password|)$/.test(element.type || ""));
+    }
+
+    function fire(element, type, data) {
+        var event = new Event(type, { bubbles: true, cancelable: false });
+        Object.defineProperty(event, "data", { value: data });
+        element.dispatchEvent(event);
+    }
+
+    function replace(element, start, end, text) {
+        element.value = element.value.slice(0, start) + text + element.value.slice(end);
+    }
+
+    // Caret coordinates inside a field aren't exposed, so the field's text up to the caret is
+    // laid out in a hidden copy of the field, and the caret is where a marker after it ends up.
+    // Single line inputs centre their text vertically, which the copy doesn't, so the result is
+    // kept inside the field.
+    function caretRect(element) {
+        var field = element.getBoundingClientRect();
+        var style = window.getComputedStyle(element);
+        var mirror = document.createElement("div");
+        MIRRORED.forEach(function (name) {
+            mirror.style[name] = style[name];
+        });
+        mirror.style.position = "absolute";
+        mirror.style.visibility = "hidden";
+        mirror.style.overflow = "hidden";
+        mirror.style.whiteSpace = element.tagName === "TEXTAREA" ? "pre-wrap" : "pre";
+        mirror.style.wordWrap = "break-word";
+        mirror.style.left = (field.left + window.pageXOffset) + "px";
+        mirror.style.top = (field.top + window.pageYOffset) + "px";
+        var text = element.value.slice(0, element.selectionEnd);
+        mirror.textContent = element.type === "password" ? text.replace(/./g, "\u2022") : text;
+        var marker = document.createElement("span");
+        marker.textContent = "\u200b";
+        mirror.appendChild(marker);
+        document.body.appendChild(mirror);
+        var rect = marker.getBoundingClientRect();
+        document.body.removeChild(mirror);
+        var height = Math.min(rect.height, field.height);
+        var left = rect.left - element.scrollLeft;
+        var top = rect.top - element.scrollTop;
+        return {
+            left: Math.max(field.left, Math.min(left, field.right)),
+            top: Math.max(field.top, Math.min(top, field.bottom - height)),
+            height: height
+        };
+    }
+
+    function reportCaret() {
+        var element = document.activeElement;
+        var caret = null;
+        if (textField(element) && document.body) {
+            var rect = caretRect(element);
+            var ratio = window.devicePixelRatio || 1;
+            caret = {
+                x: rect.left * ratio,
+                y: (rect.top + rect.height) * ratio,
+                height: rect.height * ratio
+            };
+        }
+        var key = JSON.stringify(caret);
+        if (key !== lastCaret) {
+            lastCaret = key;
+            game.send("ime.caret", caret);
+        }
+    }
+
+    function endComposition(text) {
+        var element = composition.element;
+        replace(element, composition.start, composition.end, text);
+        var caret = composition.start + text.length;
+        element.setSelectionRange(caret, caret);
+        composition = null;
+        fire(element, "compositionend", text);
+        fire(element, "input", text);
+    }
+
+    game.on("ime.preedit", function (payload) {
+        var element = document.activeElement;
+        if (!textField(element)) {
+            return;
+        }
+        if (!composition || composition.element !== element) {
+            composition = {
+                element: element,
+                start: element.selectionStart,
+                end: element.selectionEnd
+            };
+            fire(element, "compositionstart", "");
+        }
+        replace(element, composition.start, composition.end, payload.text);
+        composition.end = composition.start + payload.text.length;
+        var cursor = composition.start +
+            (payload.cursor === null ? payload.text.length : payload.cursor);
+        element.setSelectionRange(cursor, cursor);
+        fire(element, "compositionupdate", payload.text);
+        reportCaret();
+    });
+
+    game.on("ime.commit", function (payload) {
+        var element = document.activeElement;
+        if (composition && composition.element === element) {
+            endComposition(payload.text);
+        } else if (textField(element)) {
+            var start = element.selectionStart;
+            replace(element, start, element.selectionEnd, payload.text);
+            element.setSelectionRange(start + payload.text.length, start + payload.text.length);
+            fire(element, "input", payload.text);
+        }
+    });
+
+    game.on("ime.cancel", function () {
+        if (composition) {
+            endComposition("");
+        }
+    });
+
+    document.addEventListener("focusin", reportCaret, true);
+    document.addEventListener("focusout", function () {
+        composition = null;
+        setTimeout(reportCaret, 0);
+    }, true);
+    // The caret moves as the field is typed in or clicked
+    document.addEventListener("input", reportCaret, true);
+    document.addEventListener("keyup", reportCaret, true);
+    document.addEventListener("mouseup", reportCaret, true);
+    window.addEventListener("resize", reportCaret);
+    window.addEventListener("scroll", reportCaret, true);
+})();
@@ -0,0 +1,179 @@
+// Drag and drop between the game and pages, see src/servo_ui/drag.rs.
+//
+// Servo doesn't implement HTML5 drag and drop, so drags are driven from mouse events here. While
+// dragging, dragenter, dragover, dragleave and drop are fired on the element under the cursor,
+// with the payload readable through event.dataTransfer.getData("application/json"). An element
+// accepts a drop by calling preventDefault on dragover, as in browsers. Elements with
+// draggable="true" and a JSON data-drag-payload (and optionally data-drag-icon) start drags.
+(function () {
+    if (!window.game || window.top !== window) {
+        return;
+    }
+
+    // Distance the mouse moves with the button down before a page drag starts, in CSS pixels
+    var DRAG_DISTANCE = 6;
+
+    var drag = null;
+    var pressed = null;
+    var icon = null;
+
+    function dataTransfer(payload) {
+        var json = JSON.stringify(payload);
+        return {
+            types: ["application/json", "text/plain"],
+            dropEffect: "move",
+            effectAllowed: "all",
+            getData: function (type) {
+                return type === "application/json" || type === "text/plain" ? json : "";
+            },
+            setData: function () {}
+        };
+    }
+
+    function fire(element, type, x, y) {
+        var event = new MouseEvent(type, {
+            bubbles: true,
+            cancelable: true,
+            clientX: x,
+            clientY: y
+        });
+        Object.defineProperty(event, "dataTransfer", { value: drag.transfer });
+        return element.dispatchEvent(event);
+    }
+
+    function showIcon(url, x, y) {
+        if (!url) {
+            return;
+        }
+        icon = document.createElement("img");
+        icon.id = "game-drag-icon";
+        icon.src = url;
+        icon.style.cssText = "position: fixed; pointer-events: none; z-index: 2147483644;" +
+            "width: 48px; height: 48px; opacity: 0.8;";
+        document.body.appendChild(icon);
+        moveIcon(x, y);
+    }
+
+    function moveIcon(x, y) {
+        if (icon) {
+            icon.style.left = (x - 24) + "px";
+            icon.style.top = (y - 24) + "px";
+        }
+    }
+
+    function begin(payload, iconUrl, x, y) {
+        drag = { payload: payload, transfer: dataTransfer(payload), over: null, accepted: false };
+        showIcon(iconUrl, x, y);
+        move(x, y);
+    }
+
+    function move(x, y) {
+        moveIcon(x, y);
+        var element = document.elementFromPoint(x, y);
+        if (element === document.documentElement) {
+            element = null;
+        }
+        if (element !== drag.over) {
+            if (drag.over) {
+                fire(drag.over, "dragleave", x, y);
+            }
+            drag.over = element;
+            if (element) {
+                fire(element, "dragenter", x, y);
+            }
+        }
+        // An element accepts the drop by cancelling dragover
+        drag.accepted = element !== null && !fire(element, "dragover", x, y);
+    }
+
+    // overPage is false when the release happened over the game world, where the page only hears
+    // about it through drag.release
+    function finish(x, y, overPage) {
+        var current = drag;
+        move(x, y);
+        if (current.accepted) {
+            fire(current.over, "drop", x, y);
+            var target = current.over;
+            game.send("drag.drop", {
+                target: {
+                    id: target.id || null,
+                    tag: target.tagName.toLowerCase(),
+                    dataset: Object.assign({}, target.dataset)
+                }
+            });
+        } else {
+            if (current.over) {
+                fire(current.over, "dragleave", x, y);
+            }
+            // Nothing here accepted the drop. Over page content that rejects it rather than
+            // dropping it on the world behind the page
+            game.send("drag.end", { overPage: overPage });
+        }
+        clear();
+    }
+
+    function clear() {
+        if (icon) {
+            icon.parentNode.removeChild(icon);
+            icon = null;
+        }
+        drag = null;
+    }
+
+    document.addEventListener("mousedown", function (event) {
+        var source = event.target.closest ? event.target.closest("[draggable=true]") : null;
+        if (source && source.dataset.dragPayload) {
+            pressed = { source: source, x: event.clientX, y: event.clientY };
+            event.preventDefault();
+        }
+    }, true);
+
+    document.addEventListener("mousemove", function (event) {
+        if (pressed && !drag) {
+            var dx = event.clientX - pressed.x;
+            var dy = event.clientY - pressed.y;
+            if (Math.sqrt(dx * dx + dy * dy) >= DRAG_DISTANCE) {
+                var payload;
+                try {
+                    payload = JSON.parse(pressed.source.dataset.dragPayload);
+                } catch (e) {
+                    console.error("Invalid data-drag-payload: " + e);
+                    pressed = null;
+                    return;
+                }
+                var iconUrl = pressed.source.dataset.dragIcon || null;
+                game.send("drag.start", { payload: payload, icon: iconUrl });
+                begin(payload, iconUrl, event.clientX, event.clientY);
+            }
+        } else if (drag) {
+            move(event.clientX, event.clientY);
+        }
+    }, true);
+
+    document.addEventListener("mouseup", function (event) {
+        pressed = null;
+        if (drag) {
+            finish(event.clientX, event.clientY, true);
+        }
+    }, true);
+
+    game.on("drag.start", function (payload) {
+        begin(payload.payload, payload.icon, -1000, -1000);
+    });
+
+    // Released over the game world, where the page doesn't see mouseup
+    game.on("drag.release", function (payload) {
+        if (drag) {
+            var ratio = window.devicePixelRatio || 1;
+            finish(payload.x / ratio, payload.y / ratio, false);
+        }
+    });
+
+    game.on("drag.cancel", function () {
+        if (drag && drag.over) {
+            fire(drag.over, "dragleave", 0, 0);
+        }
+        pressed = null;
+        clear();
+    });
+})();
@@ -0,0 +1,161 @@
+// Page side of UiDriver and UiAutomation, see src/servo_ui/automation.rs.
+//
+// Carries out automation.run messages once their element matches, is visible and, for clicks and
+// typing, enabled, polling until the timeout the game passed. Answers every command with an
+// automation.result message holding either a value or an error saying what was wrong with the
+// element when time ran out.
+//
+// Clicks are answered before they are dispatched, and dispatched only once the answer has gone
+// out, so a click which navigates away can't lose its answer and be sent again to the new
+// document. Commands this document has already been sent are ignored, so none runs twice.
+(function () {
+    if (!window.game || window.top !== window) {
+        return;
+    }
+
+    var POLL_INTERVAL = 50;
+
+    // Ids of the commands sent to this document
+    var received = {};
+
+    function visible(element) {
+        if (element.getClientRects().length === 0) {
+            return false;
+        }
+        var style = window.getComputedStyle(element);
+        return style.visibility !== "hidden" && style.display !== "none";
+    }
+
+    function editable(element) {
+        var tag = element.tagName;
+        return tag === "TEXTAREA" || tag === "INPUT" || element.isContentEditable;
+    }
+
+    // The element for a command, or a string saying why it isn't usable yet
+    function find(command) {
+        var element;
+        try {
+            element = document.querySelector(command.selector);
+        } catch (e) {
+            throw new Error("invalid selector (" + e.message + ")");
+        }
+        if (!element) {
+            return "no element matches it";
+        }
+        var description = "the matching <" + element.tagName.toLowerCase() + ">";
+        if (!visible(element)) {
+            return description + " isn't visible";
+        }
+        if ((command.op === "click" || command.op === "type") && element.disabled) {
+            return description + " is disabled";
+        }
+        if (command.op === "type" && !editable(element)) {
+            throw new Error(description + " isn't a text field or editable element");
+        }
+        return element;
+    }
+
+    function mouse(element, type) {
+        var rect = element.getBoundingClientRect();
+        element.dispatchEvent(new MouseEvent(type, {
+            bubbles: true,
+            cancelable: true,
+            button: 0,
+            clientX: rect.left + rect.width / 2,
+            clientY: rect.top + rect.height / 2
+        }));
+    }
+
+    function key(element, type, ch) {
+        element.dispatchEvent(new KeyboardEvent(type, {
+            bubbles: true,
+            cancelable: true,
+            key: ch
+        }));
+    }
+
+    function typeInto(element, text) {
+        element.focus();
+        Array.prototype.forEach.call(text, function (ch) {
+            key(element, "keydown", ch);
+            key(element, "keypress", ch);
+            if (element.isContentEditable) {
+                element.textContent += ch;
+            } else {
+                element.value += ch;
+            }
+            element.dispatchEvent(new Event("input", { bubbles: true }));
+            key(element, "keyup", ch);
+        });
+        element.dispatchEvent(new Event("change", { bubbles: true }));
+    }
+
+    function perform(command, element) {
+        switch (command.op) {
+            case "click":
+                if (element.focus) {
+                    element.focus();
+                }
+                mouse(element, "mousedown");
+                mouse(element, "mouseup");
+                mouse(element, "click");
+                return null;
+            case "type":
+                typeInto(element, command.text);
+                return null;
+            case "wait":
+                return null;
+            case "text":
+                var tag = element.tagName;
+                if (tag === "INPUT" || tag === "TEXTAREA" || tag === "SELECT") {
+                    return element.value;
+                }
+                return element.textContent.trim();
+            default:
+                throw new Error("unknown automation command '" + command.op + "'");
+        }
+    }
+
+    function answer(command, value, error) {
+        game.send("automation.result", {
+            id: command.id,
+            ok: error === null,
+            value: value,
+            error: error
+        });
+    }
+
+    game.on("automation.run", function (command) {
+        if (received[command.id]) {
+            return;
+        }
+        received[command.id] = true;
+        var deadline = Date.now() + command.timeout;
+        (function attempt() {
+            var element;
+            try {
+                element = find(command);
+                if (typeof element !== "string") {
+                    if (command.op === "click") {
+                        // game.send flushes the answer on a timeout queued before this one
+                        answer(command, null, null);
+                        setTimeout(function () {
+                            perform(command, element);
+                        }, 0);
+                    } else {
+                        answer(command, perform(command, element), null);
+                    }
+                    return;
+                }
+            } catch (e) {
+                answer(command, null, e.message);
+                return;
+            }
+            if (Date.now() >= deadline) {
+                answer(command, null, element + " after " + command.timeout + " ms");
+                return;
+            }
+            setTimeout(attempt, POLL_INTERVAL);
+        })();
+    });
+})();
@@ -0,0 +1,52 @@
+// Hotkeys declared in markup, see src/servo_ui/hotkeys.rs.
+//
+// Answers hotkeys.scan with every element's data-hotkey and data-action as hotkeys.found, and
+// sends the list again whenever elements or those attributes change, so the game's action map
+// follows the page.
+(function () {
+    if (!window.game || window.top !== window) {
+        return;
+    }
+
+    var scanned = false;
+    var pending = false;
+
+    function describe(element) {
+        var text = "<" + element.tagName.toLowerCase();
+        if (element.id) {
+            text += " id=\"" + element.id + "\"";
+        }
+        return text + " data-hotkey=\"" + element.dataset.hotkey + "\">";
+    }
+
+    function scan() {
+        pending = false;
+        var hotkeys = [];
+        var elements = document.querySelectorAll("[data-hotkey][data-action]");
+        Array.prototype.forEach.call(elements, function (element) {
+            hotkeys.push({
+                hotkey: element.dataset.hotkey,
+                action: element.dataset.action,
+                element: describe(element)
+            });
+        });
+        game.send("hotkeys.found", { hotkeys: hotkeys });
+    }
+
+    game.on("hotkeys.scan", function () {
+        scanned = true;
+        scan();
+    });
+
+    new MutationObserver(function () {
+        if (scanned && !pending) {
+            pending = true;
+            setTimeout(scan, 0);
+        }
+    }).observe(document.documentElement, {
+        childList: true,
+        subtree: true,
+        attributes: true,
+        attributeFilter: ["data-hotkey", "data-action"]
+    });
+})();
@@ -0,0 +1,109 @@
+// Developer console overlay, see src/servo_ui/console.rs.
+//
+// Shown and hidden by the game when the console key is pressed. Enter runs the line, Up and Down
+// walk the history and Tab completes the argument under the cursor.
+(function () {
+    if (!window.game || window.top !== window) {
+        return;
+    }
+
+    var root = null;
+    var output = null;
+    var input = null;
+    var history = [];
+    var historyIndex = 0;
+
+    function print(text, className) {
+        var line = document.createElement("pre");
+        line.textContent = text;
+        line.style.margin = "0";
+        line.style.whiteSpace = "pre-wrap";
+        if (className === "error") {
+            line.style.color = "#f88";
+        } else if (className === "input") {
+            line.style.color = "#8cf";
+        }
+        output.appendChild(line);
+        output.scrollTop = output.scrollHeight;
+    }
+
+    function run(line) {
+        print("> " + line, "input");
+        history.push(line);
+        historyIndex = history.length;
+        game.call("console.run", { line: line }).then(function (result) {
+            if (result) {
+                print(result);
+            }
+        }, function (error) {
+            print(error.message, "error");
+        });
+    }
+
+    function complete() {
+        game.call("console.complete", { line: input.value }).then(function (result) {
+            if (result.completions.length === 1) {
+                input.value = result.completions[0] + " ";
+            } else if (result.completions.length > 1) {
+                print(result.completions.join("    "));
+                if (result.common.length > input.value.length) {
+                    input.value = result.common;
+                }
+            }
+        });
+    }
+
+    function create() {
+        root = document.createElement("div");
+        root.id = "game-console";
+        root.style.cssText = "position: fixed; left: 0; right: 0; top: 0; height: 40%;" +
+            "background: rgba(0, 0, 0, 0.85); color: #ddd; font: 14px monospace;" +
+            "z-index: 2147483647; display: none;";
+        output = document.createElement("div");
+        output.style.cssText = "position: absolute; left: 8px; right: 8px; top: 8px; bottom: 36px;" +
+            "overflow-y: auto;";
+        input = document.createElement("input");
+        input.style.cssText = "position: absolute; left: 8px; right: 8px; bottom: 8px;" +
+            "background: #222; color: #fff; border: 1px solid #555; font: 14px monospace;";
+        input.addEventListener("keydown", function (event) {
+            if (event.key === "Enter") {
+                if (input.value.trim()) {
+                    run(input.value);
+                }
+                input.value = "";
+            } else if (event.key === "ArrowUp") {
+                historyIndex = Math.max(0, historyIndex - 1);
+                input.value = history[historyIndex] || "";
+            } else if (event.key === "ArrowDown") {
+                historyIndex = Math.min(history.length, historyIndex + 1);
+                input.value = history[historyIndex] || "";
+            } else if (event.key === "Tab") {
+                complete();
+            } else {
+                return;
+            }
+            event.preventDefault();
+        });
+        root.appendChild(output);
+        root.appendChild(input);
+        document.body.appendChild(root);
+        print("Type 'help' for a list of commands");
+    }
+
+    game.on("console.toggle", function () {
+        if (!root) {
+            create();
+        }
+        if (root.style.display === "none") {
+            root.style.display = "block";
+            input.focus();
+            game.call("console.history").then(function (lines) {
+                history = lines;
+                historyIndex = history.length;
+            });
+        } else {
+            root.style.display = "none";
+            input.blur();
+        }
+    });
+})();
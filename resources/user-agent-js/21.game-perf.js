@@ -0,0 +1,62 @@
+// Performance overlay, see src/servo_ui/profiling.rs.
+//
+// Shown and hidden by the game when the performance overlay key is pressed. The graphs are drawn
+// by ui/perf.html in a frame over the page. This script forwards it the `perf` binding, which
+// carries Servo's own layout, script and JS heap figures when its profilers are on, along with
+// the page's frame time, which only the page can measure.
+(function () {
+    if (!window.game || window.top !== window) {
+        return;
+    }
+
+    var frame = null;
+    var unsubscribe = null;
+    var lastFrame = 0;
+    var pageFrameMs = 0;
+
+    function countFrame(now) {
+        if (!frame) {
+            return;
+        }
+        if (lastFrame) {
+            pageFrameMs = now - lastFrame;
+        }
+        lastFrame = now;
+        requestAnimationFrame(countFrame);
+    }
+
+    function post(perf) {
+        frame.contentWindow.postMessage({
+            perf: perf,
+            page: {
+                frame_ms: pageFrameMs
+            }
+        }, "*");
+    }
+
+    function show(url) {
+        frame = document.createElement("iframe");
+        frame.id = "game-perf";
+        frame.src = url;
+        frame.style.cssText = "position: fixed; right: 8px; top: 8px; width: 360px; height: 300px;" +
+            "border: none; background: transparent; pointer-events: none; z-index: 2147483646;";
+        document.body.appendChild(frame);
+        unsubscribe = game.store.subscribe("perf", post);
+        lastFrame = 0;
+        requestAnimationFrame(countFrame);
+    }
+
+    function hide() {
+        unsubscribe();
+        frame.parentNode.removeChild(frame);
+        frame = null;
+    }
+
+    game.on("perf.toggle", function (payload) {
+        if (frame) {
+            hide();
+        } else {
+            show(payload.url);
+        }
+    });
+})();
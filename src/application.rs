@@ -1,34 +1,81 @@
 use amethyst::prelude::*;
 use amethyst::Result;
-use amethyst::renderer::{DisplayConfig, DrawShaded, Event, Pipeline, PosNormTex, RenderBundle,
-                         Stage};
+use amethyst::renderer::{DrawShaded, Event, Pipeline, PosNormTex, RenderBundle, Stage};
 use boilerplate;
-use servo_ui::{ServoUiBundle, ServoUiPass};
+use cli::Options;
+use servo_ui::{ui_transition, DisplaySettings, ServoUiBundle, ServoUiPass, StateRegistry, UiState,
+               SETTINGS_PAGE};
 
 pub struct GameState;
 
 impl State for GameState {
     fn on_start(&mut self, world: &mut World) {
         boilerplate::initialise_camera(world);
+        world.write_resource::<StateRegistry>().register("settings", || {
+            Box::new(UiState::new(SettingsState, Some(SETTINGS_PAGE)))
+        });
     }
-    fn handle_event(&mut self, _: &mut World, _: Event) -> Trans {
-        Trans::None
+    fn handle_event(&mut self, world: &mut World, _: Event) -> Trans {
+        ui_transition(world)
+    }
+    fn update(&mut self, world: &mut World) -> Trans {
+        ui_transition(world)
     }
 }
 
-pub fn run() -> Result<()> {
+/// Shows the settings page until it pops itself.
+pub struct SettingsState;
+
+impl State for SettingsState {
+    fn handle_event(&mut self, world: &mut World, _: Event) -> Trans {
+        ui_transition(world)
+    }
+    fn update(&mut self, world: &mut World) -> Trans {
+        ui_transition(world)
+    }
+}
+
+pub fn run(options: Options) -> Result<()> {
     let pipe = Pipeline::build().with_stage(
         Stage::with_backbuffer()
             .clear_target([0.2, 0.2, 0.2, 1.0], 0.)
             .with_pass(DrawShaded::<PosNormTex>::new()),
     );
 
-    let path = "./resources/display_config.ron";
-    let config = DisplayConfig::load(&path);
+    let settings = DisplaySettings::load(&options.display_config);
+    // Command line overrides only last for this run, they aren't saved with the settings
+    let mut config = settings.display.clone();
+    if let Some(size) = options.size {
+        config.dimensions = Some(size);
+    }
+    if options.fullscreen {
+        config.fullscreen = true;
+    }
+    if options.headless {
+        config.visibility = false;
+    }
+
+    let mut ui = ServoUiBundle::new()
+        .with_gl_state_checks(cfg!(debug_assertions))
+        .with_resources(options.resources.clone())
+        .with_headless(options.headless)
+        .with_display_settings(settings);
+    if let Some(url) = options.url {
+        ui = ui.with_start_url(url);
+    }
+    if let Some(port) = options.devtools_port {
+        ui = ui.with_devtools(port);
+    }
+    if let Some(path) = options.record_input {
+        ui = ui.with_input_recording(path);
+    }
+    if let Some(path) = options.replay_input {
+        ui = ui.with_input_replay(path);
+    }
 
-    let mut world = Application::build("resources/", GameState)?
+    let mut world = Application::build(&options.resources, UiState::new(GameState, None))?
         .with_bundle(RenderBundle::new(pipe, Some(config)))?
-        .with_bundle(ServoUiBundle {})?
+        .with_bundle(ui)?
         .build()?;
     world.run();
     Ok(())
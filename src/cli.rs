@@ -0,0 +1,223 @@
+extern crate clap;
+extern crate servo as libservo;
+
+use std::env;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use self::clap::{App, Arg, ArgMatches, Error, ErrorKind};
+use self::libservo::servo_url::ServoUrl;
+use log::LogLevelFilter;
+
+/// Everything that can be set from the command line when launching the game.
+pub struct Options {
+    /// URL of the first UI page to load.
+    pub url: Option<String>,
+    /// Display config to load and save settings to, `display.ron` in the resources directory by
+    /// default.
+    pub display_config: PathBuf,
+    /// Overrides the window dimensions in the display config.
+    pub size: Option<(u32, u32)>,
+    pub fullscreen: bool,
+    /// Run without a visible window, with Servo in headless mode.
+    pub headless: bool,
+    pub resources: PathBuf,
+    pub devtools_port: Option<u16>,
+    pub log_level: LogLevelFilter,
+    /// File to record input to, for reproducing UI bugs.
+    pub record_input: Option<PathBuf>,
+    /// Recording to replay instead of live input.
+    pub replay_input: Option<PathBuf>,
+}
+
+/// Parses the process arguments. Prints usage and exits on `--help`, `--version` or invalid
+/// arguments.
+pub fn parse() -> Options {
+    let matches = App::new("openrlfb")
+        .version(env!("CARGO_PKG_VERSION"))
+        .about("Roguelike with a Servo rendered UI")
+        .arg(
+            Arg::with_name("url")
+                .help("URL of the first UI page to load")
+                .index(1),
+        )
+        .arg(
+            Arg::with_name("display-config")
+                .long("display-config")
+                .value_name("FILE")
+                .help("Display config to load, defaults to display.ron in the resources directory"),
+        )
+        .arg(
+            Arg::with_name("size")
+                .long("size")
+                .value_name("WIDTHxHEIGHT")
+                .help("Window size, overriding the display config"),
+        )
+        .arg(
+            Arg::with_name("fullscreen")
+                .long("fullscreen")
+                .help("Start fullscreen"),
+        )
+        .arg(
+            Arg::with_name("headless")
+                .long("headless")
+                .help("Run without showing a window"),
+        )
+        .arg(
+            Arg::with_name("resources")
+                .long("resources")
+                .value_name("DIR")
+                .help("Resources directory used by amethyst and Servo")
+                .default_value("resources"),
+        )
+        .arg(
+            Arg::with_name("devtools")
+                .long("devtools")
+                .value_name("PORT")
+                .help("Start Servo's devtools server on this localhost port (debug builds only)"),
+        )
+        .arg(
+            Arg::with_name("record-input")
+                .long("record-input")
+                .value_name("FILE")
+                .help("Record input to a file, as RON if it ends in .ron and JSON otherwise"),
+        )
+        .arg(
+            Arg::with_name("replay-input")
+                .long("replay-input")
+                .value_name("FILE")
+                .help("Replay input recorded with --record-input instead of live input"),
+        )
+        .arg(
+            Arg::with_name("log-level")
+                .long("log-level")
+                .value_name("LEVEL")
+                .help("Logging verbosity")
+                .possible_values(&["off", "error", "warn", "info", "debug", "trace"])
+                .default_value("warn"),
+        )
+        .get_matches();
+    match options(&matches) {
+        Ok(options) => options,
+        Err(e) => e.exit(),
+    }
+}
+
+fn options(matches: &ArgMatches) -> Result<Options, Error> {
+    let size = match matches.value_of("size") {
+        Some(size) => Some(parse_size(size)?),
+        None => None,
+    };
+    let devtools_port = match matches.value_of("devtools") {
+        Some(port) => Some(u16::from_str(port).map_err(|_| {
+            Error::with_description(
+                &format!("Invalid devtools port '{}'", port),
+                ErrorKind::InvalidValue,
+            )
+        })?),
+        None => None,
+    };
+    let log_level = LogLevelFilter::from_str(matches.value_of("log-level").unwrap_or("warn"))
+        .map_err(|_| Error::with_description("Invalid log level", ErrorKind::InvalidValue))?;
+    let resources = PathBuf::from(matches.value_of("resources").unwrap_or("resources"));
+    let resources = if resources.is_absolute() {
+        resources
+    } else {
+        env::current_dir()?.join(resources)
+    };
+    let display_config = match matches.value_of("display-config") {
+        Some(path) => PathBuf::from(path),
+        None => resources.join("display.ron"),
+    };
+    let url = match matches.value_of("url") {
+        Some(url) => Some(parse_url(url)?),
+        None => None,
+    };
+    Ok(Options {
+        url: url,
+        display_config: display_config,
+        size: size,
+        fullscreen: matches.is_present("fullscreen"),
+        headless: matches.is_present("headless"),
+        resources: resources,
+        devtools_port: devtools_port,
+        log_level: log_level,
+        record_input: matches.value_of("record-input").map(PathBuf::from),
+        replay_input: matches.value_of("replay-input").map(PathBuf::from),
+    })
+}
+
+/// Accepts either a URL or the path of a local file, which is turned into a `file://` URL.
+fn parse_url(url: &str) -> Result<String, Error> {
+    let path = Path::new(url);
+    if path.exists() {
+        let path = if path.is_absolute() {
+            path.to_path_buf()
+        } else {
+            env::current_dir()?.join(path)
+        };
+        return ServoUrl::from_file_path(&path)
+            .map(|url| url.into_string())
+            .map_err(|_| {
+                Error::with_description(
+                    &format!("Can't load '{}' as a page", path.display()),
+                    ErrorKind::InvalidValue,
+                )
+            });
+    }
+    ServoUrl::parse(url)
+        .map(|url| url.into_string())
+        .map_err(|e| {
+            Error::with_description(
+                &format!("'{}' is neither a URL nor an existing file: {}", url, e),
+                ErrorKind::InvalidValue,
+            )
+        })
+}
+
+fn parse_size(size: &str) -> Result<(u32, u32), Error> {
+    let invalid = || {
+        Error::with_description(
+            &format!("Invalid window size '{}', expected WIDTHxHEIGHT", size),
+            ErrorKind::InvalidValue,
+        )
+    };
+    let mut parts = size.splitn(2, 'x');
+    let width = parts.next().and_then(|w| u32::from_str(w).ok());
+    let height = parts.next().and_then(|h| u32::from_str(h).ok());
+    match (width, height) {
+        (Some(width), Some(height)) if width > 0 && height > 0 => Ok((width, height)),
+        _ => Err(invalid()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sizes_need_a_positive_width_and_height() {
+        assert_eq!(parse_size("1280x720").ok(), Some((1280, 720)));
+        for size in ["1280", "0x720", "1280x0", "widexhigh", "1280x720x2", ""].iter() {
+            assert!(parse_size(size).is_err(), "accepted '{}'", size);
+        }
+    }
+
+    #[test]
+    fn urls_are_validated() {
+        assert_eq!(
+            parse_url("https://example.com/ui/menu.html").ok(),
+            Some("https://example.com/ui/menu.html".to_string())
+        );
+        assert!(parse_url("about:blank").is_ok());
+        assert!(parse_url("no-such-page.html").is_err());
+        assert!(parse_url("http://[::1/menu.html").is_err());
+    }
+
+    #[test]
+    fn existing_files_become_file_urls() {
+        let manifest = concat!(env!("CARGO_MANIFEST_DIR"), "/Cargo.toml");
+        let url = parse_url(manifest).unwrap();
+        assert!(url.starts_with("file:///"), "{}", url);
+        assert!(url.ends_with("/Cargo.toml"), "{}", url);
+    }
+}
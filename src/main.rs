@@ -1,10 +1,30 @@
 extern crate amethyst;
+extern crate env_logger;
+extern crate log;
+#[macro_use]
+extern crate percent_encoding;
+extern crate ron;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+#[macro_use]
+extern crate serde_json;
 
 mod application;
 mod boilerplate;
+mod cli;
 mod servo_ui;
 
+use std::process;
+
 fn main() {
-    println!("Hello, world!");
-    println!("{:?}", application::run())
+    let options = cli::parse();
+    env_logger::LogBuilder::new()
+        .filter(None, options.log_level)
+        .init()
+        .expect("Failed to initialise logger");
+    if let Err(e) = application::run(options) {
+        eprintln!("openrlfb: {}", e);
+        process::exit(1);
+    }
 }
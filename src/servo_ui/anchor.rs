@@ -0,0 +1,288 @@
+extern crate servo as libservo;
+
+use amethyst::core::cgmath::{Matrix4, SquareMatrix, Vector3, Vector4};
+use amethyst::core::transform::GlobalTransform;
+use amethyst::ecs::{Component, DenseVecStorage, Entities, Join, ReadStorage, System};
+use amethyst::renderer::{Camera, ScreenDimensions};
+use amethyst::prelude::World;
+use amethyst::shred::{Fetch, FetchMut};
+use amethyst::shrev::{EventChannel, ReaderId};
+use self::libservo::BrowserId;
+use super::{UiMessage, UiOutbox, UiPages};
+
+/// The page element an anchored entity is shown with.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AnchorElement {
+    /// An element already in the page, found by id.
+    Id(String),
+    /// A `<template>` in the page, found by id and cloned once for each entity anchored to it.
+    Template(String),
+}
+
+/// Makes a page element follow this entity on screen, such as a nameplate or health bar.
+#[derive(Clone, Debug)]
+pub struct UiAnchor {
+    pub element: AnchorElement,
+    /// World space offset from the entity's origin to the point the element is placed at.
+    pub offset: Vector3<f32>,
+}
+
+impl UiAnchor {
+    pub fn element(id: &str) -> Self {
+        Self {
+            element: AnchorElement::Id(id.to_string()),
+            offset: Vector3::new(0., 0., 0.),
+        }
+    }
+
+    pub fn template(id: &str) -> Self {
+        Self {
+            element: AnchorElement::Template(id.to_string()),
+            offset: Vector3::new(0., 0., 0.),
+        }
+    }
+
+    pub fn with_offset(mut self, offset: Vector3<f32>) -> Self {
+        self.offset = offset;
+        self
+    }
+}
+
+impl Component for UiAnchor {
+    type Storage = DenseVecStorage<Self>;
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+struct AnchorPosition {
+    entity: u32,
+    /// Tells apart entities which reuse a deleted entity's id.
+    generation: i32,
+    element: AnchorElement,
+    x: f32,
+    y: f32,
+    depth: f32,
+    visible: bool,
+}
+
+/// Projects a world space point to window pixels from the top left, returning them with the
+/// depth and whether the point is on screen. Points behind the camera aren't projected.
+fn project(
+    view_proj: Matrix4<f32>,
+    point: Vector4<f32>,
+    width: f32,
+    height: f32,
+) -> (f32, f32, f32, bool) {
+    let clip = view_proj * point;
+    if clip.w <= 0. {
+        return (0., 0., 0., false);
+    }
+    let ndc = clip.truncate() / clip.w;
+    let on_screen = ndc.x.abs() <= 1. && ndc.y.abs() <= 1. && ndc.z <= 1.;
+    (
+        ((ndc.x + 1.) / 2. * width).round(),
+        ((1. - ndc.y) / 2. * height).round(),
+        ndc.z,
+        on_screen,
+    )
+}
+
+/// Projects every anchored entity through the camera and sends the screen positions to the
+/// current page in one `anchors.update` message per frame. Anchors behind the camera or off
+/// screen are sent with `visible: false` so the page can hide them.
+///
+/// Nothing is sent while the positions are unchanged from the last frame, except to a page which
+/// has just loaded or been shown.
+pub struct AnchorSystem {
+    message_reader: ReaderId<UiMessage>,
+    last: Vec<AnchorPosition>,
+    browser: Option<BrowserId>,
+}
+
+impl AnchorSystem {
+    pub fn new(world: &mut World) -> Self {
+        Self {
+            message_reader: world
+                .write_resource::<EventChannel<UiMessage>>()
+                .register_reader(),
+            last: vec![],
+            browser: None,
+        }
+    }
+}
+
+impl<'a> System<'a> for AnchorSystem {
+    type SystemData = (
+        Entities<'a>,
+        ReadStorage<'a, UiAnchor>,
+        ReadStorage<'a, GlobalTransform>,
+        ReadStorage<'a, Camera>,
+        Fetch<'a, ScreenDimensions>,
+        Fetch<'a, EventChannel<UiMessage>>,
+        Fetch<'a, UiPages>,
+        FetchMut<'a, UiOutbox>,
+    );
+
+    fn run(&mut self, data: Self::SystemData) {
+        let (entities, anchors, transforms, cameras, dimensions, messages, pages, mut outbox) =
+            data;
+        // A new document or a different page hasn't seen the positions sent so far
+        for message in messages.read(&mut self.message_reader) {
+            if message.name == "page.loaded" && Some(message.browser) == pages.current() {
+                self.last.clear();
+            }
+        }
+        if pages.current() != self.browser {
+            self.browser = pages.current();
+            self.last.clear();
+        }
+
+        let view_proj = match (&cameras, &transforms).join().next() {
+            Some((camera, transform)) => match Matrix4::from(transform.0).invert() {
+                Some(view) => camera.proj * view,
+                None => return,
+            },
+            None => return,
+        };
+        let (width, height) = (dimensions.width(), dimensions.height());
+
+        let positions: Vec<AnchorPosition> = (&*entities, &anchors, &transforms)
+            .join()
+            .map(|(entity, anchor, transform)| {
+                let world = Matrix4::from(transform.0) * anchor.offset.extend(1.);
+                let (x, y, depth, visible) = project(view_proj, world, width, height);
+                AnchorPosition {
+                    entity: entity.id(),
+                    generation: entity.gen().id(),
+                    element: anchor.element.clone(),
+                    x: x,
+                    y: y,
+                    depth: depth,
+                    visible: visible,
+                }
+            })
+            .collect();
+
+        if positions != self.last {
+            outbox.send_to_current(
+                "anchors.update",
+                json!({
+                    "anchors": positions,
+                    "width": width,
+                    "height": height,
+                }),
+            );
+            self.last = positions;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use amethyst::core::cgmath::{perspective, Deg};
+    use amethyst::ecs::RunNow;
+    use amethyst::renderer::Projection;
+    use super::*;
+
+    fn view_proj() -> Matrix4<f32> {
+        perspective(Deg(60.), 2., 0.1, 100.)
+    }
+
+    fn point(x: f32, y: f32, z: f32) -> Vector4<f32> {
+        Vector4::new(x, y, z, 1.)
+    }
+
+    #[test]
+    fn projects_points_ahead_to_window_pixels() {
+        let (x, y, depth, visible) = project(view_proj(), point(0., 0., -5.), 800., 400.);
+        assert_eq!((x, y, visible), (400., 200., true));
+        assert!(depth > -1. && depth < 1.);
+
+        // Up and to the left is towards the window's top left
+        let (x, y, _, visible) = project(view_proj(), point(-1., 1., -5.), 800., 400.);
+        assert!(x < 400. && y < 200. && visible);
+    }
+
+    #[test]
+    fn points_behind_the_camera_are_hidden() {
+        assert_eq!(
+            project(view_proj(), point(0., 0., 5.), 800., 400.),
+            (0., 0., 0., false)
+        );
+        assert_eq!(
+            project(view_proj(), point(1., 1., 0.), 800., 400.),
+            (0., 0., 0., false)
+        );
+    }
+
+    #[test]
+    fn points_off_screen_are_placed_but_hidden() {
+        let (x, _, _, visible) = project(view_proj(), point(100., 0., -5.), 800., 400.);
+        assert!(x > 800. && !visible);
+        let (_, y, _, visible) = project(view_proj(), point(0., -100., -5.), 800., 400.);
+        assert!(y > 400. && !visible);
+        // Past the far plane
+        let (_, _, depth, visible) = project(view_proj(), point(0., 0., -500.), 800., 400.);
+        assert!(depth > 1. && !visible);
+    }
+
+    #[test]
+    fn entities_reusing_an_id_are_sent_with_their_generation() {
+        let mut world = World::new();
+        world.register::<UiAnchor>();
+        world.register::<GlobalTransform>();
+        world.register::<Camera>();
+        world.add_resource(ScreenDimensions::new(800, 400));
+        world.add_resource(EventChannel::<UiMessage>::new());
+        world.add_resource(UiPages::new());
+        world.add_resource(UiOutbox::new());
+        world
+            .create_entity()
+            .with(Camera::from(Projection::perspective(2., Deg(60.))))
+            .with(GlobalTransform(Matrix4::identity().into()))
+            .build();
+        let mut system = AnchorSystem::new(&mut world);
+        let anchored = |world: &mut World| {
+            world
+                .create_entity()
+                .with(UiAnchor::element("nameplate"))
+                .with(GlobalTransform(
+                    Matrix4::from_translation(Vector3::new(0., 0., -5.)).into(),
+                ))
+                .build()
+        };
+        let sent = |world: &mut World| -> Vec<(u64, i64, bool)> {
+            world
+                .write_resource::<UiOutbox>()
+                .drain()
+                .into_iter()
+                .flat_map(|(_, _, payload)| payload["anchors"].as_array().cloned().unwrap())
+                .map(|anchor| {
+                    (
+                        anchor["entity"].as_u64().unwrap(),
+                        anchor["generation"].as_i64().unwrap(),
+                        anchor["visible"].as_bool().unwrap(),
+                    )
+                })
+                .collect()
+        };
+
+        let first = anchored(&mut world);
+        system.run_now(&world.res);
+        let before = sent(&mut world);
+        assert_eq!(before.len(), 1);
+        system.run_now(&world.res);
+        assert!(sent(&mut world).is_empty());
+
+        world.delete_entity(first).unwrap();
+        world.maintain();
+        let second = anchored(&mut world);
+        assert_eq!(second.id(), first.id());
+        system.run_now(&world.res);
+        let after = sent(&mut world);
+        assert_eq!(after.len(), 1);
+        assert_eq!(after[0].0, before[0].0);
+        assert!(after[0].1 != before[0].1);
+        assert!(after[0].2);
+    }
+}
@@ -0,0 +1,608 @@
+extern crate servo as libservo;
+
+use std::collections::HashMap;
+use std::thread;
+use std::time::{Duration, Instant};
+use amethyst::ecs::System;
+use amethyst::prelude::World;
+use amethyst::shred::{Fetch, FetchMut};
+use amethyst::shrev::{EventChannel, ReaderId};
+use serde_json::Value;
+use self::libservo::BrowserId;
+use super::{ServoHandle, UiMessage, UiOutbox, UiPages};
+
+// Driving pages from Rust, for tests and tutorials.
+//
+// Each action is sent to the page as an `automation.run` message, which `18.game-automation.js`
+// carries out once the element it targets exists and is visible, answering with an
+// `automation.result` message. The page gives up after the action's timeout and explains why,
+// and the Rust side gives up shortly after that if no answer comes at all.
+//
+// An action still waiting for its element when its page loads a new document is sent again to
+// the new document. Nothing is ever performed twice: clicks are answered before they are
+// dispatched, so a click which navigates has already answered, and pages ignore actions they
+// have already been sent.
+//
+// Inside the game, actions are queued on the UiAutomation resource and AutomationSystem sends
+// them through the UiOutbox, so game states can poll for the answers each frame. Outside it,
+// UiDriver drives a UiEngine it has to itself, blocking until each action is done.
+
+// How long past an action's timeout to wait for the page's own answer
+const ANSWER_GRACE_MS: u64 = 1000;
+// Pause between polls of the engine while waiting for an answer
+const POLL_INTERVAL_MS: u64 = 5;
+
+/// Something that runs pages, which UiDriver can send messages through.
+///
+/// Implemented by ServoHandle, both with a window and with `headless` set in the config, for
+/// tests which start Servo themselves rather than through ServoUiBundle.
+pub trait UiEngine {
+    /// The page currently shown.
+    fn current_page(&self) -> BrowserId;
+    /// Sends a message to the page currently shown.
+    fn send(&mut self, name: &str, payload: Value);
+    /// Lets the engine make progress, running page scripts and layout.
+    fn pump(&mut self);
+    /// Messages sent by pages since the last call.
+    fn take_messages(&mut self) -> Vec<UiMessage>;
+    /// Puts back messages taken but not meant for the caller, so whoever reads messages next
+    /// still gets them.
+    fn requeue(&mut self, messages: Vec<UiMessage>);
+}
+
+impl UiEngine for ServoHandle {
+    fn current_page(&self) -> BrowserId {
+        self.browser
+    }
+
+    fn send(&mut self, name: &str, payload: Value) {
+        self.send_messages(vec![(None, name.to_string(), payload)]);
+    }
+
+    fn pump(&mut self) {
+        self.update();
+    }
+
+    fn take_messages(&mut self) -> Vec<UiMessage> {
+        self.window.take_messages()
+    }
+
+    fn requeue(&mut self, messages: Vec<UiMessage>) {
+        for message in messages {
+            self.window.queue_message(message);
+        }
+    }
+}
+
+/// An action for the page to carry out.
+struct Command {
+    id: u64,
+    op: &'static str,
+    /// What the action does, as written in errors before the selector.
+    action: &'static str,
+    selector: String,
+    text: Value,
+    timeout_ms: u64,
+}
+
+impl Command {
+    fn new(id: u64, op: &'static str, selector: &str, text: Value, timeout: Duration) -> Self {
+        let action = match op {
+            "click" => "click",
+            "type" => "type into",
+            "wait" => "wait for",
+            _ => "read the text of",
+        };
+        Self {
+            id: id,
+            op: op,
+            action: action,
+            selector: selector.to_string(),
+            text: text,
+            timeout_ms: timeout.as_secs() * 1000 + (timeout.subsec_nanos() / 1_000_000) as u64,
+        }
+    }
+
+    fn payload(&self) -> Value {
+        json!({
+            "id": self.id,
+            "op": self.op,
+            "selector": self.selector,
+            "text": self.text,
+            "timeout": self.timeout_ms,
+        })
+    }
+
+    /// When to stop waiting for the page to answer, if the command was sent at `sent`.
+    fn deadline(&self, sent: Instant) -> Instant {
+        sent + Duration::from_millis(self.timeout_ms + ANSWER_GRACE_MS)
+    }
+
+    /// The outcome carried by a message, None if it isn't this command's answer.
+    fn answer(&self, message: &UiMessage) -> Option<Result<Value, String>> {
+        let payload = &message.payload;
+        if message.name != "automation.result" || payload["id"].as_u64() != Some(self.id) {
+            return None;
+        }
+        if payload["ok"] != Value::Bool(true) {
+            return Some(Err(match payload["error"].as_str() {
+                Some(error) => format!("Failed to {} '{}': {}", self.action, self.selector, error),
+                None => format!("Failed to {} '{}'", self.action, self.selector),
+            }));
+        }
+        let value = payload["value"].clone();
+        if self.op == "text" && !value.is_string() {
+            return Some(Err(format!(
+                "Failed to {} '{}': the page returned {} instead of a string",
+                self.action, self.selector, value
+            )));
+        }
+        Some(Ok(value))
+    }
+
+    fn unanswered(&self) -> String {
+        format!(
+            "Failed to {} '{}': the page didn't answer within {} ms. Check that a page is loaded \
+             and 18.game-automation.js is among the user scripts.",
+            self.action,
+            self.selector,
+            self.timeout_ms + ANSWER_GRACE_MS
+        )
+    }
+}
+
+/// Clicks, types into and reads page elements found by CSS selector, blocking until the page
+/// has carried out each action.
+///
+/// Actions wait up to the timeout for their element to exist and be visible, and clicks and
+/// typing for it to be enabled as well. If the page navigates while an action waits, the action
+/// is retried on the new document. Errors say which action failed and why.
+///
+/// Within a running game use UiAutomation instead, since Servo only makes progress between
+/// frames there.
+pub struct UiDriver<'a, E: UiEngine + 'a> {
+    engine: &'a mut E,
+    timeout: Duration,
+    next_id: u64,
+    // Messages taken from the engine while waiting which weren't answers, given back on drop
+    unrelated: Vec<UiMessage>,
+}
+
+impl<'a, E: UiEngine + 'a> UiDriver<'a, E> {
+    pub fn new(engine: &'a mut E) -> Self {
+        Self {
+            engine: engine,
+            timeout: Duration::from_secs(5),
+            next_id: 0,
+            unrelated: vec![],
+        }
+    }
+
+    /// How long each action waits for its element.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// Clicks the element, firing mousedown, mouseup and click at its center.
+    pub fn click(&mut self, selector: &str) -> Result<(), String> {
+        self.run("click", selector, Value::Null).map(|_| ())
+    }
+
+    /// Focuses a text field or editable element and types `text` into it at the end, firing
+    /// key and input events for each character.
+    pub fn type_text(&mut self, selector: &str, text: &str) -> Result<(), String> {
+        self.run("type", selector, Value::String(text.to_string()))
+            .map(|_| ())
+    }
+
+    /// Waits until an element matching the selector exists and is visible.
+    pub fn wait_for(&mut self, selector: &str) -> Result<(), String> {
+        self.run("wait", selector, Value::Null).map(|_| ())
+    }
+
+    /// The element's text content, or its value for form fields.
+    pub fn query_text(&mut self, selector: &str) -> Result<String, String> {
+        self.run("text", selector, Value::Null)
+            .map(|text| text.as_str().unwrap_or("").to_string())
+    }
+
+    fn run(&mut self, op: &'static str, selector: &str, text: Value) -> Result<Value, String> {
+        self.next_id += 1;
+        let command = Command::new(self.next_id, op, selector, text, self.timeout);
+        let page = self.engine.current_page();
+        self.engine.send("automation.run", command.payload());
+
+        let deadline = command.deadline(Instant::now());
+        while Instant::now() < deadline {
+            self.engine.pump();
+            for message in self.engine.take_messages() {
+                if let Some(answer) = command.answer(&message) {
+                    return answer;
+                }
+                // The document the action was waiting in is gone, so start over in the new one
+                if message.name == "page.loaded" && message.browser == page {
+                    self.engine.send("automation.run", command.payload());
+                }
+                self.unrelated.push(message);
+            }
+            thread::sleep(Duration::from_millis(POLL_INTERVAL_MS));
+        }
+        Err(command.unanswered())
+    }
+}
+
+impl<'a, E: UiEngine + 'a> Drop for UiDriver<'a, E> {
+    fn drop(&mut self) {
+        let unrelated = self.unrelated.drain(..).collect();
+        self.engine.requeue(unrelated);
+    }
+}
+
+/// Identifies an action queued on UiAutomation, to collect its result with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct AutomationId(u64);
+
+/// Clicks, types into and reads elements of the page currently shown, from game code.
+///
+/// Actions are queued, sent to the page shown when AutomationSystem next runs and carried out by
+/// it over the following frames, and behave as with UiDriver. Their results are collected with
+/// `take_result`, which returns None until the page has answered. Text read with `query_text`
+/// comes back as a JSON string.
+pub struct UiAutomation {
+    timeout: Duration,
+    next_id: u64,
+    queued: Vec<Command>,
+    // Commands sent, the page they went to, and when to give up on them
+    sent: Vec<(Command, BrowserId, Instant)>,
+    results: HashMap<u64, Result<Value, String>>,
+}
+
+impl UiAutomation {
+    pub fn new() -> Self {
+        Self {
+            timeout: Duration::from_secs(5),
+            next_id: 0,
+            queued: vec![],
+            sent: vec![],
+            results: HashMap::new(),
+        }
+    }
+
+    /// How long each action waits for its element.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    pub fn click(&mut self, selector: &str) -> AutomationId {
+        self.queue("click", selector, Value::Null)
+    }
+
+    pub fn type_text(&mut self, selector: &str, text: &str) -> AutomationId {
+        self.queue("type", selector, Value::String(text.to_string()))
+    }
+
+    pub fn wait_for(&mut self, selector: &str) -> AutomationId {
+        self.queue("wait", selector, Value::Null)
+    }
+
+    pub fn query_text(&mut self, selector: &str) -> AutomationId {
+        self.queue("text", selector, Value::Null)
+    }
+
+    /// The outcome of an action once the page has answered or it has timed out.
+    pub fn take_result(&mut self, id: AutomationId) -> Option<Result<Value, String>> {
+        self.results.remove(&id.0)
+    }
+
+    /// Whether any action is still waiting for its result.
+    pub fn is_busy(&self) -> bool {
+        !self.queued.is_empty() || !self.sent.is_empty()
+    }
+
+    fn queue(&mut self, op: &'static str, selector: &str, text: Value) -> AutomationId {
+        self.next_id += 1;
+        self.queued
+            .push(Command::new(self.next_id, op, selector, text, self.timeout));
+        AutomationId(self.next_id)
+    }
+
+    /// Sends queued actions to `page`, collects answers from `messages` and gives up on actions
+    /// whose page hasn't answered by `now`.
+    fn update<'m, I>(
+        &mut self,
+        messages: I,
+        page: Option<BrowserId>,
+        outbox: &mut UiOutbox,
+        now: Instant,
+    ) where
+        I: IntoIterator<Item = &'m UiMessage>,
+    {
+        // Until a page is shown there is nothing to carry the actions out
+        if let Some(page) = page {
+            for command in self.queued.drain(..) {
+                outbox.send(page, "automation.run", command.payload());
+                let deadline = command.deadline(now);
+                self.sent.push((command, page, deadline));
+            }
+        }
+        for message in messages {
+            // The document the actions were waiting in is gone, so start over in the new one
+            if message.name == "page.loaded" {
+                for &(ref command, page, _) in self.sent.iter() {
+                    if page == message.browser {
+                        outbox.send(page, "automation.run", command.payload());
+                    }
+                }
+                continue;
+            }
+            let answered = self.sent.iter().position(|&(ref command, page, _)| {
+                page == message.browser && command.answer(message).is_some()
+            });
+            if let Some(index) = answered {
+                let (command, _, _) = self.sent.remove(index);
+                if let Some(answer) = command.answer(message) {
+                    self.results.insert(command.id, answer);
+                }
+            }
+        }
+        let (expired, waiting): (Vec<_>, Vec<_>) = self.sent
+            .drain(..)
+            .partition(|&(_, _, deadline)| now >= deadline);
+        self.sent = waiting;
+        for (command, _, _) in expired {
+            self.results.insert(command.id, Err(command.unanswered()));
+        }
+    }
+}
+
+/// Carries out the actions queued on UiAutomation.
+pub struct AutomationSystem {
+    reader_id: ReaderId<UiMessage>,
+}
+
+impl AutomationSystem {
+    pub fn new(world: &mut World) -> Self {
+        Self {
+            reader_id: world
+                .write_resource::<EventChannel<UiMessage>>()
+                .register_reader(),
+        }
+    }
+}
+
+impl<'a> System<'a> for AutomationSystem {
+    type SystemData = (
+        Fetch<'a, EventChannel<UiMessage>>,
+        FetchMut<'a, UiAutomation>,
+        FetchMut<'a, UiOutbox>,
+        Fetch<'a, UiPages>,
+    );
+
+    fn run(&mut self, (messages, mut automation, mut outbox, pages): Self::SystemData) {
+        let messages = messages.read(&mut self.reader_id);
+        automation.update(messages, pages.current(), &mut outbox, Instant::now());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate servo as libservo;
+
+    use std::collections::VecDeque;
+    use self::libservo::BrowserId;
+    use super::super::test_browsers;
+    use super::*;
+
+    /// Hands out prepared batches of messages, one per call to take_messages.
+    struct FakeEngine {
+        page: BrowserId,
+        sent: Vec<(String, Value)>,
+        incoming: VecDeque<Vec<UiMessage>>,
+        requeued: Vec<UiMessage>,
+    }
+
+    impl FakeEngine {
+        fn new(page: BrowserId, incoming: Vec<Vec<UiMessage>>) -> Self {
+            Self {
+                page: page,
+                sent: vec![],
+                incoming: incoming.into_iter().collect(),
+                requeued: vec![],
+            }
+        }
+    }
+
+    impl UiEngine for FakeEngine {
+        fn current_page(&self) -> BrowserId {
+            self.page
+        }
+
+        fn send(&mut self, name: &str, payload: Value) {
+            self.sent.push((name.to_string(), payload));
+        }
+
+        fn pump(&mut self) {}
+
+        fn take_messages(&mut self) -> Vec<UiMessage> {
+            self.incoming.pop_front().unwrap_or(vec![])
+        }
+
+        fn requeue(&mut self, messages: Vec<UiMessage>) {
+            self.requeued.extend(messages);
+        }
+    }
+
+    fn message(browser: BrowserId, name: &str, payload: Value) -> UiMessage {
+        UiMessage {
+            browser: browser,
+            name: name.to_string(),
+            payload: payload,
+        }
+    }
+
+    fn result(browser: BrowserId, id: u64, value: Value) -> UiMessage {
+        message(
+            browser,
+            "automation.result",
+            json!({ "id": id, "ok": true, "value": value }),
+        )
+    }
+
+    #[test]
+    fn driver_returns_the_pages_answer() {
+        let browser = test_browsers(1)[0];
+        let answers = vec![vec![result(browser, 1, json!("Start"))]];
+        let mut engine = FakeEngine::new(browser, answers);
+        assert_eq!(
+            UiDriver::new(&mut engine).query_text("#title"),
+            Ok("Start".to_string())
+        );
+        assert_eq!(engine.sent.len(), 1);
+        assert_eq!(engine.sent[0].0, "automation.run");
+        assert_eq!(engine.sent[0].1["op"], json!("text"));
+        assert_eq!(engine.sent[0].1["selector"], json!("#title"));
+    }
+
+    #[test]
+    fn driver_reports_the_pages_error() {
+        let browser = test_browsers(1)[0];
+        let error = json!({ "id": 1, "ok": false, "error": "no element matches it" });
+        let answers = vec![vec![message(browser, "automation.result", error)]];
+        let mut engine = FakeEngine::new(browser, answers);
+        assert_eq!(
+            UiDriver::new(&mut engine).click("#play"),
+            Err("Failed to click '#play': no element matches it".to_string())
+        );
+    }
+
+    #[test]
+    fn driver_gives_up_when_the_page_never_answers() {
+        let mut engine = FakeEngine::new(test_browsers(1)[0], vec![]);
+        let error = UiDriver::new(&mut engine)
+            .with_timeout(Duration::from_millis(0))
+            .wait_for("#menu")
+            .unwrap_err();
+        assert!(error.starts_with("Failed to wait for '#menu': the page didn't answer within"));
+    }
+
+    #[test]
+    fn driver_resends_after_its_page_loaded_and_requeues_other_messages() {
+        let browsers = test_browsers(2);
+        let mut engine = FakeEngine::new(
+            browsers[0],
+            vec![
+                vec![
+                    // Another page loading doesn't disturb the action
+                    message(browsers[1], "page.loaded", Value::Null),
+                    message(browsers[0], "page.loaded", Value::Null),
+                    result(browsers[0], 7, Value::Null),
+                ],
+                vec![result(browsers[0], 1, Value::Null)],
+            ],
+        );
+        assert_eq!(UiDriver::new(&mut engine).type_text("#name", "Ann"), Ok(()));
+        assert_eq!(engine.sent.len(), 2);
+        assert_eq!(engine.sent[0], engine.sent[1]);
+        let requeued: Vec<&str> = engine
+            .requeued
+            .iter()
+            .map(|message| message.name.as_str())
+            .collect();
+        assert_eq!(
+            requeued,
+            vec!["page.loaded", "page.loaded", "automation.result"]
+        );
+    }
+
+    #[test]
+    fn queued_actions_wait_for_a_page() {
+        let browser = test_browsers(1)[0];
+        let mut automation = UiAutomation::new();
+        let mut outbox = UiOutbox::new();
+        let now = Instant::now();
+        automation.click("#play");
+        automation.update(&[], None, &mut outbox, now);
+        assert!(outbox.drain().is_empty());
+        automation.update(&[], Some(browser), &mut outbox, now);
+        let sent = outbox.drain();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].0, Some(browser));
+        assert_eq!(sent[0].1, "automation.run");
+    }
+
+    #[test]
+    fn queued_actions_are_resent_only_to_their_page() {
+        let browsers = test_browsers(2);
+        let mut automation = UiAutomation::new();
+        let mut outbox = UiOutbox::new();
+        let now = Instant::now();
+        let click = automation.click("#play");
+        automation.update(&[], Some(browsers[0]), &mut outbox, now);
+        outbox.drain();
+        assert_eq!(automation.take_result(click), None);
+
+        let loaded = |browser| message(browser, "page.loaded", Value::Null);
+        automation.update(&[loaded(browsers[1])], Some(browsers[1]), &mut outbox, now);
+        assert!(outbox.drain().is_empty());
+        automation.update(&[loaded(browsers[0])], Some(browsers[1]), &mut outbox, now);
+        let sent = outbox.drain();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].0, Some(browsers[0]));
+
+        // Answers only count from the page the action went to
+        automation.update(&[result(browsers[1], 1, Value::Null)], None, &mut outbox, now);
+        assert_eq!(automation.take_result(click), None);
+        automation.update(&[result(browsers[0], 1, Value::Null)], None, &mut outbox, now);
+        assert_eq!(automation.take_result(click), Some(Ok(Value::Null)));
+        assert!(!automation.is_busy());
+    }
+
+    #[test]
+    fn answered_actions_are_not_resent() {
+        let browser = test_browsers(1)[0];
+        let mut automation = UiAutomation::new();
+        let mut outbox = UiOutbox::new();
+        let now = Instant::now();
+        let click = automation.click("#start-game");
+        automation.update(&[], Some(browser), &mut outbox, now);
+        outbox.drain();
+
+        // A click which navigates answers before the new document loads
+        let messages = [
+            result(browser, 1, Value::Null),
+            message(browser, "page.loaded", Value::Null),
+        ];
+        automation.update(&messages, Some(browser), &mut outbox, now);
+        assert!(outbox.drain().is_empty());
+        assert_eq!(automation.take_result(click), Some(Ok(Value::Null)));
+    }
+
+    #[test]
+    fn queued_actions_time_out() {
+        let browser = test_browsers(1)[0];
+        let mut automation = UiAutomation::new().with_timeout(Duration::from_millis(100));
+        let mut outbox = UiOutbox::new();
+        let now = Instant::now();
+        let text = automation.query_text("#gold");
+        automation.update(&[], Some(browser), &mut outbox, now);
+        let later = now + Duration::from_millis(1099);
+        automation.update(&[], Some(browser), &mut outbox, later);
+        assert_eq!(automation.take_result(text), None);
+        let later = now + Duration::from_millis(1100);
+        automation.update(&[], Some(browser), &mut outbox, later);
+        match automation.take_result(text) {
+            Some(Err(error)) => assert!(error.contains("didn't answer within 1100 ms")),
+            other => panic!("expected a timeout, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,338 @@
+extern crate servo as libservo;
+
+use std::collections::HashMap;
+use std::time::Instant;
+use serde::Serialize;
+use serde_json::{self, Map, Value};
+use amethyst::ecs::{Component, Entities, Join, ReadStorage};
+use amethyst::prelude::World;
+use amethyst::shred::{Resource, Resources, RunNow, SystemData};
+use amethyst::shrev::{EventChannel, ReaderId};
+use self::libservo::BrowserId;
+use super::{PerfStats, UiMessage, UiOutbox};
+
+// Reactive binding of ECS data to pages.
+//
+// Resources and components registered in UiBindings are serialized every frame for as long as a
+// page is subscribed to them (`game.store.subscribe(name, callback)` in the store user script).
+// The first value a page receives is a full `store.snapshot`, after that only `store.patch`
+// messages holding a JSON merge patch (RFC 7386) against the last value sent. Pages lose their
+// subscriptions when a new document starts loading, so they always start again from a snapshot.
+// Scripts subscribe while the document loads, before `page.loaded`, so that can't be the signal.
+
+type Observer = Box<Fn(&Resources) -> Value + Send + Sync>;
+
+/// Resources and components pages can subscribe to by name.
+pub struct UiBindings {
+    observers: HashMap<String, Observer>,
+}
+
+impl UiBindings {
+    pub fn new() -> Self {
+        Self {
+            observers: HashMap::new(),
+        }
+    }
+
+    /// Makes the resource `T` observable under `name`.
+    pub fn observe_resource<T>(&mut self, name: &str)
+    where
+        T: Resource + Serialize,
+    {
+        self.observe(name, |res| serialize(&*res.fetch::<T>(0)));
+    }
+
+    /// Makes every `T` component observable under `name`, as an object keyed by entity id.
+    pub fn observe_component<T>(&mut self, name: &str)
+    where
+        T: Component + Serialize,
+    {
+        self.observe(name, |res| {
+            let (entities, storage) = <(Entities, ReadStorage<T>)>::fetch(res, 0);
+            let components = (&*entities, &storage)
+                .join()
+                .map(|(entity, component)| (entity.id().to_string(), serialize(component)))
+                .collect::<Map<String, Value>>();
+            Value::Object(components)
+        });
+    }
+
+    /// Makes an arbitrary value computed from the world's resources observable under `name`.
+    pub fn observe<F>(&mut self, name: &str, observer: F)
+    where
+        F: Fn(&Resources) -> Value + Send + Sync + 'static,
+    {
+        self.observers.insert(name.to_string(), Box::new(observer));
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        self.observers.keys().map(|k| k.as_str()).collect()
+    }
+
+    /// Current value of an observable, None if nothing is registered under `name`.
+    pub fn value(&self, res: &Resources, name: &str) -> Option<Value> {
+        self.observers.get(name).map(|observer| observer(res))
+    }
+}
+
+fn serialize<T: Serialize>(value: &T) -> Value {
+    match serde_json::to_value(value) {
+        Ok(value) => value,
+        Err(e) => {
+            eprintln!("ERROR: Failed to serialize observed value: {}", e);
+            Value::Null
+        }
+    }
+}
+
+/// Computes the JSON merge patch turning `old` into `new`, None if they are equal.
+///
+/// Merge patches can't set an object member to null, since null removes the member, so nulls
+/// inside objects are dropped on the page side.
+pub fn merge_patch(old: &Value, new: &Value) -> Option<Value> {
+    if old == new {
+        return None;
+    }
+    match (old, new) {
+        (&Value::Object(ref old), &Value::Object(ref new)) => {
+            let mut patch = Map::new();
+            for (key, value) in new {
+                match old.get(key) {
+                    Some(old_value) => if let Some(change) = merge_patch(old_value, value) {
+                        patch.insert(key.clone(), change);
+                    },
+                    None => {
+                        patch.insert(key.clone(), value.clone());
+                    }
+                }
+            }
+            for key in old.keys() {
+                if !new.contains_key(key) {
+                    patch.insert(key.clone(), Value::Null);
+                }
+            }
+            Some(Value::Object(patch))
+        }
+        _ => Some(new.clone()),
+    }
+}
+
+struct Subscription {
+    browser: BrowserId,
+    name: String,
+    // Last value sent to the page, None until the snapshot has gone out
+    sent: Option<Value>,
+}
+
+/// Sends snapshots and patches of observed data to subscribed pages.
+///
+/// At most `updates_per_frame` messages are sent each frame. Subscriptions which don't fit are
+/// picked up first on the next frame, so a large number of bindings can't stall one frame.
+pub struct BindingSystem {
+    reader_id: ReaderId<UiMessage>,
+    subscriptions: Vec<Subscription>,
+    next: usize,
+    updates_per_frame: usize,
+}
+
+impl BindingSystem {
+    pub fn new(world: &mut World, updates_per_frame: usize) -> Self {
+        Self {
+            reader_id: world
+                .write_resource::<EventChannel<UiMessage>>()
+                .register_reader(),
+            subscriptions: vec![],
+            next: 0,
+            updates_per_frame: updates_per_frame,
+        }
+    }
+
+    fn handle_message(&mut self, message: &UiMessage) {
+        let names = || {
+            message.payload["names"]
+                .as_array()
+                .map(|names| {
+                    names
+                        .iter()
+                        .filter_map(|name| name.as_str().map(|name| name.to_string()))
+                        .collect()
+                })
+                .unwrap_or(vec![])
+        };
+        match message.name.as_str() {
+            "page.started" => {
+                self.subscriptions
+                    .retain(|subscription| subscription.browser != message.browser);
+            }
+            "store.subscribe" => for name in names() {
+                let existing = self.subscriptions.iter_mut().find(|subscription| {
+                    subscription.browser == message.browser && subscription.name == name
+                });
+                match existing {
+                    // Subscribing again asks for a fresh snapshot
+                    Some(subscription) => subscription.sent = None,
+                    None => self.subscriptions.push(Subscription {
+                        browser: message.browser,
+                        name: name,
+                        sent: None,
+                    }),
+                }
+            },
+            "store.unsubscribe" => for name in names() {
+                self.subscriptions.retain(|subscription| {
+                    subscription.browser != message.browser || subscription.name != name
+                });
+            },
+            _ => {}
+        }
+    }
+
+    /// Handles subscription messages and queues snapshots and patches for changed bindings.
+    fn update_subscriptions(&mut self, res: &Resources) {
+        let messages: Vec<UiMessage> = res.fetch::<EventChannel<UiMessage>>(0)
+            .read(&mut self.reader_id)
+            .cloned()
+            .collect();
+        for message in messages.iter() {
+            self.handle_message(message);
+        }
+        if self.subscriptions.is_empty() {
+            return;
+        }
+
+        let mut updates = vec![];
+        {
+            let bindings = res.fetch::<UiBindings>(0);
+            let mut values: HashMap<String, Option<Value>> = HashMap::new();
+            let count = self.subscriptions.len();
+            let start = self.next % count;
+            let mut visited = 0;
+            while visited < count && updates.len() < self.updates_per_frame {
+                let subscription = &mut self.subscriptions[(start + visited) % count];
+                visited += 1;
+                let value = values
+                    .entry(subscription.name.clone())
+                    .or_insert_with(|| bindings.value(res, &subscription.name))
+                    .clone();
+                let value = match value {
+                    Some(value) => value,
+                    None => {
+                        if subscription.sent.is_none() {
+                            eprintln!(
+                                "WARNING: Page subscribed to unknown binding '{}'",
+                                subscription.name
+                            );
+                            subscription.sent = Some(Value::Null);
+                        }
+                        continue;
+                    }
+                };
+                let update = match subscription.sent {
+                    None => Some((
+                        "store.snapshot",
+                        json!({ "name": subscription.name, "value": value }),
+                    )),
+                    Some(ref sent) => merge_patch(sent, &value).map(|patch| {
+                        (
+                            "store.patch",
+                            json!({ "name": subscription.name, "patch": patch }),
+                        )
+                    }),
+                };
+                if let Some((kind, payload)) = update {
+                    updates.push((subscription.browser, kind, payload));
+                    subscription.sent = Some(value);
+                }
+            }
+            self.next = (start + visited) % count;
+        }
+
+        let mut outbox = res.fetch_mut::<UiOutbox>(0);
+        for (browser, kind, payload) in updates {
+            outbox.send(browser, kind, payload);
+        }
+    }
+}
+
+impl<'a> RunNow<'a> for BindingSystem {
+    fn run_now(&mut self, res: &'a Resources) {
+        let started = Instant::now();
+        self.update_subscriptions(res);
+        res.fetch_mut::<PerfStats>(0).record_system("binding", started.elapsed());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::test_browsers;
+    use super::*;
+
+    fn system() -> BindingSystem {
+        BindingSystem {
+            reader_id: EventChannel::<UiMessage>::new().register_reader(),
+            subscriptions: vec![],
+            next: 0,
+            updates_per_frame: 10,
+        }
+    }
+
+    fn message(browser: BrowserId, name: &str, payload: Value) -> UiMessage {
+        UiMessage {
+            browser: browser,
+            name: name.to_string(),
+            payload: payload,
+        }
+    }
+
+    fn subscribed(system: &BindingSystem) -> Vec<(BrowserId, &str)> {
+        system
+            .subscriptions
+            .iter()
+            .map(|subscription| (subscription.browser, subscription.name.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn subscriptions_made_while_loading_survive_page_loaded() {
+        let browser = test_browsers(1)[0];
+        let mut system = system();
+        system.handle_message(&message(browser, "page.started", Value::Null));
+        system.handle_message(&message(browser, "store.subscribe", json!({ "names": ["hp"] })));
+        system.handle_message(&message(browser, "page.loaded", Value::Null));
+        assert_eq!(subscribed(&system), vec![(browser, "hp")]);
+    }
+
+    #[test]
+    fn new_document_drops_only_its_pages_subscriptions() {
+        let browsers = test_browsers(2);
+        let mut system = system();
+        for &browser in browsers.iter() {
+            system.handle_message(&message(browser, "store.subscribe", json!({ "names": ["hp"] })));
+        }
+        system.handle_message(&message(browsers[0], "page.started", Value::Null));
+        assert_eq!(subscribed(&system), vec![(browsers[1], "hp")]);
+    }
+
+    #[test]
+    fn subscribing_again_asks_for_a_snapshot() {
+        let browser = test_browsers(1)[0];
+        let mut system = system();
+        let subscribe = message(browser, "store.subscribe", json!({ "names": ["hp"] }));
+        system.handle_message(&subscribe);
+        system.subscriptions[0].sent = Some(json!(10));
+        system.handle_message(&subscribe);
+        assert_eq!(system.subscriptions.len(), 1);
+        assert_eq!(system.subscriptions[0].sent, None);
+    }
+
+    #[test]
+    fn merge_patch_removes_and_changes_members() {
+        let old = json!({ "hp": 10, "name": "Ann", "pos": { "x": 1, "y": 2 } });
+        let new = json!({ "hp": 9, "pos": { "x": 1, "y": 3 } });
+        assert_eq!(
+            merge_patch(&old, &new),
+            Some(json!({ "hp": 9, "name": null, "pos": { "y": 3 } }))
+        );
+        assert_eq!(merge_patch(&old, &old), None);
+    }
+}
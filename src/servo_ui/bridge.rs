@@ -0,0 +1,107 @@
+extern crate servo as libservo;
+
+use serde_json::{self, Value};
+use self::libservo::BrowserId;
+use self::libservo::servo_url::ServoUrl;
+use percent_encoding::{percent_decode, utf8_percent_encode, DEFAULT_ENCODE_SET};
+
+// Message transport between page JavaScript and Rust.
+//
+// Pages send messages through `game.send(name, payload)`, defined by the bridge user script in
+// resources/user-agent-js. The script batches everything sent in one tick into a navigation to
+// `game://messages?<percent-encoded JSON array>`, which ServoWindow::allow_navigation refuses and
+// queues as UiMessages instead. Rust sends messages back by loading a `javascript:` URL which
+// calls `game.__receive(name, payload)` in the page.
+
+/// Scheme of the navigations used to carry messages from pages.
+pub const MESSAGE_SCHEME: &str = "game";
+
+define_encode_set! {
+    /// Characters which can't appear literally in a `javascript:` URL.
+    pub SCRIPT_ENCODE_SET = [DEFAULT_ENCODE_SET] | {'%'}
+}
+
+/// A message sent by page JavaScript.
+#[derive(Clone, Debug)]
+pub struct UiMessage {
+    pub browser: BrowserId,
+    pub name: String,
+    pub payload: Value,
+}
+
+#[derive(Deserialize)]
+struct RawMessage {
+    name: String,
+    #[serde(default)]
+    payload: Value,
+}
+
+/// Messages queued to be sent to pages by ServoUiSystem on its next run.
+pub struct UiOutbox {
+    messages: Vec<(Option<BrowserId>, String, Value)>,
+}
+
+impl UiOutbox {
+    pub fn new() -> Self {
+        Self { messages: vec![] }
+    }
+
+    /// Queues a message for one page.
+    pub fn send(&mut self, browser: BrowserId, name: &str, payload: Value) {
+        self.messages.push((Some(browser), name.to_string(), payload));
+    }
+
+    /// Queues a message for the page currently shown.
+    pub fn send_to_current(&mut self, name: &str, payload: Value) {
+        self.messages.push((None, name.to_string(), payload));
+    }
+
+    pub fn drain(&mut self) -> Vec<(Option<BrowserId>, String, Value)> {
+        self.messages.drain(..).collect()
+    }
+}
+
+/// Parses the messages carried by a navigation to the message scheme. Returns None if the URL
+/// isn't a message navigation at all.
+pub fn parse_messages(browser: BrowserId, url: &ServoUrl) -> Option<Vec<UiMessage>> {
+    if url.scheme() != MESSAGE_SCHEME {
+        return None;
+    }
+    let query = url.query().unwrap_or("");
+    let json = percent_decode(query.as_bytes()).decode_utf8_lossy();
+    match serde_json::from_str::<Vec<RawMessage>>(&json) {
+        Ok(messages) => Some(
+            messages
+                .into_iter()
+                .map(|message| UiMessage {
+                    browser: browser,
+                    name: message.name,
+                    payload: message.payload,
+                })
+                .collect(),
+        ),
+        Err(e) => {
+            eprintln!("ERROR: Malformed message from page: {}", e);
+            Some(vec![])
+        }
+    }
+}
+
+/// Builds the `javascript:` URL which delivers a message to a page.
+pub fn message_url(name: &str, payload: &Value) -> ServoUrl {
+    let script = format!(
+        "window.game && game.__receive({}, {});",
+        Value::String(name.to_string()),
+        payload
+    );
+    script_url(&script)
+}
+
+/// Builds a `javascript:` URL which runs a script in a page.
+pub fn script_url(script: &str) -> ServoUrl {
+    let url = format!(
+        "javascript:{}",
+        utf8_percent_encode(script, SCRIPT_ENCODE_SET)
+    );
+    ServoUrl::parse(&url).expect("Failed to build script URL")
+}
@@ -1,16 +1,243 @@
+use std::path::PathBuf;
 use amethyst::core::bundle::{ECSBundle, Result};
 use amethyst::ecs::DispatcherBuilder;
 use amethyst::prelude::World;
-use amethyst::renderer::TextureHandle;
-use super::ServoUiSystem;
+use amethyst::renderer::{DisplayConfig, TextureHandle};
+use amethyst::shrev::EventChannel;
+use amethyst::winit::VirtualKeyCode;
+use super::{ActionMap, AnchorSystem, AutomationSystem, BindingSystem, CursorMode, DisplaySettings,
+            DragEvent, DragSession, DragSystem, FrameUpload, GamepadSource, HotkeySystem, ImeCaret,
+            ImeComposition, ImeEvent, ImeSystem, NavBindings, NavigationSystem, PerfStats,
+            RoutedEvent, RpcRegistry, RpcSystem, ServoUiConfig, ServoUiSystem, StateRegistry,
+            SwallowableKeys, UiAction, UiAnchor, UiAutomation, UiBindings, UiMessage, UiOutbox,
+            UiPages, UiTransitions, UnhandledKey};
+use super::console::{register_builtin_commands, register_console_calls, CommandRegistry,
+                     ConsoleHistory};
+use super::inspector::{register_inspector, Inspector};
+use super::loading::{register_loading, LoadingProgress};
+use super::settings::register_settings;
+use super::transitions::register_transition_calls;
+
+pub struct ServoUiBundle {
+    config: ServoUiConfig,
+    gamepad: Option<Box<GamepadSource>>,
+}
+
+impl ServoUiBundle {
+    pub fn new() -> Self {
+        Self {
+            config: ServoUiConfig::default(),
+            gamepad: None,
+        }
+    }
+
+    /// Report GL state that Servo composites fail to leave as they found it.
+    pub fn with_gl_state_checks(mut self, enabled: bool) -> Self {
+        self.config.debug_gl_state = enabled;
+        self
+    }
+
+    /// Choose how composited frames are handed to the renderer.
+    pub fn with_frame_upload(mut self, upload: FrameUpload) -> Self {
+        self.config.upload = upload;
+        self
+    }
+
+    /// Load this URL instead of the test page when Servo starts.
+    pub fn with_start_url<S: Into<String>>(mut self, url: S) -> Self {
+        self.config.start_url = Some(url.into());
+        self
+    }
+
+    /// Load Servo's resources from this directory instead of `./resources`.
+    pub fn with_resources<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.config.resources = Some(path.into());
+        self
+    }
+
+    /// Run Servo in headless mode.
+    pub fn with_headless(mut self, headless: bool) -> Self {
+        self.config.headless = headless;
+        self
+    }
+
+    /// Limit the number of binding snapshots and patches sent to pages each frame.
+    pub fn with_binding_updates_per_frame(mut self, updates: usize) -> Self {
+        self.config.binding_updates_per_frame = updates;
+        self
+    }
+
+    /// Show and hide the developer console with this key instead of backtick, or disable the
+    /// console with None. The console can't run commands in release builds.
+    pub fn with_console_key(mut self, key: Option<VirtualKeyCode>) -> Self {
+        self.config.console_key = key;
+        self
+    }
+
+    /// Show and hide the entity inspector with this key instead of F12, or disable the
+    /// inspector with None.
+    pub fn with_inspector_key(mut self, key: Option<VirtualKeyCode>) -> Self {
+        self.config.inspector_key = key;
+        self
+    }
+
+    /// Show and hide the performance overlay with this key instead of F3, or disable the overlay
+    /// with None.
+    pub fn with_perf_key(mut self, key: Option<VirtualKeyCode>) -> Self {
+        self.config.perf_key = key;
+        self
+    }
+
+    /// Print reports from Servo's time and memory profilers to stdout every `period` seconds. On
+    /// Unix they are read back from stdout and shown by the performance overlay too.
+    pub fn with_servo_profiler(mut self, period: f64) -> Self {
+        self.config.servo_profiler_period = Some(period);
+        self
+    }
+
+    /// Let the settings page edit these display settings, saving changes to the file they were
+    /// loaded from.
+    pub fn with_display_settings(mut self, settings: DisplaySettings) -> Self {
+        self.config.display_settings = Some(settings);
+        self
+    }
+
+    /// Let clicks through to the game where the UI's alpha is below `alpha`, instead of 16.
+    pub fn with_hit_test_alpha(mut self, alpha: u8) -> Self {
+        self.config.hit_test_alpha = alpha;
+        self
+    }
+
+    /// Move focus around pages with input from this gamepad.
+    pub fn with_gamepad<S: GamepadSource + 'static>(mut self, source: S) -> Self {
+        self.gamepad = Some(Box::new(source));
+        self
+    }
+
+    /// Use these gamepad bindings for page navigation instead of the d-pad, south to confirm and
+    /// east to cancel.
+    pub fn with_nav_bindings(mut self, bindings: NavBindings) -> Self {
+        self.config.nav_bindings = bindings;
+        self
+    }
+
+    /// Send single-finger touches to pages as mouse input, for pages written only for the mouse.
+    pub fn with_touch_mouse_emulation(mut self, enabled: bool) -> Self {
+        self.config.touch_emulates_mouse = enabled;
+        self
+    }
+
+    /// Zoom pages when two fingers pinch, on by default.
+    pub fn with_pinch_zoom(mut self, enabled: bool) -> Self {
+        self.config.pinch_zoom = enabled;
+        self
+    }
+
+    /// Which keys the focused page may keep from the game by handling them, all keys by default.
+    /// Other keys are published as UnhandledKeys even when the page handles them.
+    pub fn with_swallowable_keys(mut self, keys: SwallowableKeys) -> Self {
+        self.config.swallowable_keys = keys;
+        self
+    }
+
+    /// Record window input to a file, one event per line with its frame number and time.
+    pub fn with_input_recording<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.config.record_input = Some(path.into());
+        self
+    }
+
+    /// Replay input recorded with `with_input_recording`, on the same frames it was recorded on.
+    pub fn with_input_replay<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.config.replay_input = Some(path.into());
+        self
+    }
+
+    /// Start Servo's devtools server on the given localhost port, so remote debugging tools can
+    /// attach to UI pages. Has no effect in release builds.
+    pub fn with_devtools(mut self, port: u16) -> Self {
+        self.config.devtools_port = Some(port);
+        self
+    }
+
+    /// Start Servo's WebDriver server on the given localhost port, so UI pages can be automated.
+    /// Has no effect in release builds.
+    pub fn with_webdriver(mut self, port: u16) -> Self {
+        self.config.webdriver_port = Some(port);
+        self
+    }
+}
 
-pub struct ServoUiBundle;
 impl<'a, 'b> ECSBundle<'a, 'b> for ServoUiBundle {
     fn build(
         self,
         world: &mut World,
         dispatcher: DispatcherBuilder<'a, 'b>,
     ) -> Result<DispatcherBuilder<'a, 'b>> {
-        Ok(dispatcher.add_thread_local(ServoUiSystem::new(world)))
+        world.add_resource(EventChannel::<UiMessage>::new());
+        world.add_resource(EventChannel::<RoutedEvent>::new());
+        world.add_resource(EventChannel::<UnhandledKey>::new());
+        world.add_resource(UiOutbox::new());
+        let mut rpc = RpcRegistry::new();
+        let mut bindings = UiBindings::new();
+        register_transition_calls(&mut rpc);
+        register_loading(&mut bindings, &mut rpc);
+        register_console_calls(&mut rpc);
+        let mut commands = CommandRegistry::new();
+        register_builtin_commands(&mut commands);
+        world.add_resource(commands);
+        world.add_resource(ConsoleHistory::new(100));
+        let mut inspector = Inspector::new();
+        register_inspector(&mut inspector, &mut bindings, &mut rpc);
+        world.add_resource(inspector);
+        bindings.observe_resource::<PerfStats>("perf");
+        world.add_resource(PerfStats::new());
+        register_settings(&mut bindings, &mut rpc);
+        world.add_resource(
+            self.config
+                .display_settings
+                .clone()
+                .unwrap_or_else(|| DisplaySettings::new(DisplayConfig::default())),
+        );
+        world.add_resource(rpc);
+        world.add_resource(bindings);
+        world.add_resource(StateRegistry::new());
+        world.add_resource(UiTransitions::new());
+        world.add_resource(LoadingProgress::new());
+        world.register::<UiAnchor>();
+        world.add_resource(UiPages::new());
+        world.add_resource(CursorMode::default());
+        world.add_resource(EventChannel::<ImeEvent>::new());
+        world.add_resource(ImeCaret::default());
+        world.add_resource(ImeComposition::default());
+        world.add_resource(DragSession::new());
+        world.add_resource(EventChannel::<DragEvent>::new());
+        world.add_resource(ActionMap::new());
+        world.add_resource(EventChannel::<UiAction>::new());
+        world.add_resource(UiAutomation::new());
+        let binding_system = BindingSystem::new(world, self.config.binding_updates_per_frame);
+        let nav_bindings = self.config.nav_bindings.clone();
+        let navigation_system = self.gamepad
+            .map(|source| NavigationSystem::new(source, nav_bindings));
+        let ui_system = ServoUiSystem::new(world, self.config)?;
+        let rpc_system = RpcSystem::new(world);
+        let ime_system = ImeSystem::new(world);
+        let drag_system = DragSystem::new(world);
+        let hotkey_system = HotkeySystem::new(world);
+        let automation_system = AutomationSystem::new(world);
+        let anchor_system = AnchorSystem::new(world);
+        let mut dispatcher = dispatcher
+            .add(anchor_system, "ui_anchor_system", &[])
+            .add(ime_system, "ui_ime_system", &[])
+            .add(drag_system, "ui_drag_system", &[])
+            .add(hotkey_system, "ui_hotkey_system", &[])
+            .add(automation_system, "ui_automation_system", &[]);
+        // Gamepad sources often aren't Send, and navigation should reach the page this frame
+        if let Some(navigation_system) = navigation_system {
+            dispatcher = dispatcher.add_thread_local(navigation_system);
+        }
+        Ok(dispatcher
+            .add_thread_local(ui_system)
+            .add_thread_local(rpc_system)
+            .add_thread_local(binding_system))
     }
 }
@@ -0,0 +1,111 @@
+use std::path::PathBuf;
+use amethyst::winit::VirtualKeyCode;
+use super::{DisplaySettings, NavBindings, SwallowableKeys};
+
+/// How composited UI frames reach the amethyst texture drawn by the UI pass.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FrameUpload {
+    /// Servo renders straight into the GL texture behind the target handle. Only works when gfx
+    /// and Servo share one GL context.
+    SharedTexture,
+    /// Servo renders into its own framebuffer, which is read back through pixel buffer objects
+    /// and uploaded as a dynamic amethyst `Texture`. Works with any renderer backend and with
+    /// software GL, at the cost of a copy through system memory.
+    Readback,
+}
+
+impl Default for FrameUpload {
+    fn default() -> Self {
+        FrameUpload::SharedTexture
+    }
+}
+
+/// Options used when starting Servo, set through the builder methods on ServoUiBundle.
+#[derive(Clone, Debug)]
+pub struct ServoUiConfig {
+    /// Compare the GL state before and after every composite and report anything Servo left
+    /// changed, once for each piece of state.
+    pub debug_gl_state: bool,
+    pub upload: FrameUpload,
+    /// Port for Servo's remote devtools server on localhost. Ignored in release builds.
+    pub devtools_port: Option<u16>,
+    /// Port for Servo's WebDriver server on localhost. Ignored in release builds.
+    pub webdriver_port: Option<u16>,
+    /// Page loaded when Servo starts. Defaults to `test.html` in the resources directory.
+    pub start_url: Option<String>,
+    /// Directory Servo loads its resources from. Defaults to `resources` in the working
+    /// directory.
+    pub resources: Option<PathBuf>,
+    /// Run Servo in headless mode, without presenting to a window.
+    pub headless: bool,
+    /// Most binding snapshots and patches sent to pages in one frame.
+    pub binding_updates_per_frame: usize,
+    /// Key which shows and hides the developer console, None to disable it. The console can't
+    /// run commands in release builds.
+    pub console_key: Option<VirtualKeyCode>,
+    /// Key which shows and hides the entity inspector, None to disable it. Only set by default
+    /// in debug builds.
+    pub inspector_key: Option<VirtualKeyCode>,
+    /// Key which shows and hides the performance overlay, None to disable it. Only set by
+    /// default in debug builds.
+    pub perf_key: Option<VirtualKeyCode>,
+    /// Seconds between reports from Servo's time and memory profilers, printed to stdout and, on
+    /// Unix, shown by the performance overlay. None leaves them off.
+    pub servo_profiler_period: Option<f64>,
+    /// Display settings edited by the settings page. Defaults to amethyst's display defaults,
+    /// saved nowhere.
+    pub display_settings: Option<DisplaySettings>,
+    /// Alpha below which the composited UI lets clicks and wheel events through to the game.
+    pub hit_test_alpha: u8,
+    /// Gamepad buttons used to move focus around pages, when a gamepad source is given.
+    pub nav_bindings: NavBindings,
+    /// Send single-finger touches to pages as mouse input instead of touch events.
+    pub touch_emulates_mouse: bool,
+    /// Zoom pages when two fingers pinch.
+    pub pinch_zoom: bool,
+    /// Keys the focused page may keep from the game by handling them.
+    pub swallowable_keys: SwallowableKeys,
+    /// File to record the input reaching ServoUiSystem to, as JSON or as RON for `.ron` files.
+    pub record_input: Option<PathBuf>,
+    /// Recording to replay instead of live input, until it runs out.
+    pub replay_input: Option<PathBuf>,
+}
+
+impl Default for ServoUiConfig {
+    fn default() -> Self {
+        Self {
+            debug_gl_state: false,
+            upload: FrameUpload::default(),
+            devtools_port: None,
+            webdriver_port: None,
+            start_url: None,
+            resources: None,
+            headless: false,
+            binding_updates_per_frame: 16,
+            console_key: if cfg!(debug_assertions) {
+                Some(VirtualKeyCode::Grave)
+            } else {
+                None
+            },
+            inspector_key: if cfg!(debug_assertions) {
+                Some(VirtualKeyCode::F12)
+            } else {
+                None
+            },
+            perf_key: if cfg!(debug_assertions) {
+                Some(VirtualKeyCode::F3)
+            } else {
+                None
+            },
+            servo_profiler_period: None,
+            display_settings: None,
+            hit_test_alpha: 16,
+            nav_bindings: NavBindings::default(),
+            touch_emulates_mouse: false,
+            pinch_zoom: true,
+            swallowable_keys: SwallowableKeys::default(),
+            record_input: None,
+            replay_input: None,
+        }
+    }
+}
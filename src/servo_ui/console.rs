@@ -0,0 +1,534 @@
+use std::collections::{BTreeMap, VecDeque};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json::{self, Value};
+use amethyst::core::cgmath::Matrix4;
+use amethyst::core::transform::GlobalTransform;
+use amethyst::ecs::{EntitiesRes, Join, WriteStorage};
+use amethyst::shred::{Resource, Resources, SystemData};
+use super::{CursorMode, RpcRegistry, UiPages};
+
+// Developer console.
+//
+// The console overlay (resources/user-agent-js/20.game-console.js) is toggled by ServoUiSystem
+// when the console key is pressed, and sends each line to `console.run`. The first word of a
+// line names the command, which gets the rest of the line either split into arguments by
+// `tokenize` or, for commands like `set` taking JSON, as it was typed. Commands run inside
+// RpcSystem, so they have the same access to resources as RPC handlers. Pages can only run them
+// in debug builds.
+
+type Completer = Box<Fn(&Resources, &CommandRegistry, usize, &str) -> Vec<String> + Send + Sync>;
+
+enum CommandHandler {
+    Args(Box<Fn(&Resources, &[String]) -> Result<String, String> + Send + Sync>),
+    Line(Box<Fn(&Resources, &str) -> Result<String, String> + Send + Sync>),
+}
+
+struct Command {
+    usage: String,
+    help: String,
+    handler: CommandHandler,
+    completer: Option<Completer>,
+}
+
+struct Settable {
+    get: Box<Fn(&Resources) -> Result<Value, String> + Send + Sync>,
+    set: Box<Fn(&Resources, Value) -> Result<(), String> + Send + Sync>,
+}
+
+/// Commands the developer console can run, and the resources its `get` and `set` commands can
+/// read and write.
+pub struct CommandRegistry {
+    commands: BTreeMap<String, Command>,
+    settable: BTreeMap<String, Settable>,
+}
+
+impl CommandRegistry {
+    pub fn new() -> Self {
+        Self {
+            commands: BTreeMap::new(),
+            settable: BTreeMap::new(),
+        }
+    }
+
+    /// Registers a command. The handler gets the arguments after the command name, and returns
+    /// the text to print or an error message.
+    pub fn register<F>(&mut self, name: &str, usage: &str, help: &str, handler: F)
+    where
+        F: Fn(&Resources, &[String]) -> Result<String, String> + Send + Sync + 'static,
+    {
+        self.insert(name, usage, help, CommandHandler::Args(Box::new(handler)));
+    }
+
+    /// Registers a command whose handler gets the rest of the line after the command name as it
+    /// was typed, with surrounding whitespace trimmed, for arguments tokenize would mangle.
+    pub fn register_line<F>(&mut self, name: &str, usage: &str, help: &str, handler: F)
+    where
+        F: Fn(&Resources, &str) -> Result<String, String> + Send + Sync + 'static,
+    {
+        self.insert(name, usage, help, CommandHandler::Line(Box::new(handler)));
+    }
+
+    fn insert(&mut self, name: &str, usage: &str, help: &str, handler: CommandHandler) {
+        self.commands.insert(
+            name.to_string(),
+            Command {
+                usage: usage.to_string(),
+                help: help.to_string(),
+                handler: handler,
+                completer: None,
+            },
+        );
+    }
+
+    /// Sets how a command's arguments are tab-completed. The completer gets the index of the
+    /// argument being completed and its text so far, and returns the candidates.
+    pub fn set_completer<F>(&mut self, name: &str, completer: F)
+    where
+        F: Fn(&Resources, &CommandRegistry, usize, &str) -> Vec<String> + Send + Sync + 'static,
+    {
+        if let Some(command) = self.commands.get_mut(name) {
+            command.completer = Some(Box::new(completer));
+        }
+    }
+
+    /// Lets the `get` and `set` commands read and write the resource `T` as JSON under `name`.
+    /// Games add their own resources after adding ServoUiBundle, with
+    /// `world.write_resource::<CommandRegistry>().settable::<T>(name)`.
+    pub fn settable<T>(&mut self, name: &str)
+    where
+        T: Resource + Serialize + DeserializeOwned,
+    {
+        self.settable.insert(
+            name.to_string(),
+            Settable {
+                get: Box::new(|res| {
+                    serde_json::to_value(&*res.fetch::<T>(0)).map_err(|e| e.to_string())
+                }),
+                set: Box::new(|res, value| {
+                    let value = serde_json::from_value(value).map_err(|e| e.to_string())?;
+                    *res.fetch_mut::<T>(0) = value;
+                    Ok(())
+                }),
+            },
+        );
+    }
+
+    pub fn settable_names(&self) -> Vec<String> {
+        self.settable.keys().cloned().collect()
+    }
+
+    pub fn get(&self, res: &Resources, name: &str) -> Result<Value, String> {
+        match self.settable.get(name) {
+            Some(settable) => (settable.get)(res),
+            None => Err(format!("Unknown resource '{}'", name)),
+        }
+    }
+
+    pub fn set(&self, res: &Resources, name: &str, value: Value) -> Result<(), String> {
+        match self.settable.get(name) {
+            Some(settable) => (settable.set)(res, value),
+            None => Err(format!("Unknown resource '{}'", name)),
+        }
+    }
+
+    /// Parses and runs one console line.
+    pub fn run(&self, res: &Resources, line: &str) -> Result<String, String> {
+        let (name, rest) = split_first_word(line);
+        if name.is_empty() {
+            return Ok(String::new());
+        }
+        let command = match self.commands.get(name) {
+            Some(command) => command,
+            None => return Err(format!("Unknown command '{}', try 'help'", name)),
+        };
+        let result = match command.handler {
+            CommandHandler::Args(ref handler) => handler(res, &tokenize(rest)?),
+            CommandHandler::Line(ref handler) => handler(res, rest.trim()),
+        };
+        result.map_err(|e| format!("{}\nusage: {} {}", e, name, command.usage))
+    }
+
+    /// Possible completions of the last argument on a line, each returned as the whole line.
+    pub fn complete(&self, res: &Resources, line: &str) -> Vec<String> {
+        let mut args = match tokenize(line) {
+            Ok(args) => args,
+            Err(_) => return vec![],
+        };
+        // Trailing whitespace means a new, empty argument is being started
+        if args.is_empty() || line.ends_with(char::is_whitespace) {
+            args.push(String::new());
+        }
+        let partial = args.pop().unwrap_or_default();
+        let candidates = if args.is_empty() {
+            self.commands.keys().cloned().collect()
+        } else {
+            match self.commands.get(&args[0]) {
+                Some(&Command {
+                    completer: Some(ref completer),
+                    ..
+                }) => completer(res, self, args.len() - 1, &partial),
+                _ => vec![],
+            }
+        };
+        candidates
+            .into_iter()
+            .filter(|candidate| candidate.starts_with(&partial))
+            .map(|candidate| {
+                let mut line = args.iter().map(|arg| quote(arg)).collect::<Vec<_>>();
+                line.push(quote(&candidate));
+                line.join(" ")
+            })
+            .collect()
+    }
+
+    fn help(&self, command: Option<&str>) -> Result<String, String> {
+        match command {
+            Some(name) => match self.commands.get(name) {
+                Some(command) => Ok(format!("{} {}\n  {}", name, command.usage, command.help)),
+                None => Err(format!("Unknown command '{}'", name)),
+            },
+            None => Ok(self.commands
+                .iter()
+                .map(|(name, command)| format!("{} {}\n  {}", name, command.usage, command.help))
+                .collect::<Vec<_>>()
+                .join("\n")),
+        }
+    }
+}
+
+/// Splits a console line into arguments on whitespace. Single or double quotes group an
+/// argument containing whitespace, and a backslash escapes the next character outside single
+/// quotes.
+pub fn tokenize(line: &str) -> Result<Vec<String>, String> {
+    let mut args = vec![];
+    let mut current: Option<String> = None;
+    let mut quote: Option<char> = None;
+    let mut chars = line.chars();
+    while let Some(ch) = chars.next() {
+        match (quote, ch) {
+            (Some(q), ch) if ch == q => quote = None,
+            (Some('\''), ch) => current.get_or_insert_with(String::new).push(ch),
+            (_, '\\') => match chars.next() {
+                Some(escaped) => current.get_or_insert_with(String::new).push(escaped),
+                None => return Err("Line ends with an unfinished escape".to_string()),
+            },
+            (Some(_), ch) => current.get_or_insert_with(String::new).push(ch),
+            (None, '"') | (None, '\'') => {
+                quote = Some(ch);
+                current.get_or_insert_with(String::new);
+            }
+            (None, ch) if ch.is_whitespace() => if let Some(arg) = current.take() {
+                args.push(arg);
+            },
+            (None, ch) => current.get_or_insert_with(String::new).push(ch),
+        }
+    }
+    if quote.is_some() {
+        return Err("Line ends inside a quoted argument".to_string());
+    }
+    args.extend(current);
+    Ok(args)
+}
+
+/// Splits off the first word of a line, returning it and the rest of the line.
+fn split_first_word(line: &str) -> (&str, &str) {
+    let line = line.trim_left();
+    match line.find(char::is_whitespace) {
+        Some(end) => (&line[..end], &line[end..]),
+        None => (line, ""),
+    }
+}
+
+/// Quotes an argument if tokenize would otherwise split or unescape it.
+fn quote(arg: &str) -> String {
+    if !arg.is_empty() && !arg.contains(|c: char| c.is_whitespace() || "\"'\\".contains(c)) {
+        arg.to_string()
+    } else {
+        format!("\"{}\"", arg.replace('\\', "\\\\").replace('"', "\\\""))
+    }
+}
+
+/// Lines previously run in the console, oldest first.
+pub struct ConsoleHistory {
+    lines: VecDeque<String>,
+    capacity: usize,
+}
+
+impl ConsoleHistory {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            lines: VecDeque::new(),
+            capacity: capacity,
+        }
+    }
+
+    pub fn push(&mut self, line: &str) {
+        if line.trim().is_empty() || self.lines.back().map(|last| last == line) == Some(true) {
+            return;
+        }
+        self.lines.push_back(line.to_string());
+        while self.lines.len() > self.capacity {
+            self.lines.pop_front();
+        }
+    }
+
+    pub fn lines(&self) -> Vec<String> {
+        self.lines.iter().cloned().collect()
+    }
+}
+
+#[derive(Deserialize)]
+struct ConsoleLine {
+    line: String,
+}
+
+/// Registers the RPC calls used by the console overlay, in debug builds only.
+pub fn register_console_calls(rpc: &mut RpcRegistry) {
+    // Any page could call these, and commands can rewrite any settable resource
+    if !cfg!(debug_assertions) {
+        return;
+    }
+    rpc.register("console.run", |res, args: ConsoleLine| {
+        res.fetch_mut::<ConsoleHistory>(0).push(&args.line);
+        res.fetch::<CommandRegistry>(0).run(res, &args.line)
+    });
+    rpc.register("console.complete", |res, args: ConsoleLine| {
+        let completions = res.fetch::<CommandRegistry>(0)
+            .complete(res, &args.line);
+        Ok(json!({
+            "completions": completions,
+            "common": common_prefix(&completions),
+        }))
+    });
+    rpc.register("console.history", |res, _: ()| {
+        Ok(res.fetch::<ConsoleHistory>(0).lines())
+    });
+}
+
+fn common_prefix(lines: &[String]) -> String {
+    let mut prefix = match lines.first() {
+        Some(first) => first.clone(),
+        None => return String::new(),
+    };
+    for line in &lines[1..] {
+        while !line.starts_with(&prefix) {
+            prefix.pop();
+        }
+    }
+    prefix
+}
+
+fn parse_f32(arg: &str) -> Result<f32, String> {
+    arg.parse()
+        .map_err(|_| format!("'{}' is not a number", arg))
+}
+
+/// Registers the built-in commands: help, spawn, entities, get, set, navigate and reload, and
+/// makes the CursorMode settable as `cursor_mode`.
+pub fn register_builtin_commands(commands: &mut CommandRegistry) {
+    commands.register(
+        "help",
+        "[command]",
+        "Lists commands, or describes one command",
+        |res, args| {
+            res.fetch::<CommandRegistry>(0)
+                .help(args.first().map(|arg| arg.as_str()))
+        },
+    );
+    commands.set_completer("help", |_, commands, index, _| {
+        if index == 0 {
+            commands.commands.keys().cloned().collect()
+        } else {
+            vec![]
+        }
+    });
+
+    commands.register(
+        "spawn",
+        "[x y z]",
+        "Creates an entity, with a transform at the given position if there is one",
+        |res, args| {
+            let position = match args.len() {
+                0 => None,
+                3 => Some([
+                    parse_f32(&args[0])?,
+                    parse_f32(&args[1])?,
+                    parse_f32(&args[2])?,
+                ]),
+                _ => return Err("Expected no arguments or three coordinates".to_string()),
+            };
+            let entity = res.fetch::<EntitiesRes>(0).create();
+            if let Some(position) = position {
+                let mut transforms = WriteStorage::<GlobalTransform>::fetch(res, 0);
+                transforms.insert(
+                    entity,
+                    GlobalTransform(Matrix4::from_translation(position.into()).into()),
+                );
+            }
+            Ok(format!("Spawned entity {}", entity.id()))
+        },
+    );
+
+    commands.register("entities", "", "Lists living entities", |res, _| {
+        let entities = res.fetch::<EntitiesRes>(0);
+        let lines: Vec<String> = (&*entities)
+            .join()
+            .map(|entity| format!("{} (generation {})", entity.id(), entity.gen().id()))
+            .collect();
+        Ok(format!("{} entities\n{}", lines.len(), lines.join("\n")))
+    });
+
+    commands.register(
+        "get",
+        "<resource>",
+        "Prints a resource as JSON",
+        |res, args| match args.first() {
+            Some(name) => {
+                let value = res.fetch::<CommandRegistry>(0).get(res, name)?;
+                serde_json::to_string_pretty(&value).map_err(|e| e.to_string())
+            }
+            None => Err("Expected a resource name".to_string()),
+        },
+    );
+
+    // The JSON is parsed as typed, since tokenize would strip its quotes
+    commands.register_line(
+        "set",
+        "<resource> <json>",
+        "Replaces a resource with a JSON value",
+        |res, rest| {
+            let (name, value) = split_first_word(rest);
+            let value = value.trim();
+            if name.is_empty() || value.is_empty() {
+                return Err("Expected a resource name and a value".to_string());
+            }
+            let value = serde_json::from_str(value).map_err(|e| e.to_string())?;
+            res.fetch::<CommandRegistry>(0).set(res, name, value)?;
+            Ok(format!("Set {}", name))
+        },
+    );
+    commands.settable::<CursorMode>("cursor_mode");
+    for name in &["get", "set"] {
+        commands.set_completer(name, |_, commands, index, _| {
+            if index == 0 {
+                commands.settable_names()
+            } else {
+                vec![]
+            }
+        });
+    }
+
+    commands.register(
+        "navigate",
+        "<url>",
+        "Loads a URL, or a path relative to the resources directory, in the current UI page",
+        |res, args| match args.first() {
+            Some(url) => {
+                res.fetch_mut::<UiPages>(0).navigate(url);
+                Ok(format!("Navigating to {}", url))
+            }
+            None => Err("Expected a URL".to_string()),
+        },
+    );
+
+    commands.register("reload", "", "Reloads the current UI page", |res, _| {
+        res.fetch_mut::<UiPages>(0).reload();
+        Ok("Reloading".to_string())
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn builtin_resources() -> Resources {
+        let mut res = Resources::new();
+        let mut commands = CommandRegistry::new();
+        register_builtin_commands(&mut commands);
+        res.add(commands);
+        res.add(CursorMode::default());
+        res
+    }
+
+    fn run(res: &Resources, line: &str) -> Result<String, String> {
+        res.fetch::<CommandRegistry>(0).run(res, line)
+    }
+
+    fn complete(res: &Resources, line: &str) -> Vec<String> {
+        res.fetch::<CommandRegistry>(0).complete(res, line)
+    }
+
+    #[test]
+    fn tokenizes_quotes_and_escapes() {
+        assert_eq!(
+            tokenize("  spawn 1  2\t3 "),
+            Ok(vec!["spawn", "1", "2", "3"]
+                .into_iter()
+                .map(String::from)
+                .collect())
+        );
+        assert_eq!(
+            tokenize(r#"navigate "a b" 'c \d' e\ f "\"g\"" ''"#),
+            Ok(vec!["navigate", "a b", "c \\d", "e f", "\"g\"", ""]
+                .into_iter()
+                .map(String::from)
+                .collect())
+        );
+        assert!(tokenize("navigate \"a b").is_err());
+        assert!(tokenize("navigate a\\").is_err());
+    }
+
+    #[test]
+    fn quoted_arguments_tokenize_back_unchanged() {
+        for arg in &["plain", "a b", "say \"hi\"", "back\\slash", "it's", ""] {
+            assert_eq!(tokenize(&quote(arg)), Ok(vec![arg.to_string()]));
+        }
+    }
+
+    #[test]
+    fn completes_commands_and_their_arguments() {
+        let res = builtin_resources();
+        assert_eq!(complete(&res, "he"), vec!["help"]);
+        assert_eq!(complete(&res, "e"), vec!["entities"]);
+        assert_eq!(complete(&res, "help s"), vec!["help set", "help spawn"]);
+        assert_eq!(complete(&res, "get "), vec!["get cursor_mode"]);
+        assert_eq!(complete(&res, "set cur"), vec!["set cursor_mode"]);
+        assert!(complete(&res, "set cursor_mode ").is_empty());
+        assert!(complete(&res, "unknown ").is_empty());
+        assert!(complete(&res, "help \"unfinished").is_empty());
+    }
+
+    #[test]
+    fn set_parses_the_json_as_typed() {
+        let res = builtin_resources();
+        assert_eq!(
+            run(&res, "set cursor_mode \"Locked\""),
+            Ok("Set cursor_mode".to_string())
+        );
+        assert_eq!(*res.fetch::<CursorMode>(0), CursorMode::Locked);
+        assert_eq!(run(&res, "get cursor_mode"), Ok("\"Locked\"".to_string()));
+
+        assert!(run(&res, "set cursor_mode Free").is_err());
+        assert!(run(&res, "set cursor_mode").is_err());
+        assert!(run(&res, "set unknown 1").is_err());
+        assert_eq!(*res.fetch::<CursorMode>(0), CursorMode::Locked);
+    }
+
+    #[test]
+    fn runs_nothing_for_blank_lines_and_rejects_unknown_commands() {
+        let res = builtin_resources();
+        assert_eq!(run(&res, "   "), Ok(String::new()));
+        assert!(run(&res, "unknown").is_err());
+        assert!(run(&res, "spawn 1 2").is_err());
+    }
+
+    #[test]
+    fn history_skips_blank_and_repeated_lines_and_drops_the_oldest() {
+        let mut history = ConsoleHistory::new(3);
+        for line in &["help", "help", " ", "get cursor_mode", "entities", "help"] {
+            history.push(line);
+        }
+        assert_eq!(history.lines(), vec!["get cursor_mode", "entities", "help"]);
+    }
+}
@@ -0,0 +1,169 @@
+extern crate glutin;
+
+use self::glutin::{CursorState, GlWindow};
+
+/// How the game wants the mouse cursor, applied to the window by ServoUiSystem. A page holding a
+/// pointer lock takes precedence until it exits the lock.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CursorMode {
+    /// Visible and free to leave the window, for menus.
+    Free,
+    /// Hidden over the window but free to leave it.
+    Hidden,
+    /// Hidden and grabbed, for camera control. Read mouse movement from `DeviceEvent::MouseMotion`.
+    Locked,
+}
+
+impl Default for CursorMode {
+    fn default() -> Self {
+        CursorMode::Free
+    }
+}
+
+impl CursorMode {
+    pub fn apply(&self, window: &GlWindow) {
+        let state = match *self {
+            CursorMode::Free => CursorState::Normal,
+            CursorMode::Hidden => CursorState::Hide,
+            CursorMode::Locked => CursorState::Grab,
+        };
+        if let Err(e) = window.set_cursor_state(state) {
+            eprintln!("ERROR: Failed to set cursor state {:?}: {}", state, e);
+        }
+    }
+}
+
+/// Tracks whether the current page holds a pointer lock, requested through the Pointer Lock API
+/// provided by `15.game-pointer-lock.js`, and applies it or the game's CursorMode to the window.
+///
+/// While a page holds the lock the cursor is grabbed, cursor moves aren't sent to Servo and the
+/// relative motion is sent to the page instead. Escape or the window losing focus exits the
+/// lock, as in browsers.
+pub struct PointerLock {
+    page_locked: bool,
+    applied: Option<CursorMode>,
+    motion: (f64, f64),
+}
+
+impl PointerLock {
+    pub fn new() -> Self {
+        Self {
+            page_locked: false,
+            applied: None,
+            motion: (0., 0.),
+        }
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.page_locked
+    }
+
+    /// Handles lock requests from the page. Returns true if the page's lock changed.
+    pub fn handle_message(&mut self, name: &str) -> bool {
+        match name {
+            "pointer.lock" => self.set_locked(true),
+            // A new document never starts locked
+            "pointer.unlock" | "page.loaded" => self.set_locked(false),
+            _ => false,
+        }
+    }
+
+    /// Exits the page's lock. Returns true if it held one.
+    pub fn release(&mut self) -> bool {
+        self.set_locked(false)
+    }
+
+    pub fn add_motion(&mut self, (dx, dy): (f64, f64)) {
+        self.motion.0 += dx;
+        self.motion.1 += dy;
+    }
+
+    /// Relative motion since the last call, if there was any.
+    pub fn take_motion(&mut self) -> Option<(f64, f64)> {
+        let motion = self.motion;
+        self.motion = (0., 0.);
+        if motion == (0., 0.) {
+            None
+        } else {
+            Some(motion)
+        }
+    }
+
+    /// Applies the page's lock, or otherwise the game's cursor mode, if it differs from what the
+    /// window has.
+    pub fn apply(&mut self, mode: CursorMode, window: &GlWindow) {
+        let mode = self.window_mode(mode);
+        if self.applied != Some(mode) {
+            mode.apply(window);
+            self.applied = Some(mode);
+        }
+    }
+
+    fn window_mode(&self, mode: CursorMode) -> CursorMode {
+        if self.page_locked {
+            CursorMode::Locked
+        } else {
+            mode
+        }
+    }
+
+    fn set_locked(&mut self, locked: bool) -> bool {
+        let changed = self.page_locked != locked;
+        self.page_locked = locked;
+        if !locked {
+            self.motion = (0., 0.);
+        }
+        changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pages_lock_and_unlock_and_new_documents_start_unlocked() {
+        let mut lock = PointerLock::new();
+        assert!(lock.handle_message("pointer.lock"));
+        assert!(!lock.handle_message("pointer.lock"));
+        assert!(lock.is_locked());
+        assert_eq!(lock.window_mode(CursorMode::Free), CursorMode::Locked);
+        assert!(!lock.handle_message("page.started"));
+        assert!(lock.is_locked());
+
+        assert!(lock.handle_message("page.loaded"));
+        assert!(!lock.is_locked());
+        assert_eq!(lock.window_mode(CursorMode::Hidden), CursorMode::Hidden);
+        assert!(!lock.handle_message("page.loaded"));
+
+        lock.handle_message("pointer.lock");
+        assert!(lock.handle_message("pointer.unlock"));
+        assert!(!lock.is_locked());
+    }
+
+    #[test]
+    fn motion_adds_up_until_taken() {
+        let mut lock = PointerLock::new();
+        lock.handle_message("pointer.lock");
+        assert_eq!(lock.take_motion(), None);
+        lock.add_motion((1., -2.));
+        lock.add_motion((0.5, 0.5));
+        assert_eq!(lock.take_motion(), Some((1.5, -1.5)));
+        assert_eq!(lock.take_motion(), None);
+    }
+
+    #[test]
+    fn releasing_the_lock_drops_motion_not_yet_sent() {
+        let mut lock = PointerLock::new();
+        lock.handle_message("pointer.lock");
+        lock.add_motion((3., 4.));
+        assert!(lock.release());
+        assert!(!lock.release());
+        assert_eq!(lock.take_motion(), None);
+
+        lock.handle_message("pointer.lock");
+        lock.add_motion((3., 4.));
+        lock.handle_message("page.loaded");
+        assert_eq!(lock.take_motion(), None);
+    }
+}
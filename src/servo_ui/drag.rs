@@ -0,0 +1,193 @@
+use amethyst::ecs::System;
+use amethyst::renderer::Event;
+use amethyst::shred::{Fetch, FetchMut};
+use amethyst::shrev::{EventChannel, ReaderId};
+use amethyst::prelude::World;
+use amethyst::winit::{ElementState, WindowEvent};
+use serde_json::Value;
+use super::{RoutedEvent, UiMessage, UiOutbox};
+
+/// An item being dragged, between the game world and pages or between page elements.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Drag {
+    pub payload: Value,
+    /// Image URL shown under the cursor while dragging, resolved against the page's URL.
+    pub icon: Option<String>,
+    /// Whether the drag started on a page element rather than in the game.
+    pub from_page: bool,
+}
+
+/// How a drag ended, published on `EventChannel<DragEvent>`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum DragEvent {
+    /// Dropped on a page element which accepted it by cancelling `dragover`. The target has the
+    /// element's `id`, `tag` and `data-*` attributes as `dataset`.
+    DroppedOnPage { payload: Value, target: Value },
+    /// Released over the game world, at a window position.
+    DroppedOnWorld {
+        payload: Value,
+        position: (f64, f64),
+    },
+    /// Stopped with `DragSession::cancel`, or released over page content which didn't accept it.
+    Cancelled { payload: Value },
+}
+
+/// The drag in progress, if any. Games start drags from the world with `start`, and pages start
+/// them from elements with `draggable="true"` and a JSON `data-drag-payload`.
+///
+/// While dragging, `17.game-drag.js` shows the icon under the cursor and fires HTML5 `dragenter`,
+/// `dragover`, `dragleave` and `drop` events on page elements, with the payload available from
+/// `event.dataTransfer.getData("application/json")`.
+pub struct DragSession {
+    active: Option<Drag>,
+    // Changes to announce to the page on DragSystem's next run
+    start_pending: bool,
+    cancel_pending: bool,
+}
+
+impl DragSession {
+    pub fn new() -> Self {
+        Self {
+            active: None,
+            start_pending: false,
+            cancel_pending: false,
+        }
+    }
+
+    pub fn active(&self) -> Option<&Drag> {
+        self.active.as_ref()
+    }
+
+    /// Starts dragging `payload` from the game, replacing any drag in progress. The drag ends
+    /// when the mouse button is released.
+    pub fn start(&mut self, payload: Value, icon: Option<&str>) {
+        self.active = Some(Drag {
+            payload: payload,
+            icon: icon.map(|icon| icon.to_string()),
+            from_page: false,
+        });
+        self.start_pending = true;
+    }
+
+    /// Stops the drag in progress without dropping it anywhere.
+    pub fn cancel(&mut self) {
+        if self.active.is_some() {
+            self.cancel_pending = true;
+        }
+    }
+}
+
+/// Carries drags between the game and pages, and publishes how they end as DragEvents.
+pub struct DragSystem {
+    event_reader: ReaderId<RoutedEvent>,
+    message_reader: ReaderId<UiMessage>,
+    cursor: (f64, f64),
+}
+
+impl DragSystem {
+    pub fn new(world: &mut World) -> Self {
+        Self {
+            event_reader: world
+                .write_resource::<EventChannel<RoutedEvent>>()
+                .register_reader(),
+            message_reader: world
+                .write_resource::<EventChannel<UiMessage>>()
+                .register_reader(),
+            cursor: (0., 0.),
+        }
+    }
+}
+
+impl<'a> System<'a> for DragSystem {
+    type SystemData = (
+        Fetch<'a, EventChannel<RoutedEvent>>,
+        Fetch<'a, EventChannel<UiMessage>>,
+        FetchMut<'a, DragSession>,
+        FetchMut<'a, EventChannel<DragEvent>>,
+        FetchMut<'a, UiOutbox>,
+    );
+
+    fn run(
+        &mut self,
+        (events, messages, mut session, mut drag_events, mut outbox): Self::SystemData,
+    ) {
+        for message in messages.read(&mut self.message_reader) {
+            match message.name.as_str() {
+                "drag.start" => {
+                    session.active = Some(Drag {
+                        payload: message.payload["payload"].clone(),
+                        icon: message.payload["icon"].as_str().map(|icon| icon.to_string()),
+                        from_page: true,
+                    });
+                    session.start_pending = false;
+                }
+                "drag.drop" => {
+                    if let Some(drag) = session.active.take() {
+                        drag_events.single_write(DragEvent::DroppedOnPage {
+                            payload: drag.payload,
+                            target: message.payload["target"].clone(),
+                        });
+                    }
+                }
+                "drag.end" => {
+                    if let Some(drag) = session.active.take() {
+                        let event = if message.payload["overPage"].as_bool().unwrap_or(false) {
+                            // The page was in the way, so the world behind it shouldn't get it
+                            DragEvent::Cancelled {
+                                payload: drag.payload,
+                            }
+                        } else {
+                            DragEvent::DroppedOnWorld {
+                                payload: drag.payload,
+                                position: self.cursor,
+                            }
+                        };
+                        drag_events.single_write(event);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        if session.cancel_pending {
+            session.cancel_pending = false;
+            session.start_pending = false;
+            if let Some(drag) = session.active.take() {
+                outbox.send_to_current("drag.cancel", json!({}));
+                drag_events.single_write(DragEvent::Cancelled {
+                    payload: drag.payload,
+                });
+            }
+        }
+        if session.start_pending {
+            session.start_pending = false;
+            if let Some(ref drag) = session.active {
+                outbox.send_to_current(
+                    "drag.start",
+                    json!({ "payload": drag.payload, "icon": drag.icon }),
+                );
+            }
+        }
+
+        for routed in events.read(&mut self.event_reader) {
+            if let Event::WindowEvent { ref event, .. } = routed.event {
+                match event {
+                    &WindowEvent::CursorMoved { position, .. } => self.cursor = position,
+                    // Releases over the world never reach the page, so tell it where the drag
+                    // ended. The page settles the drop and answers with drag.drop or drag.end
+                    &WindowEvent::MouseInput {
+                        state: ElementState::Released,
+                        ..
+                    } if session.active.is_some() =>
+                    {
+                        outbox.send_to_current(
+                            "drag.release",
+                            json!({ "x": self.cursor.0, "y": self.cursor.1 }),
+                        );
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+}
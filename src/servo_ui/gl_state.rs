@@ -0,0 +1,375 @@
+extern crate servo as libservo;
+
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::rc::Rc;
+use self::libservo::gl;
+
+// Texture units whose 2D binding is saved, more than gfx or WebRender bind at once
+const TEXTURE_UNITS: gl::GLuint = 8;
+
+/// Snapshot of the GL state that gfx relies on and that WebRender is known to change without
+/// restoring it afterwards.
+///
+/// The colour write mask isn't captured, since gleam reads a single value where GL writes four.
+/// It is reset to all channels on restore instead, as gfx sets it for each pipeline anyway.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct GlState {
+    pub framebuffer: gl::GLint,
+    pub renderbuffer: gl::GLint,
+    pub program: gl::GLint,
+    pub vertex_array: gl::GLint,
+    pub array_buffer: gl::GLint,
+    pub element_array_buffer: gl::GLint,
+    pub pixel_unpack_buffer: gl::GLint,
+    pub pixel_pack_buffer: gl::GLint,
+    pub active_texture: gl::GLint,
+    /// 2D texture bound to the active unit.
+    pub texture_2d: gl::GLint,
+    /// 2D texture bound to each of the first units, the active one included.
+    pub texture_units: Vec<gl::GLint>,
+    pub viewport: [gl::GLint; 4],
+    pub scissor_box: [gl::GLint; 4],
+    pub blend: bool,
+    pub blend_src_rgb: gl::GLint,
+    pub blend_dst_rgb: gl::GLint,
+    pub blend_src_alpha: gl::GLint,
+    pub blend_dst_alpha: gl::GLint,
+    pub blend_equation_rgb: gl::GLint,
+    pub blend_equation_alpha: gl::GLint,
+    pub depth_test: bool,
+    pub depth_func: gl::GLint,
+    pub depth_mask: bool,
+    pub scissor_test: bool,
+    pub cull_face: bool,
+    pub stencil_test: bool,
+    /// Front face stencil settings, which are restored for both faces.
+    pub stencil_func: gl::GLint,
+    pub stencil_ref: gl::GLint,
+    pub stencil_value_mask: gl::GLint,
+    pub stencil_writemask: gl::GLint,
+    pub stencil_fail: gl::GLint,
+    pub stencil_pass_depth_fail: gl::GLint,
+    pub stencil_pass_depth_pass: gl::GLint,
+    pub front_face: gl::GLint,
+    pub cull_face_mode: gl::GLint,
+    pub polygon_offset_fill: bool,
+    pub polygon_offset_factor: gl::GLfloat,
+    pub polygon_offset_units: gl::GLfloat,
+    pub unpack_alignment: gl::GLint,
+    pub pack_alignment: gl::GLint,
+}
+
+impl GlState {
+    /// Reads the current state from the bound context.
+    pub fn capture(gl: &gl::Gl) -> Self {
+        let rect = |name| {
+            [
+                gl.get_integer_iv(name, 0),
+                gl.get_integer_iv(name, 1),
+                gl.get_integer_iv(name, 2),
+                gl.get_integer_iv(name, 3),
+            ]
+        };
+        let active_texture = gl.get_integer_v(gl::ACTIVE_TEXTURE);
+        let texture_2d = gl.get_integer_v(gl::TEXTURE_BINDING_2D);
+        let texture_units = (0..TEXTURE_UNITS)
+            .map(|unit| {
+                gl.active_texture(gl::TEXTURE0 + unit);
+                gl.get_integer_v(gl::TEXTURE_BINDING_2D)
+            })
+            .collect();
+        gl.active_texture(active_texture as gl::GLenum);
+        GlState {
+            framebuffer: gl.get_integer_v(gl::FRAMEBUFFER_BINDING),
+            renderbuffer: gl.get_integer_v(gl::RENDERBUFFER_BINDING),
+            program: gl.get_integer_v(gl::CURRENT_PROGRAM),
+            vertex_array: gl.get_integer_v(gl::VERTEX_ARRAY_BINDING),
+            array_buffer: gl.get_integer_v(gl::ARRAY_BUFFER_BINDING),
+            element_array_buffer: gl.get_integer_v(gl::ELEMENT_ARRAY_BUFFER_BINDING),
+            pixel_unpack_buffer: gl.get_integer_v(gl::PIXEL_UNPACK_BUFFER_BINDING),
+            pixel_pack_buffer: gl.get_integer_v(gl::PIXEL_PACK_BUFFER_BINDING),
+            active_texture: active_texture,
+            texture_2d: texture_2d,
+            texture_units: texture_units,
+            viewport: rect(gl::VIEWPORT),
+            scissor_box: rect(gl::SCISSOR_BOX),
+            blend: gl.is_enabled(gl::BLEND) != 0,
+            blend_src_rgb: gl.get_integer_v(gl::BLEND_SRC_RGB),
+            blend_dst_rgb: gl.get_integer_v(gl::BLEND_DST_RGB),
+            blend_src_alpha: gl.get_integer_v(gl::BLEND_SRC_ALPHA),
+            blend_dst_alpha: gl.get_integer_v(gl::BLEND_DST_ALPHA),
+            blend_equation_rgb: gl.get_integer_v(gl::BLEND_EQUATION_RGB),
+            blend_equation_alpha: gl.get_integer_v(gl::BLEND_EQUATION_ALPHA),
+            depth_test: gl.is_enabled(gl::DEPTH_TEST) != 0,
+            depth_func: gl.get_integer_v(gl::DEPTH_FUNC),
+            depth_mask: gl.get_boolean_v(gl::DEPTH_WRITEMASK) != 0,
+            scissor_test: gl.is_enabled(gl::SCISSOR_TEST) != 0,
+            cull_face: gl.is_enabled(gl::CULL_FACE) != 0,
+            stencil_test: gl.is_enabled(gl::STENCIL_TEST) != 0,
+            stencil_func: gl.get_integer_v(gl::STENCIL_FUNC),
+            stencil_ref: gl.get_integer_v(gl::STENCIL_REF),
+            stencil_value_mask: gl.get_integer_v(gl::STENCIL_VALUE_MASK),
+            stencil_writemask: gl.get_integer_v(gl::STENCIL_WRITEMASK),
+            stencil_fail: gl.get_integer_v(gl::STENCIL_FAIL),
+            stencil_pass_depth_fail: gl.get_integer_v(gl::STENCIL_PASS_DEPTH_FAIL),
+            stencil_pass_depth_pass: gl.get_integer_v(gl::STENCIL_PASS_DEPTH_PASS),
+            front_face: gl.get_integer_v(gl::FRONT_FACE),
+            cull_face_mode: gl.get_integer_v(gl::CULL_FACE_MODE),
+            polygon_offset_fill: gl.is_enabled(gl::POLYGON_OFFSET_FILL) != 0,
+            polygon_offset_factor: gl.get_float_v(gl::POLYGON_OFFSET_FACTOR),
+            polygon_offset_units: gl.get_float_v(gl::POLYGON_OFFSET_UNITS),
+            unpack_alignment: gl.get_integer_v(gl::UNPACK_ALIGNMENT),
+            pack_alignment: gl.get_integer_v(gl::PACK_ALIGNMENT),
+        }
+    }
+
+    /// Writes this snapshot back into the bound context.
+    pub fn restore(&self, gl: &gl::Gl) {
+        let toggle = |cap, enabled| if enabled {
+            gl.enable(cap)
+        } else {
+            gl.disable(cap)
+        };
+        gl.bind_framebuffer(gl::FRAMEBUFFER, self.framebuffer as gl::GLuint);
+        gl.bind_renderbuffer(gl::RENDERBUFFER, self.renderbuffer as gl::GLuint);
+        gl.use_program(self.program as gl::GLuint);
+        gl.bind_vertex_array(self.vertex_array as gl::GLuint);
+        gl.bind_buffer(gl::ARRAY_BUFFER, self.array_buffer as gl::GLuint);
+        gl.bind_buffer(
+            gl::ELEMENT_ARRAY_BUFFER,
+            self.element_array_buffer as gl::GLuint,
+        );
+        gl.bind_buffer(
+            gl::PIXEL_UNPACK_BUFFER,
+            self.pixel_unpack_buffer as gl::GLuint,
+        );
+        gl.bind_buffer(gl::PIXEL_PACK_BUFFER, self.pixel_pack_buffer as gl::GLuint);
+        for (unit, &texture) in self.texture_units.iter().enumerate() {
+            gl.active_texture(gl::TEXTURE0 + unit as gl::GLuint);
+            gl.bind_texture(gl::TEXTURE_2D, texture as gl::GLuint);
+        }
+        gl.active_texture(self.active_texture as gl::GLenum);
+        gl.bind_texture(gl::TEXTURE_2D, self.texture_2d as gl::GLuint);
+        gl.viewport(
+            self.viewport[0],
+            self.viewport[1],
+            self.viewport[2],
+            self.viewport[3],
+        );
+        gl.scissor(
+            self.scissor_box[0],
+            self.scissor_box[1],
+            self.scissor_box[2],
+            self.scissor_box[3],
+        );
+        toggle(gl::BLEND, self.blend);
+        gl.blend_func_separate(
+            self.blend_src_rgb as gl::GLenum,
+            self.blend_dst_rgb as gl::GLenum,
+            self.blend_src_alpha as gl::GLenum,
+            self.blend_dst_alpha as gl::GLenum,
+        );
+        gl.blend_equation_separate(
+            self.blend_equation_rgb as gl::GLenum,
+            self.blend_equation_alpha as gl::GLenum,
+        );
+        toggle(gl::DEPTH_TEST, self.depth_test);
+        gl.depth_func(self.depth_func as gl::GLenum);
+        gl.depth_mask(self.depth_mask);
+        toggle(gl::SCISSOR_TEST, self.scissor_test);
+        toggle(gl::CULL_FACE, self.cull_face);
+        toggle(gl::STENCIL_TEST, self.stencil_test);
+        gl.stencil_func(
+            self.stencil_func as gl::GLenum,
+            self.stencil_ref,
+            self.stencil_value_mask as gl::GLuint,
+        );
+        gl.stencil_mask(self.stencil_writemask as gl::GLuint);
+        gl.stencil_op(
+            self.stencil_fail as gl::GLenum,
+            self.stencil_pass_depth_fail as gl::GLenum,
+            self.stencil_pass_depth_pass as gl::GLenum,
+        );
+        gl.front_face(self.front_face as gl::GLenum);
+        gl.cull_face(self.cull_face_mode as gl::GLenum);
+        toggle(gl::POLYGON_OFFSET_FILL, self.polygon_offset_fill);
+        gl.polygon_offset(self.polygon_offset_factor, self.polygon_offset_units);
+        gl.color_mask(true, true, true, true);
+        gl.pixel_store_i(gl::UNPACK_ALIGNMENT, self.unpack_alignment);
+        gl.pixel_store_i(gl::PACK_ALIGNMENT, self.pack_alignment);
+    }
+
+    /// Lists the fields which differ between two snapshots, formatted as `name: self -> other`.
+    pub fn diff(&self, other: &GlState) -> Vec<String> {
+        let mut changes = vec![];
+        macro_rules! compare {
+            ($($field:ident),*) => {
+                $(
+                    if self.$field != other.$field {
+                        changes.push(format!(
+                            "{}: {:?} -> {:?}",
+                            stringify!($field),
+                            self.$field,
+                            other.$field
+                        ));
+                    }
+                )*
+            }
+        }
+        compare!(
+            framebuffer,
+            renderbuffer,
+            program,
+            vertex_array,
+            array_buffer,
+            element_array_buffer,
+            pixel_unpack_buffer,
+            pixel_pack_buffer,
+            active_texture,
+            texture_2d,
+            texture_units,
+            viewport,
+            scissor_box,
+            blend,
+            blend_src_rgb,
+            blend_dst_rgb,
+            blend_src_alpha,
+            blend_dst_alpha,
+            blend_equation_rgb,
+            blend_equation_alpha,
+            depth_test,
+            depth_func,
+            depth_mask,
+            scissor_test,
+            cull_face,
+            stencil_test,
+            stencil_func,
+            stencil_ref,
+            stencil_value_mask,
+            stencil_writemask,
+            stencil_fail,
+            stencil_pass_depth_fail,
+            stencil_pass_depth_pass,
+            front_face,
+            cull_face_mode,
+            polygon_offset_fill,
+            polygon_offset_factor,
+            polygon_offset_units,
+            unpack_alignment,
+            pack_alignment
+        );
+        changes
+    }
+}
+
+/// Holds the GL state captured before Servo composites and puts it back when dropped.
+///
+/// When `check_leaks` is set the state Servo left is captured before restoring and compared
+/// against the original snapshot. Each field Servo changes is reported the first time it does,
+/// rather than on every composite.
+pub struct GlStateGuard {
+    gl: Rc<gl::Gl>,
+    saved: GlState,
+    check_leaks: bool,
+}
+
+impl GlStateGuard {
+    pub fn new(gl: Rc<gl::Gl>, check_leaks: bool) -> Self {
+        let saved = GlState::capture(&*gl);
+        Self {
+            gl: gl,
+            saved: saved,
+            check_leaks: check_leaks,
+        }
+    }
+
+    pub fn saved(&self) -> &GlState {
+        &self.saved
+    }
+}
+
+thread_local! {
+    // Fields already reported as changed by a composite
+    static REPORTED: RefCell<HashSet<String>> = RefCell::new(HashSet::new());
+}
+
+/// The changes from `diff` whose field hasn't been reported before, marking them reported.
+fn unreported(changes: Vec<String>) -> Vec<String> {
+    REPORTED.with(|reported| {
+        let mut reported = reported.borrow_mut();
+        changes
+            .into_iter()
+            .filter(|change| {
+                let field = change.split(':').next().unwrap_or("");
+                reported.insert(field.to_string())
+            })
+            .collect()
+    })
+}
+
+impl Drop for GlStateGuard {
+    fn drop(&mut self) {
+        if self.check_leaks {
+            let composited = GlState::capture(&*self.gl);
+            for change in unreported(self.saved.diff(&composited)) {
+                eprintln!("WARNING: Servo composite changed GL state, restoring it: {}", change);
+            }
+        }
+        self.saved.restore(&*self.gl);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state() -> GlState {
+        GlState {
+            framebuffer: 3,
+            texture_units: vec![0; TEXTURE_UNITS as usize],
+            viewport: [0, 0, 800, 600],
+            depth_test: true,
+            ..GlState::default()
+        }
+    }
+
+    #[test]
+    fn identical_states_have_no_diff() {
+        assert!(state().diff(&state()).is_empty());
+    }
+
+    #[test]
+    fn diff_lists_changed_fields_in_order() {
+        let mut changed = state();
+        changed.framebuffer = 0;
+        changed.texture_units[3] = 7;
+        changed.viewport = [0, 0, 400, 300];
+        changed.depth_test = false;
+        changed.polygon_offset_units = 1.5;
+        assert_eq!(
+            state().diff(&changed),
+            vec![
+                "framebuffer: 3 -> 0",
+                "texture_units: [0, 0, 0, 0, 0, 0, 0, 0] -> [0, 0, 0, 7, 0, 0, 0, 0]",
+                "viewport: [0, 0, 800, 600] -> [0, 0, 400, 300]",
+                "depth_test: true -> false",
+                "polygon_offset_units: 0.0 -> 1.5",
+            ]
+        );
+    }
+
+    #[test]
+    fn each_field_is_reported_once() {
+        let changes = vec![
+            "blend: false -> true".to_string(),
+            "depth_func: 513 -> 515".to_string(),
+        ];
+        assert_eq!(unreported(changes.clone()), changes);
+        let again = vec![
+            "blend: true -> false".to_string(),
+            "front_face: 2305 -> 2304".to_string(),
+        ];
+        assert_eq!(unreported(again), vec!["front_face: 2305 -> 2304".to_string()]);
+    }
+}
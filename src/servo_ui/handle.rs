@@ -1,27 +1,64 @@
 extern crate gfx_device_gl;
 extern crate glutin;
 extern crate servo as libservo;
-use self::libservo::gl;
+use self::libservo::{gl, BrowserId};
 use self::libservo::Servo;
-use self::glutin::{GlContext, GlWindow, WindowEvent as GlutinWindowEvent};
+use self::glutin::{ElementState, GlContext, GlWindow, KeyboardInput,
+                   MouseButton as GlutinMouseButton, MouseScrollDelta, TouchPhase,
+                   WindowEvent as GlutinWindowEvent};
 use self::libservo::servo_config::resource_files::set_resources_path;
 use self::libservo::servo_config::opts;
 use self::libservo::ipc_channel::ipc;
 use self::libservo::servo_url::ServoUrl;
-use self::libservo::compositing::windowing::WindowEvent;
+use self::libservo::compositing::windowing::{AnimationState, MouseWindowEvent, WindowEvent};
+use self::libservo::euclid::{TypedPoint2D, TypedVector2D};
+use self::libservo::script_traits::{MouseButton, TouchEventType};
+use self::libservo::style_traits::DevicePixel;
+use self::libservo::webrender_api::ScrollLocation;
+use self::libservo::msg::constellation_msg::{self, Key, KeyState};
 
+use std::collections::HashMap;
 use std::env;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
+use std::sync::atomic::AtomicUsize;
 use std::rc::Rc;
+use std::time::Duration;
 use amethyst::prelude::World;
 use amethyst::renderer::ScreenDimensions;
 use amethyst::winit::EventsLoopProxy;
 
-use super::ServoWindow;
+use serde_json::Value;
+use super::{bridge, keys, PageKey, PageRequest, ServoUiConfig, ServoWindow, TouchTranslator,
+            UiMessage};
+
+// Furthest the cursor can move between press and release for them to still count as a click
+const CLICK_DISTANCE: f32 = 10.0;
+// Pixels scrolled per line for wheels which report line deltas
+const LINE_HEIGHT: f32 = 38.0;
 
 pub struct ServoHandle {
     pub window: Rc<ServoWindow>,
     pub servo: Servo<ServoWindow>,
+    // The browser currently selected for display
+    pub browser: BrowserId,
+    // The browser opened for the start URL, shown when no state page is
+    pub default_browser: BrowserId,
+    // Browsers opened for state pages, kept alive while their state is paused
+    pub pages: HashMap<PageKey, BrowserId>,
+    // The state page currently shown, None when the default browser is
+    pub shown: Option<PageKey>,
+    // What was shown before a toggled page, to go back to when it is toggled off
+    pub toggled_from: Option<(Option<PageKey>, BrowserId)>,
+    // Last cursor position reported by the window
+    pub cursor: TypedPoint2D<f32, DevicePixel>,
+    // Button and position of the last mouse press, to detect clicks on release
+    pub mouse_down: Option<(MouseButton, TypedPoint2D<f32, DevicePixel>)>,
+    // Directory Servo's resources are loaded from, page URLs without a scheme are relative to it
+    pub resources: PathBuf,
+    // Printable key pressed, held back until winit reports the character it produced
+    pub pending_key: Option<(Key, constellation_msg::KeyModifiers)>,
+    pub touch: TouchTranslator,
 }
 
 impl ServoHandle {
@@ -29,30 +66,268 @@ impl ServoHandle {
         self.servo.handle_events(vec![]);
     }
 
+    /// Delivers messages queued in the UiOutbox to their pages. Messages without a browser go to
+    /// the one currently selected.
+    pub fn send_messages(&mut self, messages: Vec<(Option<BrowserId>, String, Value)>) {
+        let browser = self.browser;
+        let events = messages
+            .into_iter()
+            .map(|(target, name, payload)| {
+                WindowEvent::LoadUrl(
+                    target.unwrap_or(browser),
+                    bridge::message_url(&name, &payload),
+                )
+            })
+            .collect();
+        self.servo.handle_events(events);
+    }
+
+    /// Scales page content on top of the window's DPI factor. Servo picks the new factor up when
+    /// it handles the resize this sends.
+    pub fn set_ui_scale(&mut self, scale: f32) {
+        match self.window.ui_scale.lock() {
+            Ok(mut ui_scale) => *ui_scale = scale,
+            Err(_) => {
+                eprintln!("ERROR: UI scale lock poisoned.");
+                return;
+            }
+        }
+        self.servo.handle_events(vec![WindowEvent::Resize]);
+    }
+
+    /// The URL a page path resolves to, with paths relative to the resources directory.
+    pub fn page_url(&self, url: &str) -> Result<String, String> {
+        resolve_url(&self.resources, url).map(|url| url.as_str().to_string())
+    }
+
+    /// Opens, selects and closes browsers as requested through UiPages.
+    pub fn apply_page_requests(&mut self, requests: Vec<PageRequest>) {
+        for request in requests {
+            match request {
+                PageRequest::Show(key, url) => {
+                    self.toggled_from = None;
+                    self.show_page(key, &url);
+                }
+                PageRequest::Hide(key) => if self.shown == Some(key) {
+                    let default_browser = self.default_browser;
+                    self.select_browser(default_browser);
+                    self.shown = None;
+                },
+                PageRequest::Close(key) => if let Some(browser) = self.pages.remove(&key) {
+                    if self.shown == Some(key) {
+                        let default_browser = self.default_browser;
+                        self.select_browser(default_browser);
+                        self.shown = None;
+                    }
+                    self.servo
+                        .handle_events(vec![WindowEvent::CloseBrowser(browser)]);
+                    // Published like a message from the page, so per page state can be dropped
+                    self.window.queue_message(UiMessage {
+                        browser: browser,
+                        name: "page.closed".to_string(),
+                        payload: Value::Null,
+                    });
+                },
+                PageRequest::ShowDefault => {
+                    self.toggled_from = None;
+                    let default_browser = self.default_browser;
+                    self.select_browser(default_browser);
+                    self.shown = None;
+                }
+                PageRequest::Navigate(url) => match resolve_url(&self.resources, &url) {
+                    Ok(url) => {
+                        let browser = self.browser;
+                        self.servo
+                            .handle_events(vec![WindowEvent::LoadUrl(browser, url)]);
+                    }
+                    Err(e) => eprintln!("ERROR: Failed to navigate UI page: {}", e),
+                },
+                PageRequest::Reload => {
+                    let browser = self.browser;
+                    self.servo.handle_events(vec![WindowEvent::Reload(browser)]);
+                }
+                PageRequest::Toggle(key, url) => match self.toggled_from.take() {
+                    Some((shown, browser)) if self.shown == Some(key) => {
+                        self.select_browser(browser);
+                        self.shown = shown;
+                    }
+                    _ => {
+                        let previous = (self.shown, self.browser);
+                        self.show_page(key, &url);
+                        self.toggled_from = Some(previous);
+                    }
+                },
+            }
+        }
+    }
+
+    /// Selects the page's browser, opening it first if needed.
+    fn show_page(&mut self, key: PageKey, url: &str) {
+        let browser = match self.pages.get(&key).cloned() {
+            Some(browser) => browser,
+            None => match open_browser(&mut self.servo, &self.resources, url) {
+                Ok(browser) => {
+                    self.pages.insert(key, browser);
+                    browser
+                }
+                Err(e) => {
+                    eprintln!("ERROR: Failed to open UI page {}: {}", url, e);
+                    return;
+                }
+            },
+        };
+        self.select_browser(browser);
+        self.shown = Some(key);
+    }
+
+    pub fn select_browser(&mut self, browser: BrowserId) {
+        if self.browser != browser {
+            self.servo
+                .handle_events(vec![WindowEvent::SelectBrowser(browser)]);
+            self.browser = browser;
+        }
+    }
+
     pub fn forward_events(&mut self, events: Vec<GlutinWindowEvent>) {
         let events: Vec<WindowEvent> = events
             .iter()
-            .map(|e| match e {
-                &GlutinWindowEvent::Resized(x, y) => {
-                    self.window.set_dimensions(x, y);
-                    WindowEvent::Resize
-                }
-                _ => WindowEvent::Idle,
-            })
+            .flat_map(|e| self.translate_event(e))
             .collect();
         self.servo.handle_events(events);
     }
+
+    fn translate_event(&mut self, event: &GlutinWindowEvent) -> Vec<WindowEvent> {
+        match event {
+            &GlutinWindowEvent::Resized(x, y) => {
+                self.window.set_dimensions(x, y);
+                vec![WindowEvent::Resize]
+            }
+            &GlutinWindowEvent::CursorMoved {
+                position: (x, y), ..
+            } => {
+                self.cursor = TypedPoint2D::new(x as f32, y as f32);
+                vec![WindowEvent::MouseWindowMoveEventClass(self.cursor)]
+            }
+            &GlutinWindowEvent::MouseInput { state, button, .. } => {
+                self.translate_mouse_button(state, button)
+            }
+            &GlutinWindowEvent::KeyboardInput { input, .. } => self.translate_key(input),
+            &GlutinWindowEvent::ReceivedCharacter(ch) => self.translate_character(ch),
+            &GlutinWindowEvent::Touch(ref touch) => {
+                self.touch
+                    .translate(touch.phase, touch.id, touch.location)
+            }
+            &GlutinWindowEvent::MouseWheel { delta, phase, .. } => {
+                let (dx, dy) = match delta {
+                    MouseScrollDelta::LineDelta(dx, dy) => (dx * LINE_HEIGHT, dy * LINE_HEIGHT),
+                    MouseScrollDelta::PixelDelta(dx, dy) => (dx, dy),
+                };
+                let phase = match phase {
+                    TouchPhase::Started => TouchEventType::Down,
+                    TouchPhase::Moved => TouchEventType::Move,
+                    TouchPhase::Ended => TouchEventType::Up,
+                    TouchPhase::Cancelled => TouchEventType::Cancel,
+                };
+                vec![WindowEvent::Scroll(
+                    ScrollLocation::Delta(TypedVector2D::new(dx, dy)),
+                    self.cursor.to_i32(),
+                    phase,
+                )]
+            }
+            _ => vec![],
+        }
+    }
+
+    fn translate_key(&mut self, input: KeyboardInput) -> Vec<WindowEvent> {
+        // A printable key whose character never arrived still needs its key event
+        let mut events: Vec<WindowEvent> = self.pending_key
+            .take()
+            .map(|(key, modifiers)| WindowEvent::KeyEvent(None, key, KeyState::Pressed, modifiers))
+            .into_iter()
+            .collect();
+        let key = match input.virtual_keycode.and_then(keys::servo_key) {
+            Some(key) => key,
+            None => return events,
+        };
+        let modifiers = keys::servo_modifiers(input.modifiers);
+        let shortcut = input.modifiers.ctrl || input.modifiers.alt || input.modifiers.logo;
+        match input.state {
+            ElementState::Pressed if keys::is_printable(key) && !shortcut => {
+                self.pending_key = Some((key, modifiers));
+            }
+            state => {
+                events.push(WindowEvent::KeyEvent(
+                    None,
+                    key,
+                    keys::servo_key_state(state),
+                    modifiers,
+                ));
+            }
+        }
+        events
+    }
+
+    fn translate_character(&mut self, ch: char) -> Vec<WindowEvent> {
+        // Control characters arrive as key events already
+        if ch.is_control() {
+            return vec![];
+        }
+        match self.pending_key.take() {
+            Some((key, modifiers)) => vec![
+                WindowEvent::KeyEvent(Some(ch), key, KeyState::Pressed, modifiers),
+            ],
+            None => vec![],
+        }
+    }
+
+    fn translate_mouse_button(
+        &mut self,
+        state: ElementState,
+        button: GlutinMouseButton,
+    ) -> Vec<WindowEvent> {
+        let button = match button {
+            GlutinMouseButton::Left => MouseButton::Left,
+            GlutinMouseButton::Right => MouseButton::Right,
+            GlutinMouseButton::Middle => MouseButton::Middle,
+            GlutinMouseButton::Other(_) => return vec![],
+        };
+        let point = self.cursor;
+        match state {
+            ElementState::Pressed => {
+                self.mouse_down = Some((button, point));
+                vec![WindowEvent::MouseWindowEventClass(
+                    MouseWindowEvent::MouseDown(button, point),
+                )]
+            }
+            ElementState::Released => {
+                let mut events = vec![WindowEvent::MouseWindowEventClass(
+                    MouseWindowEvent::MouseUp(button, point),
+                )];
+                if let Some((down_button, down_point)) = self.mouse_down.take() {
+                    if down_button == button && (point - down_point).length() < CLICK_DISTANCE {
+                        events.push(WindowEvent::MouseWindowEventClass(
+                            MouseWindowEvent::Click(button, point),
+                        ));
+                    }
+                }
+                events
+            }
+        }
+    }
 }
 
 impl ServoHandle {
-    pub fn start_servo(world: &World) -> ServoHandle {
+    /// Starts Servo on the amethyst window's GL context and opens the start page. Fails if the
+    /// context can't be made current, the resources directory or start URL are invalid, or the
+    /// first browser can't be opened.
+    pub fn start_servo(world: &World, config: &ServoUiConfig) -> Result<ServoHandle, String> {
         // Fetch gl context
         let gl = unsafe {
             let window = world.read_resource::<Arc<GlWindow>>();
             window
                 .context()
                 .make_current()
-                .expect("Failed to make current");
+                .map_err(|e| format!("Failed to make the GL context current: {:?}", e))?;
             gl::GlFns::load_with(|s| window.context().get_proc_address(s) as *const _)
         };
 
@@ -73,28 +348,111 @@ impl ServoHandle {
             ))),
             target_texture: Arc::new(Mutex::new(None)),
             frame_buffer: Arc::new(Mutex::new(None)),
+            offscreen_buffers: Arc::new(Mutex::new(None)),
+            gl_state: Arc::new(Mutex::new(None)),
+            debug_gl_state: config.debug_gl_state,
+            upload: config.upload,
+            readback: Arc::new(Mutex::new(None)),
+            frame: Arc::new(Mutex::new(None)),
+            hit_alpha: Arc::new(Mutex::new(None)),
+            animation_state: Arc::new(Mutex::new(AnimationState::Idle)),
+            composites: Arc::new(AtomicUsize::new(0)),
+            composite_started: Arc::new(Mutex::new(None)),
+            composite_time: Arc::new(Mutex::new(Duration::from_secs(0))),
+            ui_scale: Arc::new(Mutex::new(1.0)),
+            inbox: Arc::new(Mutex::new(vec![])),
+            unhandled_keys: Arc::new(Mutex::new(vec![])),
         });
 
         // Get resources
-        let path = env::current_dir().unwrap().join("resources");
-        let path = path.to_str().unwrap().to_string();
-        set_resources_path(Some(path));
-        let opts = opts::default_opts();
+        let resources = match config.resources {
+            Some(ref path) => path.clone(),
+            None => env::current_dir()
+                .map_err(|e| format!("Failed to find the resources directory: {}", e))?
+                .join("resources"),
+        };
+        match resources.to_str() {
+            Some(path) => set_resources_path(Some(path.to_string())),
+            None => {
+                return Err(format!(
+                    "Resources path {} isn't valid UTF-8",
+                    resources.display()
+                ))
+            }
+        }
+        let mut opts = opts::default_opts();
+        opts.headless = config.headless;
+        // Scripts run before every page, including the message bridge
+        opts.userscripts = resources
+            .join("user-agent-js")
+            .to_str()
+            .map(|path| path.to_string());
+        if cfg!(debug_assertions) {
+            opts.devtools_port = config.devtools_port;
+            opts.webdriver_port = config.webdriver_port;
+            if let Some(port) = config.devtools_port {
+                println!("Servo devtools server listening on 127.0.0.1:{}", port);
+            }
+            if let Some(port) = config.webdriver_port {
+                println!("Servo WebDriver server listening on 127.0.0.1:{}", port);
+            }
+        } else if config.devtools_port.is_some() || config.webdriver_port.is_some() {
+            eprintln!("WARNING: Servo devtools and WebDriver servers are disabled in release builds.");
+        }
+        // Servo's own profilers break down layout, script and JS heap use, but only report to
+        // stdout, where ServoUiSystem reads them back
+        if let Some(period) = config.servo_profiler_period {
+            opts.time_profiling = Some(opts::OutputOptions::Stdout(period));
+            opts.mem_profiler_period = Some(period);
+        }
         opts::set_defaults(opts);
 
         // Start servo
         let mut servo = Servo::new(renderer.clone());
 
         // Launch servo
-        let url = ServoUrl::parse("file:///home/matthew/git/openrlfb/resources/test.html").unwrap();
-        let (sender, receiver) = ipc::channel().unwrap();
-        servo.handle_events(vec![WindowEvent::NewBrowser(url, sender)]);
-        let id = receiver.recv().unwrap();
+        let url = match config.start_url {
+            Some(ref url) => url.clone(),
+            None => "test.html".to_string(),
+        };
+        let id = open_browser(&mut servo, &resources, &url)
+            .map_err(|e| format!("Failed to open the start page: {}", e))?;
         servo.handle_events(vec![WindowEvent::SelectBrowser(id)]);
 
-        ServoHandle {
+        Ok(ServoHandle {
             servo: servo,
             window: renderer.clone(),
-        }
+            browser: id,
+            default_browser: id,
+            pages: HashMap::new(),
+            shown: None,
+            toggled_from: None,
+            cursor: TypedPoint2D::zero(),
+            mouse_down: None,
+            resources: resources,
+            pending_key: None,
+            touch: TouchTranslator::new(config.touch_emulates_mouse, config.pinch_zoom),
+        })
+    }
+}
+
+/// Opens a new browser on `url` without selecting it.
+fn open_browser(
+    servo: &mut Servo<ServoWindow>,
+    resources: &Path,
+    url: &str,
+) -> Result<BrowserId, String> {
+    let url = resolve_url(resources, url)?;
+    let (sender, receiver) = ipc::channel().map_err(|e| format!("{:?}", e))?;
+    servo.handle_events(vec![WindowEvent::NewBrowser(url, sender)]);
+    receiver.recv().map_err(|e| format!("{:?}", e))
+}
+
+/// Parses a page URL, treating URLs without a scheme as paths relative to the resources directory.
+fn resolve_url(resources: &Path, url: &str) -> Result<ServoUrl, String> {
+    match ServoUrl::parse(url) {
+        Ok(url) => Ok(url),
+        Err(_) => ServoUrl::from_file_path(resources.join(url))
+            .map_err(|_| format!("Invalid page path {}", url)),
     }
 }
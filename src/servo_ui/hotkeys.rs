@@ -0,0 +1,617 @@
+extern crate servo as libservo;
+
+use std::collections::HashSet;
+use std::fmt;
+use amethyst::ecs::System;
+use amethyst::prelude::World;
+use amethyst::renderer::Event;
+use amethyst::shred::{Fetch, FetchMut};
+use amethyst::shrev::{EventChannel, ReaderId};
+use amethyst::winit::{ElementState, KeyboardInput, VirtualKeyCode, WindowEvent};
+use self::libservo::BrowserId;
+use serde_json::{self, Value};
+use super::{keys, ImeCaret, RoutedEvent, UiMessage, UiOutbox, UiPages};
+
+// Shortcuts declared in page markup, such as
+// `<button data-hotkey="I" data-action="open_inventory">`.
+//
+// Once a page has loaded, HotkeySystem asks it for its hotkeys with a `hotkeys.scan` message, and
+// `19.game-hotkeys.js` answers with every element's `data-hotkey` and `data-action` in a
+// `hotkeys.found` message, again whenever they change. The bindings go into the ActionMap next
+// to the game's own, and pressing a hotkey publishes its action as a UiAction.
+
+/// A key with the modifiers which have to be held with it, written like `I`, `F5` or
+/// `Ctrl+Shift+S`. Keys are named as by `keys::key_name`, and digits can be written as is.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Hotkey {
+    pub key: VirtualKeyCode,
+    pub shift: bool,
+    pub ctrl: bool,
+    pub alt: bool,
+    pub logo: bool,
+}
+
+impl Hotkey {
+    /// A key without modifiers.
+    pub fn new(key: VirtualKeyCode) -> Self {
+        Self {
+            key: key,
+            shift: false,
+            ctrl: false,
+            alt: false,
+            logo: false,
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut parts: Vec<&str> = text.split('+').map(|part| part.trim()).collect();
+        let key = parts.pop().unwrap_or("");
+        let key = match key.len() {
+            1 if key.chars().all(|ch| ch.is_digit(10)) => {
+                keys::key_from_name(&format!("Key{}", key))
+            }
+            _ => keys::key_from_name(key),
+        };
+        let mut hotkey = match key {
+            Some(key) => Hotkey::new(key),
+            None => return Err(format!("Unknown key in hotkey '{}'", text)),
+        };
+        for modifier in parts {
+            match modifier.to_lowercase().as_str() {
+                "shift" => hotkey.shift = true,
+                "ctrl" | "control" => hotkey.ctrl = true,
+                "alt" => hotkey.alt = true,
+                "logo" | "super" | "meta" | "cmd" => hotkey.logo = true,
+                _ => {
+                    return Err(format!(
+                        "Unknown modifier '{}' in hotkey '{}'",
+                        modifier, text
+                    ))
+                }
+            }
+        }
+        Ok(hotkey)
+    }
+
+    pub fn matches(&self, input: &KeyboardInput) -> bool {
+        input.virtual_keycode == Some(self.key) && input.modifiers.shift == self.shift
+            && input.modifiers.ctrl == self.ctrl && input.modifiers.alt == self.alt
+            && input.modifiers.logo == self.logo
+    }
+}
+
+impl fmt::Display for Hotkey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.ctrl {
+            write!(f, "Ctrl+")?;
+        }
+        if self.alt {
+            write!(f, "Alt+")?;
+        }
+        if self.shift {
+            write!(f, "Shift+")?;
+        }
+        if self.logo {
+            write!(f, "Logo+")?;
+        }
+        match keys::key_name(self.key) {
+            Some(name) => write!(f, "{}", name),
+            None => write!(f, "{:?}", self.key),
+        }
+    }
+}
+
+/// Where a hotkey binding was declared.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BindingSource {
+    /// Bound by game code, active whichever page is shown.
+    Game,
+    /// Declared in a page's markup, active while that page is shown.
+    Page(BrowserId),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HotkeyBinding {
+    pub hotkey: Hotkey,
+    pub action: String,
+    pub source: BindingSource,
+}
+
+/// A binding refused because its hotkey was already bound to another action which can be active
+/// at the same time, or a page binding removed because the game bound its hotkey.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HotkeyConflict {
+    pub hotkey: Hotkey,
+    /// Action the hotkey stays bound to.
+    pub bound: String,
+    /// Action of the refused or removed binding.
+    pub refused: String,
+    pub source: BindingSource,
+}
+
+/// An action whose hotkey was pressed, published on `EventChannel<UiAction>`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UiAction {
+    pub name: String,
+    pub hotkey: Hotkey,
+    /// Page the binding was declared on, None for bindings made by the game.
+    pub page: Option<BrowserId>,
+}
+
+/// Hotkeys bound to named actions, by the game and by page markup.
+///
+/// Game bindings conflict with any other binding for the same hotkey, and page bindings with
+/// other bindings from the same page. Game bindings win over page bindings whichever came first,
+/// and otherwise the first binding for a hotkey is kept. The losing binding is removed or refused
+/// with a warning and listed in `conflicts`. Binding the same hotkey to the same action twice, as
+/// with two buttons for one action, isn't a conflict.
+///
+/// A page's bindings are dropped when it loads a new document or is closed.
+pub struct ActionMap {
+    bindings: Vec<HotkeyBinding>,
+    conflicts: Vec<HotkeyConflict>,
+}
+
+impl ActionMap {
+    pub fn new() -> Self {
+        Self {
+            bindings: vec![],
+            conflicts: vec![],
+        }
+    }
+
+    /// Binds a hotkey to an action for as long as the game runs.
+    pub fn bind(&mut self, hotkey: Hotkey, action: &str) -> Result<(), String> {
+        self.add(hotkey, action, BindingSource::Game)
+    }
+
+    /// Removes the game's bindings for an action.
+    pub fn unbind(&mut self, action: &str) {
+        self.bindings.retain(|binding| {
+            binding.source != BindingSource::Game || binding.action != action
+        });
+    }
+
+    /// Replaces the bindings declared by a page.
+    pub fn set_page_bindings(&mut self, browser: BrowserId, bindings: Vec<(Hotkey, String)>) {
+        let source = BindingSource::Page(browser);
+        self.bindings.retain(|binding| binding.source != source);
+        self.conflicts.retain(|conflict| conflict.source != source);
+        for (hotkey, action) in bindings {
+            if let Err(e) = self.add(hotkey, &action, source) {
+                eprintln!("WARNING: {}", e);
+            }
+        }
+    }
+
+    pub fn bindings(&self) -> &[HotkeyBinding] {
+        &self.bindings
+    }
+
+    /// Bindings refused because their hotkey was taken, since the game started for game bindings
+    /// and since each page last declared its hotkeys for page bindings.
+    pub fn conflicts(&self) -> &[HotkeyConflict] {
+        &self.conflicts
+    }
+
+    /// The binding a key press triggers while `page` is shown.
+    pub fn lookup(
+        &self,
+        input: &KeyboardInput,
+        page: Option<BrowserId>,
+    ) -> Option<&HotkeyBinding> {
+        self.bindings.iter().find(|binding| {
+            binding.hotkey.matches(input) && match binding.source {
+                BindingSource::Game => true,
+                BindingSource::Page(browser) => Some(browser) == page,
+            }
+        })
+    }
+
+    fn add(&mut self, hotkey: Hotkey, action: &str, source: BindingSource) -> Result<(), String> {
+        let taken_by_game = self.bindings.iter().any(|binding| {
+            binding.source == BindingSource::Game && binding.hotkey == hotkey
+                && binding.action != action
+        });
+        if source == BindingSource::Game && !taken_by_game {
+            self.remove_page_bindings_for(hotkey, action);
+        }
+        let existing = self.bindings
+            .iter()
+            .find(|binding| {
+                binding.hotkey == hotkey
+                    && (source == BindingSource::Game || binding.source == BindingSource::Game
+                        || binding.source == source)
+            })
+            .cloned();
+        match existing {
+            Some(ref binding) if binding.action == action => Ok(()),
+            Some(binding) => {
+                self.conflicts.push(HotkeyConflict {
+                    hotkey: hotkey,
+                    bound: binding.action.clone(),
+                    refused: action.to_string(),
+                    source: source,
+                });
+                Err(format!(
+                    "Hotkey {} for '{}' is already bound to '{}'",
+                    hotkey, action, binding.action
+                ))
+            }
+            None => {
+                self.bindings.push(HotkeyBinding {
+                    hotkey: hotkey,
+                    action: action.to_string(),
+                    source: source,
+                });
+                Ok(())
+            }
+        }
+    }
+
+    /// Removes the page bindings a game binding of `hotkey` to `action` takes priority over.
+    fn remove_page_bindings_for(&mut self, hotkey: Hotkey, action: &str) {
+        let (taken, kept): (Vec<_>, Vec<_>) = self.bindings.drain(..).partition(|binding| {
+            binding.source != BindingSource::Game && binding.hotkey == hotkey
+                && binding.action != action
+        });
+        self.bindings = kept;
+        for binding in taken {
+            eprintln!(
+                "WARNING: Hotkey {} for '{}' is taken over by the game's '{}'",
+                hotkey, binding.action, action
+            );
+            self.conflicts.push(HotkeyConflict {
+                hotkey: hotkey,
+                bound: action.to_string(),
+                refused: binding.action,
+                source: binding.source,
+            });
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct DeclaredHotkey {
+    hotkey: String,
+    action: String,
+    /// Describes the element declaring it, for warnings.
+    #[serde(default)]
+    element: String,
+}
+
+/// Collects the hotkeys declared by pages into the ActionMap, and publishes UiActions when their
+/// hotkeys are pressed, whether or not the page has focus. Hotkeys without Ctrl, Alt or the logo
+/// key are left alone while a text field has focus, so typing doesn't trigger them.
+pub struct HotkeySystem {
+    event_reader: ReaderId<RoutedEvent>,
+    message_reader: ReaderId<UiMessage>,
+    // Keys held down, so key repeat doesn't trigger an action again
+    held: HashSet<VirtualKeyCode>,
+}
+
+impl HotkeySystem {
+    pub fn new(world: &mut World) -> Self {
+        Self {
+            event_reader: world
+                .write_resource::<EventChannel<RoutedEvent>>()
+                .register_reader(),
+            message_reader: world
+                .write_resource::<EventChannel<UiMessage>>()
+                .register_reader(),
+            held: HashSet::new(),
+        }
+    }
+}
+
+fn declared_bindings(payload: &Value) -> Vec<(Hotkey, String)> {
+    let declared: Vec<DeclaredHotkey> = match payload.get("hotkeys") {
+        Some(hotkeys) => match serde_json::from_value(hotkeys.clone()) {
+            Ok(declared) => declared,
+            Err(e) => {
+                eprintln!("ERROR: Malformed hotkeys from page: {}", e);
+                return vec![];
+            }
+        },
+        None => return vec![],
+    };
+    declared
+        .into_iter()
+        .filter_map(|declared| match Hotkey::parse(&declared.hotkey) {
+            Ok(hotkey) => Some((hotkey, declared.action)),
+            Err(e) => {
+                eprintln!("WARNING: {} on {}", e, declared.element);
+                None
+            }
+        })
+        .collect()
+}
+
+impl<'a> System<'a> for HotkeySystem {
+    type SystemData = (
+        Fetch<'a, EventChannel<RoutedEvent>>,
+        Fetch<'a, EventChannel<UiMessage>>,
+        FetchMut<'a, ActionMap>,
+        FetchMut<'a, EventChannel<UiAction>>,
+        FetchMut<'a, UiOutbox>,
+        Fetch<'a, UiPages>,
+        Fetch<'a, ImeCaret>,
+    );
+
+    fn run(
+        &mut self,
+        (events, messages, mut actions, mut ui_actions, mut outbox, pages, caret): Self::SystemData,
+    ) {
+        for message in messages.read(&mut self.message_reader) {
+            match message.name.as_str() {
+                "page.loaded" => {
+                    actions.set_page_bindings(message.browser, vec![]);
+                    outbox.send(message.browser, "hotkeys.scan", Value::Null);
+                }
+                "page.closed" => actions.set_page_bindings(message.browser, vec![]),
+                "hotkeys.found" => {
+                    let bindings = declared_bindings(&message.payload);
+                    actions.set_page_bindings(message.browser, bindings);
+                }
+                _ => {}
+            }
+        }
+
+        for routed in events.read(&mut self.event_reader) {
+            let input = match routed.event {
+                Event::WindowEvent {
+                    event: WindowEvent::KeyboardInput { input, .. },
+                    ..
+                } => input,
+                Event::WindowEvent {
+                    event: WindowEvent::Focused(false),
+                    ..
+                } => {
+                    self.held.clear();
+                    continue;
+                }
+                _ => continue,
+            };
+            let key = match input.virtual_keycode {
+                Some(key) => key,
+                None => continue,
+            };
+            if input.state == ElementState::Released {
+                self.held.remove(&key);
+                continue;
+            }
+            if !self.held.insert(key) {
+                continue;
+            }
+            let typing = caret.position.is_some() && !input.modifiers.ctrl
+                && !input.modifiers.alt && !input.modifiers.logo;
+            if typing {
+                continue;
+            }
+            if let Some(binding) = actions.lookup(&input, pages.current()) {
+                ui_actions.single_write(UiAction {
+                    name: binding.action.clone(),
+                    hotkey: binding.hotkey,
+                    page: match binding.source {
+                        BindingSource::Game => None,
+                        BindingSource::Page(browser) => Some(browser),
+                    },
+                });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use amethyst::winit::ModifiersState;
+    use super::super::test_browsers;
+    use super::*;
+
+    fn ctrl(key: VirtualKeyCode) -> Hotkey {
+        Hotkey {
+            ctrl: true,
+            ..Hotkey::new(key)
+        }
+    }
+
+    fn press(key: VirtualKeyCode, ctrl: bool) -> KeyboardInput {
+        KeyboardInput {
+            scancode: 0,
+            state: ElementState::Pressed,
+            virtual_keycode: Some(key),
+            modifiers: ModifiersState {
+                ctrl: ctrl,
+                ..ModifiersState::default()
+            },
+        }
+    }
+
+    fn actions(map: &ActionMap) -> Vec<(&str, BindingSource)> {
+        map.bindings()
+            .iter()
+            .map(|binding| (binding.action.as_str(), binding.source))
+            .collect()
+    }
+
+    #[test]
+    fn parses_modifiers_and_keys() {
+        assert_eq!(
+            Hotkey::parse("Ctrl+Shift+S"),
+            Ok(Hotkey {
+                shift: true,
+                ..ctrl(VirtualKeyCode::S)
+            })
+        );
+        assert_eq!(
+            Hotkey::parse(" control + alt + f5 "),
+            Ok(Hotkey {
+                alt: true,
+                ..ctrl(VirtualKeyCode::F5)
+            })
+        );
+        assert_eq!(Hotkey::parse("1"), Ok(Hotkey::new(VirtualKeyCode::Key1)));
+        assert_eq!(Hotkey::parse("Key1"), Ok(Hotkey::new(VirtualKeyCode::Key1)));
+        assert_eq!(
+            Hotkey::parse("Ctrl+Shift+S").map(|hotkey| hotkey.to_string()),
+            Ok("Ctrl+Shift+S".to_string())
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_keys_and_modifiers() {
+        assert_eq!(
+            Hotkey::parse("Hyper+S"),
+            Err("Unknown modifier 'Hyper' in hotkey 'Hyper+S'".to_string())
+        );
+        assert_eq!(
+            Hotkey::parse("Ctrl+Nope"),
+            Err("Unknown key in hotkey 'Ctrl+Nope'".to_string())
+        );
+        assert!(Hotkey::parse("").is_err());
+        assert!(Hotkey::parse("Ctrl+").is_err());
+    }
+
+    #[test]
+    fn matches_only_the_exact_modifiers() {
+        let hotkey = ctrl(VirtualKeyCode::S);
+        assert!(hotkey.matches(&press(VirtualKeyCode::S, true)));
+        assert!(!hotkey.matches(&press(VirtualKeyCode::S, false)));
+        assert!(!Hotkey::new(VirtualKeyCode::S).matches(&press(VirtualKeyCode::S, true)));
+    }
+
+    #[test]
+    fn game_bindings_refuse_later_page_bindings() {
+        let page = test_browsers(1)[0];
+        let mut map = ActionMap::new();
+        map.bind(Hotkey::new(VirtualKeyCode::I), "inventory").unwrap();
+        map.set_page_bindings(
+            page,
+            vec![(Hotkey::new(VirtualKeyCode::I), "items".to_string())],
+        );
+
+        assert_eq!(actions(&map), vec![("inventory", BindingSource::Game)]);
+        assert_eq!(
+            map.conflicts(),
+            &[HotkeyConflict {
+                hotkey: Hotkey::new(VirtualKeyCode::I),
+                bound: "inventory".to_string(),
+                refused: "items".to_string(),
+                source: BindingSource::Page(page),
+            }]
+        );
+    }
+
+    #[test]
+    fn game_bindings_take_over_earlier_page_bindings() {
+        let page = test_browsers(1)[0];
+        let mut map = ActionMap::new();
+        map.set_page_bindings(
+            page,
+            vec![
+                (Hotkey::new(VirtualKeyCode::I), "items".to_string()),
+                (Hotkey::new(VirtualKeyCode::M), "map".to_string()),
+            ],
+        );
+        map.bind(Hotkey::new(VirtualKeyCode::I), "inventory").unwrap();
+
+        assert_eq!(
+            actions(&map),
+            vec![
+                ("map", BindingSource::Page(page)),
+                ("inventory", BindingSource::Game),
+            ]
+        );
+        assert_eq!(
+            map.conflicts(),
+            &[HotkeyConflict {
+                hotkey: Hotkey::new(VirtualKeyCode::I),
+                bound: "inventory".to_string(),
+                refused: "items".to_string(),
+                source: BindingSource::Page(page),
+            }]
+        );
+        let binding = map.lookup(&press(VirtualKeyCode::I, false), Some(page));
+        assert_eq!(
+            binding.map(|binding| binding.action.as_str()),
+            Some("inventory")
+        );
+    }
+
+    #[test]
+    fn game_bindings_keep_the_first_action() {
+        let mut map = ActionMap::new();
+        map.bind(ctrl(VirtualKeyCode::S), "save").unwrap();
+        assert!(map.bind(ctrl(VirtualKeyCode::S), "screenshot").is_err());
+        // The same action twice isn't a conflict
+        assert!(map.bind(ctrl(VirtualKeyCode::S), "save").is_ok());
+        assert_eq!(actions(&map), vec![("save", BindingSource::Game)]);
+        assert_eq!(map.conflicts().len(), 1);
+
+        map.unbind("save");
+        assert!(map.bind(ctrl(VirtualKeyCode::S), "screenshot").is_ok());
+    }
+
+    #[test]
+    fn pages_only_conflict_with_themselves() {
+        let pages = test_browsers(2);
+        let mut map = ActionMap::new();
+        map.set_page_bindings(
+            pages[0],
+            vec![
+                (Hotkey::new(VirtualKeyCode::Q), "quit".to_string()),
+                (Hotkey::new(VirtualKeyCode::Q), "quests".to_string()),
+            ],
+        );
+        map.set_page_bindings(
+            pages[1],
+            vec![(Hotkey::new(VirtualKeyCode::Q), "quests".to_string())],
+        );
+
+        assert_eq!(
+            actions(&map),
+            vec![
+                ("quit", BindingSource::Page(pages[0])),
+                ("quests", BindingSource::Page(pages[1])),
+            ]
+        );
+        assert_eq!(map.conflicts().len(), 1);
+
+        // Each page's binding is only active while it is shown
+        let q = press(VirtualKeyCode::Q, false);
+        let action = |page| map.lookup(&q, page).map(|binding| binding.action.clone());
+        assert_eq!(action(Some(pages[0])), Some("quit".to_string()));
+        assert_eq!(action(Some(pages[1])), Some("quests".to_string()));
+        assert_eq!(action(None), None);
+    }
+
+    #[test]
+    fn closing_a_page_drops_its_bindings_and_conflicts() {
+        let pages = test_browsers(2);
+        let mut map = ActionMap::new();
+        map.bind(Hotkey::new(VirtualKeyCode::I), "inventory").unwrap();
+        map.set_page_bindings(
+            pages[0],
+            vec![
+                (Hotkey::new(VirtualKeyCode::I), "items".to_string()),
+                (Hotkey::new(VirtualKeyCode::M), "map".to_string()),
+            ],
+        );
+        map.set_page_bindings(
+            pages[1],
+            vec![(Hotkey::new(VirtualKeyCode::M), "map".to_string())],
+        );
+
+        // What HotkeySystem does on page.closed
+        map.set_page_bindings(pages[0], vec![]);
+
+        assert_eq!(
+            actions(&map),
+            vec![
+                ("inventory", BindingSource::Game),
+                ("map", BindingSource::Page(pages[1])),
+            ]
+        );
+        assert!(map.conflicts().is_empty());
+    }
+}
@@ -0,0 +1,244 @@
+use std::collections::VecDeque;
+use amethyst::ecs::System;
+use amethyst::shred::{Fetch, FetchMut};
+use amethyst::shrev::{EventChannel, ReaderId};
+use amethyst::prelude::World;
+use amethyst::winit::WindowEvent;
+use serde_json;
+use super::{UiMessage, UiOutbox};
+
+// Text composition for input methods (Japanese, Chinese, Korean and so on).
+//
+// Servo has no composition events in its embedding API, and winit 0.10 doesn't report preedit
+// text, only the committed characters. So composition is done in the page by `16.game-ime.js`,
+// from ImeEvents written by whatever talks to the platform input method. The caret position the
+// page reports is kept in ImeCaret, and ServoUiSystem moves the window's candidate window there.
+//
+// The platform usually delivers committed text as ReceivedCharacter events as well, which would
+// type it into the page a second time. ImeComposition tracks the composition so ServoUiSystem can
+// drop those characters.
+
+// Frames of ServoUiSystem for which committed text is expected as ReceivedCharacter events
+const COMMIT_FRAMES: u32 = 2;
+
+/// Composition input from the platform input method, written to `EventChannel<ImeEvent>`.
+///
+/// Nothing in this crate writes these, since winit has no way to get them. Without them input
+/// methods still work, committing text straight into the page as typed characters, but pages see
+/// no composition events. Games wanting inline composition write them from their own binding to
+/// the platform input method.
+///
+/// While a composition started by a Preedit is active, ReceivedCharacter events don't reach the
+/// page, and the characters of a Commit are dropped if they also arrive as ReceivedCharacter
+/// events within the next two frames. A Commit without a Preedit whose characters arrive before
+/// ImeSystem has read it is still typed twice, so games should start compositions with a Preedit.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ImeEvent {
+    /// Text being composed, replacing any previous preedit text, with the cursor position in
+    /// characters.
+    Preedit { text: String, cursor: Option<usize> },
+    /// Finished text, ending the composition if there is one.
+    Commit(String),
+    /// Drops the composition without inserting anything.
+    Cancel,
+}
+
+/// Caret of the page's focused text field, in window pixels from the top left. None while no
+/// text field has focus. ServoUiSystem keeps the platform candidate window at its bottom left.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ImeCaret {
+    pub position: Option<CaretPosition>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+pub struct CaretPosition {
+    pub x: f32,
+    /// Bottom of the caret, where the candidate window goes so it doesn't cover the text.
+    pub y: f32,
+    pub height: f32,
+}
+
+/// Whether a composition is active, and committed text whose ReceivedCharacter events should be
+/// dropped. Kept by ImeSystem and read by ServoUiSystem.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ImeComposition {
+    composing: bool,
+    committed: VecDeque<char>,
+    // ServoUiSystem frames left before unmatched committed text is forgotten
+    frames_left: u32,
+}
+
+impl ImeComposition {
+    pub fn is_composing(&self) -> bool {
+        self.composing
+    }
+
+    pub fn handle(&mut self, event: &ImeEvent) {
+        match event {
+            &ImeEvent::Preedit { .. } => self.composing = true,
+            &ImeEvent::Commit(ref text) => {
+                self.composing = false;
+                self.committed.extend(text.chars());
+                self.frames_left = COMMIT_FRAMES;
+            }
+            &ImeEvent::Cancel => self.composing = false,
+        }
+    }
+
+    /// Ends the composition without expecting any text, for when the page's text field loses
+    /// focus.
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+
+    /// Returns true if the event is a character the input method is typing, which shouldn't reach
+    /// the page.
+    pub fn swallows(&mut self, event: &WindowEvent) -> bool {
+        let ch = match event {
+            &WindowEvent::ReceivedCharacter(ch) => ch,
+            _ => return false,
+        };
+        if self.committed.front() == Some(&ch) {
+            self.committed.pop_front();
+            return true;
+        }
+        self.committed.clear();
+        self.composing
+    }
+
+    /// Forgets committed text which didn't arrive as characters in time, called once a frame.
+    pub fn end_frame(&mut self) {
+        if self.frames_left > 0 {
+            self.frames_left -= 1;
+        } else {
+            self.committed.clear();
+        }
+    }
+}
+
+/// Sends ImeEvents to the current page as `ime.preedit`, `ime.commit` and `ime.cancel`
+/// messages, and keeps ImeCaret up to date from the page's `ime.caret` messages. Also keeps
+/// ImeComposition, ending it when no text field has focus.
+pub struct ImeSystem {
+    ime_reader: ReaderId<ImeEvent>,
+    message_reader: ReaderId<UiMessage>,
+}
+
+impl ImeSystem {
+    pub fn new(world: &mut World) -> Self {
+        Self {
+            ime_reader: world
+                .write_resource::<EventChannel<ImeEvent>>()
+                .register_reader(),
+            message_reader: world
+                .write_resource::<EventChannel<UiMessage>>()
+                .register_reader(),
+        }
+    }
+}
+
+impl<'a> System<'a> for ImeSystem {
+    type SystemData = (
+        Fetch<'a, EventChannel<ImeEvent>>,
+        Fetch<'a, EventChannel<UiMessage>>,
+        FetchMut<'a, ImeCaret>,
+        FetchMut<'a, ImeComposition>,
+        FetchMut<'a, UiOutbox>,
+    );
+
+    fn run(
+        &mut self,
+        (ime_events, messages, mut caret, mut composition, mut outbox): Self::SystemData,
+    ) {
+        for event in ime_events.read(&mut self.ime_reader) {
+            composition.handle(event);
+            match event {
+                &ImeEvent::Preedit {
+                    ref text,
+                    cursor,
+                } => outbox.send_to_current(
+                    "ime.preedit",
+                    json!({ "text": text, "cursor": cursor }),
+                ),
+                &ImeEvent::Commit(ref text) => {
+                    outbox.send_to_current("ime.commit", json!({ "text": text }))
+                }
+                &ImeEvent::Cancel => outbox.send_to_current("ime.cancel", json!({})),
+            }
+        }
+        for message in messages.read(&mut self.message_reader) {
+            if message.name == "ime.caret" {
+                caret.position = serde_json::from_value(message.payload.clone()).ok();
+                if caret.position.is_none() {
+                    composition.reset();
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn character(ch: char) -> WindowEvent {
+        WindowEvent::ReceivedCharacter(ch)
+    }
+
+    fn preedit(text: &str) -> ImeEvent {
+        ImeEvent::Preedit {
+            text: text.to_string(),
+            cursor: None,
+        }
+    }
+
+    #[test]
+    fn characters_pass_without_a_composition() {
+        let mut composition = ImeComposition::default();
+        assert!(!composition.swallows(&character('a')));
+        assert!(!composition.swallows(&WindowEvent::Focused(true)));
+    }
+
+    #[test]
+    fn characters_are_dropped_while_composing() {
+        let mut composition = ImeComposition::default();
+        composition.handle(&preedit("ni"));
+        assert!(composition.swallows(&character('n')));
+        composition.handle(&ImeEvent::Cancel);
+        assert!(!composition.swallows(&character('n')));
+    }
+
+    #[test]
+    fn committed_text_is_dropped_once_when_typed_by_the_platform() {
+        let mut composition = ImeComposition::default();
+        composition.handle(&preedit("にほん"));
+        composition.handle(&ImeEvent::Commit("日本".to_string()));
+        assert!(composition.swallows(&character('日')));
+        composition.end_frame();
+        assert!(composition.swallows(&character('本')));
+        assert!(!composition.swallows(&character('本')));
+    }
+
+    #[test]
+    fn committed_text_is_forgotten_after_a_few_frames_or_other_input() {
+        let mut composition = ImeComposition::default();
+        composition.handle(&ImeEvent::Commit("日本".to_string()));
+        for _ in 0..COMMIT_FRAMES + 1 {
+            composition.end_frame();
+        }
+        assert!(!composition.swallows(&character('日')));
+
+        composition.handle(&ImeEvent::Commit("日本".to_string()));
+        assert!(!composition.swallows(&character('a')));
+        assert!(!composition.swallows(&character('日')));
+    }
+
+    #[test]
+    fn reset_ends_the_composition() {
+        let mut composition = ImeComposition::default();
+        composition.handle(&preedit("ni"));
+        composition.reset();
+        assert!(!composition.is_composing());
+        assert!(!composition.swallows(&character('n')));
+    }
+}
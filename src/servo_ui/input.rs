@@ -0,0 +1,378 @@
+use amethyst::renderer::Event;
+use std::collections::HashSet;
+use amethyst::winit::{ElementState, ModifiersState, TouchPhase, VirtualKeyCode, WindowEvent};
+use super::UiMessage;
+
+/// An event from amethyst's `EventChannel<Event>`, republished by ServoUiSystem along with
+/// whether the UI consumed it.
+///
+/// `EventChannel<Event>` is unfiltered: events can't be taken back out of it, so everything
+/// reading it, amethyst's own input handling and `State::handle_event` included, sees the clicks
+/// and key presses the UI consumed. Game input has to be read from `EventChannel<RoutedEvent>`,
+/// skipping consumed events.
+#[derive(Clone, Debug)]
+pub struct RoutedEvent {
+    pub event: Event,
+    pub ui_consumed: bool,
+    /// Whether the event came from an input replay rather than the window. Replayed events come
+    /// from `recording::replay_device()`.
+    pub replayed: bool,
+}
+
+/// A key press sent to the focused page which the page didn't handle, published on
+/// `EventChannel<UnhandledKey>` so game hotkeys keep working while a page has focus. Keys which
+/// pages may not swallow are published as soon as they are pressed, without a character.
+///
+/// Pages handle a key by calling `preventDefault` on it, and text fields handle the keys typed
+/// into them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct UnhandledKey {
+    pub key: VirtualKeyCode,
+    /// Character the key typed, if any.
+    pub character: Option<char>,
+    pub modifiers: ModifiersState,
+}
+
+/// Which keys the focused page may keep from the game by handling them.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SwallowableKeys {
+    All,
+    /// Only these keys, every other key reaches the game whatever the page does with it.
+    Only(HashSet<VirtualKeyCode>),
+    /// Every key except these.
+    AllExcept(HashSet<VirtualKeyCode>),
+}
+
+impl Default for SwallowableKeys {
+    fn default() -> Self {
+        SwallowableKeys::All
+    }
+}
+
+impl SwallowableKeys {
+    pub fn may_swallow(&self, key: VirtualKeyCode) -> bool {
+        match *self {
+            SwallowableKeys::All => true,
+            SwallowableKeys::Only(ref keys) => keys.contains(&key),
+            SwallowableKeys::AllExcept(ref keys) => !keys.contains(&key),
+        }
+    }
+}
+
+/// Where an input event is delivered.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Route {
+    /// Sent to Servo and consumed, the game should ignore it.
+    Ui,
+    /// Not sent to Servo.
+    Game,
+    /// Sent to Servo and left for the game as well, for events like cursor moves and resizes
+    /// which both need to see.
+    Both,
+}
+
+/// Decides per event whether the UI or the game consumes it.
+///
+/// Buttons and wheel events go to the UI when the composited page is opaque enough under the
+/// cursor and the page reports an element there which accepts pointer events, so transparent HUD
+/// areas and elements with `pointer-events: none` let clicks through to the game. Once the UI
+/// takes a button press it keeps the pointer until every button is released. Keyboard events go to
+/// the UI while the page has an element focused. Each finger is routed like a button, by where it
+/// first touches.
+///
+/// Pages report the element under the pointer and their focus with `input.pointer` and
+/// `input.focus` messages, sent by `13.game-input.js`.
+pub struct InputRouter {
+    alpha_threshold: u8,
+    cursor: (f64, f64),
+    // Whether the page's element under the pointer accepts pointer events, as last reported
+    page_pointer: bool,
+    // Whether the page has an element other than the body focused, as last reported
+    page_focus: bool,
+    // Buttons pressed over the UI and not yet released
+    captured: usize,
+    // Fingers which touched down over the UI and haven't lifted
+    touches: HashSet<u64>,
+    // Set when a click went to the game while the page had focus, so the page should blur
+    blur_requested: bool,
+}
+
+impl InputRouter {
+    /// Pixels whose alpha is below `alpha_threshold` let pointer events through to the game.
+    pub fn new(alpha_threshold: u8) -> Self {
+        Self {
+            alpha_threshold: alpha_threshold,
+            cursor: (0., 0.),
+            page_pointer: true,
+            page_focus: false,
+            captured: 0,
+            touches: HashSet::new(),
+            blur_requested: false,
+        }
+    }
+
+    /// Updates what the router knows about the page from its messages.
+    pub fn handle_message(&mut self, message: &UiMessage) {
+        match message.name.as_str() {
+            "input.pointer" => {
+                self.page_pointer = message.payload["ui"].as_bool().unwrap_or(true);
+            }
+            "input.focus" => {
+                self.page_focus = message.payload["focused"].as_bool().unwrap_or(false);
+            }
+            "page.loaded" => {
+                self.page_pointer = true;
+                self.page_focus = false;
+            }
+            _ => {}
+        }
+    }
+
+    /// Routes one window event. `alpha_at` returns the alpha of the composited UI at a window
+    /// position, or None if nothing has been composited yet.
+    pub fn route<F>(&mut self, event: &WindowEvent, alpha_at: F) -> Route
+    where
+        F: Fn(u32, u32) -> Option<u8>,
+    {
+        match event {
+            &WindowEvent::CursorMoved { position, .. } => {
+                self.cursor = position;
+                if self.captured > 0 {
+                    Route::Ui
+                } else {
+                    Route::Both
+                }
+            }
+            &WindowEvent::MouseInput { state, .. } => match state {
+                ElementState::Pressed => {
+                    let cursor = self.cursor;
+                    if self.captured > 0 || self.hit(cursor, alpha_at) {
+                        self.captured += 1;
+                        Route::Ui
+                    } else {
+                        self.blur_requested |= self.page_focus;
+                        Route::Game
+                    }
+                }
+                ElementState::Released => {
+                    if self.captured > 0 {
+                        self.captured -= 1;
+                        Route::Ui
+                    } else {
+                        Route::Game
+                    }
+                }
+            },
+            &WindowEvent::MouseWheel { .. } => {
+                let cursor = self.cursor;
+                if self.captured > 0 || self.hit(cursor, alpha_at) {
+                    Route::Ui
+                } else {
+                    Route::Game
+                }
+            }
+            &WindowEvent::KeyboardInput { .. } | &WindowEvent::ReceivedCharacter(_) => {
+                if self.page_focus {
+                    Route::Ui
+                } else {
+                    Route::Game
+                }
+            }
+            &WindowEvent::Touch(ref touch) => match touch.phase {
+                TouchPhase::Started => {
+                    if self.hit(touch.location, alpha_at) {
+                        self.touches.insert(touch.id);
+                        Route::Ui
+                    } else {
+                        self.blur_requested |= self.page_focus;
+                        Route::Game
+                    }
+                }
+                TouchPhase::Moved => {
+                    if self.touches.contains(&touch.id) {
+                        Route::Ui
+                    } else {
+                        Route::Game
+                    }
+                }
+                TouchPhase::Ended | TouchPhase::Cancelled => {
+                    if self.touches.remove(&touch.id) {
+                        Route::Ui
+                    } else {
+                        Route::Game
+                    }
+                }
+            },
+            &WindowEvent::Focused(false) => {
+                // Releases aren't reported once the window has lost focus
+                self.captured = 0;
+                self.touches.clear();
+                Route::Both
+            }
+            _ => Route::Both,
+        }
+    }
+
+    /// Returns true once after a click went to the game while the page had focus.
+    pub fn take_blur_request(&mut self) -> bool {
+        let requested = self.blur_requested;
+        self.blur_requested = false;
+        if requested {
+            self.page_focus = false;
+        }
+        requested
+    }
+
+    fn hit<F>(&self, (x, y): (f64, f64), alpha_at: F) -> bool
+    where
+        F: Fn(u32, u32) -> Option<u8>,
+    {
+        if !self.page_pointer {
+            return false;
+        }
+        if x < 0. || y < 0. {
+            return false;
+        }
+        match alpha_at(x as u32, y as u32) {
+            Some(alpha) => alpha >= self.alpha_threshold,
+            // Before the first frame is read back there is nothing to test against, so keep
+            // sending input to the page as if it were opaque
+            None => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use amethyst::winit::{MouseButton, MouseScrollDelta, Touch};
+    use serde_json::Value;
+    use super::super::{test_browsers, test_device};
+    use super::*;
+
+    const OPAQUE: Option<u8> = Some(255);
+    const CLEAR: Option<u8> = Some(0);
+
+    fn moved(x: f64, y: f64) -> WindowEvent {
+        WindowEvent::CursorMoved {
+            device_id: test_device(),
+            position: (x, y),
+            modifiers: ModifiersState::default(),
+        }
+    }
+
+    fn button(state: ElementState) -> WindowEvent {
+        WindowEvent::MouseInput {
+            device_id: test_device(),
+            state: state,
+            button: MouseButton::Left,
+            modifiers: ModifiersState::default(),
+        }
+    }
+
+    fn wheel() -> WindowEvent {
+        WindowEvent::MouseWheel {
+            device_id: test_device(),
+            delta: MouseScrollDelta::LineDelta(0., 1.),
+            phase: TouchPhase::Moved,
+            modifiers: ModifiersState::default(),
+        }
+    }
+
+    fn touch(id: u64, phase: TouchPhase, x: f64) -> WindowEvent {
+        WindowEvent::Touch(Touch {
+            device_id: test_device(),
+            phase: phase,
+            location: (x, 10.),
+            id: id,
+        })
+    }
+
+    fn message(name: &str, payload: Value) -> UiMessage {
+        UiMessage {
+            browser: test_browsers(1)[0],
+            name: name.to_string(),
+            payload: payload,
+        }
+    }
+
+    #[test]
+    fn pointer_goes_where_the_page_is_opaque() {
+        let mut router = InputRouter::new(16);
+        // Left half opaque, right half transparent
+        let alpha = |x: u32, _: u32| if x < 100 { OPAQUE } else { CLEAR };
+        assert_eq!(router.route(&moved(50., 10.), alpha), Route::Both);
+        assert_eq!(router.route(&button(ElementState::Pressed), alpha), Route::Ui);
+        assert_eq!(router.route(&button(ElementState::Released), alpha), Route::Ui);
+        assert_eq!(router.route(&wheel(), alpha), Route::Ui);
+
+        router.route(&moved(150., 10.), alpha);
+        assert_eq!(router.route(&button(ElementState::Pressed), alpha), Route::Game);
+        assert_eq!(router.route(&button(ElementState::Released), alpha), Route::Game);
+        assert_eq!(router.route(&wheel(), alpha), Route::Game);
+    }
+
+    #[test]
+    fn ui_keeps_the_pointer_until_buttons_are_released() {
+        let mut router = InputRouter::new(16);
+        let alpha = |x: u32, _: u32| if x < 100 { OPAQUE } else { CLEAR };
+        router.route(&moved(50., 10.), alpha);
+        router.route(&button(ElementState::Pressed), alpha);
+        assert_eq!(router.route(&moved(150., 10.), alpha), Route::Ui);
+        assert_eq!(router.route(&button(ElementState::Released), alpha), Route::Ui);
+        assert_eq!(router.route(&moved(160., 10.), alpha), Route::Both);
+
+        router.route(&moved(50., 10.), alpha);
+        router.route(&button(ElementState::Pressed), alpha);
+        router.route(&WindowEvent::Focused(false), alpha);
+        assert_eq!(router.route(&moved(150., 10.), alpha), Route::Both);
+    }
+
+    #[test]
+    fn elements_ignoring_pointer_events_let_clicks_through() {
+        let mut router = InputRouter::new(16);
+        let alpha = |_, _| OPAQUE;
+        router.handle_message(&message("input.pointer", json!({ "ui": false })));
+        assert_eq!(router.route(&button(ElementState::Pressed), alpha), Route::Game);
+        router.handle_message(&message("page.loaded", Value::Null));
+        assert_eq!(router.route(&button(ElementState::Pressed), alpha), Route::Ui);
+    }
+
+    #[test]
+    fn nothing_composited_counts_as_opaque() {
+        let mut router = InputRouter::new(16);
+        assert_eq!(router.route(&button(ElementState::Pressed), |_, _| None), Route::Ui);
+    }
+
+    #[test]
+    fn keys_follow_page_focus() {
+        let mut router = InputRouter::new(16);
+        let alpha = |_, _| CLEAR;
+        assert_eq!(router.route(&WindowEvent::ReceivedCharacter('a'), alpha), Route::Game);
+        router.handle_message(&message("input.focus", json!({ "focused": true })));
+        assert_eq!(router.route(&WindowEvent::ReceivedCharacter('a'), alpha), Route::Ui);
+
+        // Clicking the game takes focus away from the page
+        assert_eq!(router.route(&button(ElementState::Pressed), alpha), Route::Game);
+        assert!(router.take_blur_request());
+        assert!(!router.take_blur_request());
+        assert_eq!(router.route(&WindowEvent::ReceivedCharacter('a'), alpha), Route::Game);
+    }
+
+    #[test]
+    fn fingers_are_routed_by_where_they_touch_down() {
+        let mut router = InputRouter::new(16);
+        let alpha = |x: u32, _: u32| if x < 100 { OPAQUE } else { CLEAR };
+        assert_eq!(router.route(&touch(1, TouchPhase::Started, 50.), alpha), Route::Ui);
+        assert_eq!(router.route(&touch(2, TouchPhase::Started, 150.), alpha), Route::Game);
+        assert_eq!(router.route(&touch(1, TouchPhase::Moved, 150.), alpha), Route::Ui);
+        assert_eq!(router.route(&touch(2, TouchPhase::Moved, 50.), alpha), Route::Game);
+        assert_eq!(router.route(&touch(1, TouchPhase::Ended, 150.), alpha), Route::Ui);
+        assert_eq!(router.route(&touch(1, TouchPhase::Moved, 50.), alpha), Route::Game);
+    }
+
+    #[test]
+    fn resizes_reach_both() {
+        let mut router = InputRouter::new(16);
+        assert_eq!(router.route(&WindowEvent::Resized(800, 600), |_, _| OPAQUE), Route::Both);
+    }
+}
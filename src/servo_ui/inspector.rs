@@ -0,0 +1,191 @@
+use std::collections::BTreeMap;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json::{self, Map, Value};
+use amethyst::core::transform::GlobalTransform;
+use amethyst::ecs::{Component, Entities, EntitiesRes, Entity, Join, ReadStorage, WriteStorage};
+use amethyst::renderer::Camera;
+use amethyst::shred::{Resources, SystemData};
+use super::{RpcRegistry, UiAnchor, UiBindings};
+
+/// Page listing entities and their components, relative to the resources directory. It is opened
+/// in its own browser, in place of the current page, as a panel beside the game.
+pub const INSPECTOR_PAGE: &str = "ui/inspector.html";
+
+struct Inspectable {
+    read: Box<Fn(&Resources) -> Vec<(Entity, Value)> + Send + Sync>,
+    write: Option<Box<Fn(&Resources, Entity, Value) -> Result<(), String> + Send + Sync>>,
+}
+
+/// Component types shown on the inspector page, observable by pages as `inspector`.
+pub struct Inspector {
+    components: BTreeMap<String, Inspectable>,
+}
+
+impl Inspector {
+    pub fn new() -> Self {
+        Self {
+            components: BTreeMap::new(),
+        }
+    }
+
+    /// Shows `T` under `name`, serialized with serde, and lets the page edit it.
+    pub fn inspect<T>(&mut self, name: &str)
+    where
+        T: Component + Serialize + DeserializeOwned,
+    {
+        self.inspect_with(
+            name,
+            |component: &T| {
+                serde_json::to_value(component)
+                    .unwrap_or_else(|e| Value::String(format!("<{}>", e)))
+            },
+            |value| serde_json::from_value(value).map_err(|e| e.to_string()),
+        );
+    }
+
+    /// Shows `T` under `name` using the given conversions, for components which don't
+    /// implement serde's traits. The page can edit it if `from_json` accepts its input.
+    pub fn inspect_with<T, R, W>(&mut self, name: &str, to_json: R, from_json: W)
+    where
+        T: Component,
+        R: Fn(&T) -> Value + Send + Sync + 'static,
+        W: Fn(Value) -> Result<T, String> + Send + Sync + 'static,
+    {
+        self.components.insert(
+            name.to_string(),
+            Inspectable {
+                read: Box::new(move |res| read_components(res, &to_json)),
+                write: Some(Box::new(move |res, entity, value| {
+                    let component = from_json(value)?;
+                    WriteStorage::<T>::fetch(res, 0).insert(entity, component);
+                    Ok(())
+                })),
+            },
+        );
+    }
+
+    /// Shows which entities have a `T` under `name`, without its contents.
+    pub fn inspect_opaque<T>(&mut self, name: &str)
+    where
+        T: Component,
+    {
+        self.components.insert(
+            name.to_string(),
+            Inspectable {
+                // Not null, since merge patches would drop a null member
+                read: Box::new(|res| read_components(res, |_: &T| Value::Bool(true))),
+                write: None,
+            },
+        );
+    }
+
+    /// All living entities with their inspected components, and which components are editable.
+    pub fn snapshot(&self, res: &Resources) -> Value {
+        let mut entities: BTreeMap<u32, Map<String, Value>> = (&*res.fetch::<EntitiesRes>(0))
+            .join()
+            .map(|entity| (entity.id(), Map::new()))
+            .collect();
+        for (name, inspectable) in self.components.iter() {
+            for (entity, value) in (inspectable.read)(res) {
+                if let Some(components) = entities.get_mut(&entity.id()) {
+                    components.insert(name.clone(), value);
+                }
+            }
+        }
+        let components: Map<String, Value> = self.components
+            .iter()
+            .map(|(name, inspectable)| {
+                (
+                    name.clone(),
+                    json!({ "editable": inspectable.write.is_some() }),
+                )
+            })
+            .collect();
+        let entities: Map<String, Value> = entities
+            .into_iter()
+            .map(|(id, components)| (id.to_string(), Value::Object(components)))
+            .collect();
+        json!({
+            "components": components,
+            "entities": entities,
+        })
+    }
+
+    /// Replaces (or adds) a component on a living entity from its JSON form.
+    pub fn edit(
+        &self,
+        res: &Resources,
+        entity: u32,
+        component: &str,
+        value: Value,
+    ) -> Result<(), String> {
+        let write = match self.components.get(component) {
+            Some(&Inspectable {
+                write: Some(ref write),
+                ..
+            }) => write,
+            Some(_) => return Err(format!("'{}' can't be edited", component)),
+            None => return Err(format!("Unknown component '{}'", component)),
+        };
+        let entity = {
+            let entities = res.fetch::<EntitiesRes>(0);
+            let entity = entities.entity(entity);
+            if !entities.is_alive(entity) {
+                return Err(format!("Entity {} is not alive", entity.id()));
+            }
+            entity
+        };
+        write(res, entity, value)
+    }
+}
+
+fn read_components<T, F>(res: &Resources, to_json: F) -> Vec<(Entity, Value)>
+where
+    T: Component,
+    F: Fn(&T) -> Value,
+{
+    let (entities, storage) = <(Entities, ReadStorage<T>)>::fetch(res, 0);
+    (&*entities, &storage)
+        .join()
+        .map(|(entity, component)| (entity, to_json(component)))
+        .collect()
+}
+
+#[derive(Deserialize)]
+struct Edit {
+    entity: u32,
+    component: String,
+    value: Value,
+}
+
+/// Registers the `inspector` binding, the components of this crate and amethyst which the
+/// inspector shows by default and, in debug builds only, the `inspector.edit` call.
+pub fn register_inspector(
+    inspector: &mut Inspector,
+    bindings: &mut UiBindings,
+    rpc: &mut RpcRegistry,
+) {
+    inspector.inspect_with(
+        "GlobalTransform",
+        |transform: &GlobalTransform| json!(transform.0),
+        |value| {
+            serde_json::from_value(value)
+                .map(GlobalTransform)
+                .map_err(|e| e.to_string())
+        },
+    );
+    inspector.inspect_opaque::<Camera>("Camera");
+    inspector.inspect_opaque::<UiAnchor>("UiAnchor");
+
+    bindings.observe("inspector", |res| {
+        res.fetch::<Inspector>(0).snapshot(res)
+    });
+    // Any page could call this, so release builds don't let pages rewrite components
+    if cfg!(debug_assertions) {
+        rpc.register("inspector.edit", |res, edit: Edit| {
+            res.fetch::<Inspector>(0)
+                .edit(res, edit.entity, &edit.component, edit.value)
+        });
+    }
+}
@@ -0,0 +1,207 @@
+extern crate glutin;
+extern crate servo as libservo;
+
+use self::glutin::{ElementState, ModifiersState, VirtualKeyCode};
+use self::libservo::msg::constellation_msg::{self, Key, KeyState};
+
+// Every key ServoUiSystem deals with, as its winit virtual key code and Servo's equivalent. The
+// name of a key, as written in input recordings and hotkeys, is its virtual key code variant, such
+// as `A`, `Key1`, `F3` or `Escape`.
+macro_rules! key_table {
+    ($($code:ident => $key:ident,)*) => {
+        /// Servo's key for a winit virtual key code, None for keys Servo has no equivalent of.
+        pub fn servo_key(code: VirtualKeyCode) -> Option<Key> {
+            match code {
+                $(VirtualKeyCode::$code => Some(Key::$key),)*
+                _ => None,
+            }
+        }
+
+        /// The winit virtual key code for one of Servo's keys, the reverse of `servo_key`.
+        pub fn virtual_key(key: Key) -> Option<VirtualKeyCode> {
+            match key {
+                $(Key::$key => Some(VirtualKeyCode::$code),)*
+                _ => None,
+            }
+        }
+
+        pub fn key_name(code: VirtualKeyCode) -> Option<&'static str> {
+            match code {
+                $(VirtualKeyCode::$code => Some(stringify!($code)),)*
+                _ => None,
+            }
+        }
+
+        /// The key with a name from `key_name`, ignoring case.
+        pub fn key_from_name(name: &str) -> Option<VirtualKeyCode> {
+            $(if name.eq_ignore_ascii_case(stringify!($code)) {
+                return Some(VirtualKeyCode::$code);
+            })*
+            None
+        }
+    };
+}
+
+key_table! {
+    A => A,
+    B => B,
+    C => C,
+    D => D,
+    E => E,
+    F => F,
+    G => G,
+    H => H,
+    I => I,
+    J => J,
+    K => K,
+    L => L,
+    M => M,
+    N => N,
+    O => O,
+    P => P,
+    Q => Q,
+    R => R,
+    S => S,
+    T => T,
+    U => U,
+    V => V,
+    W => W,
+    X => X,
+    Y => Y,
+    Z => Z,
+    Key0 => Num0,
+    Key1 => Num1,
+    Key2 => Num2,
+    Key3 => Num3,
+    Key4 => Num4,
+    Key5 => Num5,
+    Key6 => Num6,
+    Key7 => Num7,
+    Key8 => Num8,
+    Key9 => Num9,
+    Numpad0 => Kp0,
+    Numpad1 => Kp1,
+    Numpad2 => Kp2,
+    Numpad3 => Kp3,
+    Numpad4 => Kp4,
+    Numpad5 => Kp5,
+    Numpad6 => Kp6,
+    Numpad7 => Kp7,
+    Numpad8 => Kp8,
+    Numpad9 => Kp9,
+    Decimal => KpDecimal,
+    Divide => KpDivide,
+    Multiply => KpMultiply,
+    Subtract => KpSubtract,
+    Add => KpAdd,
+    NumpadEnter => KpEnter,
+    NumpadEquals => KpEqual,
+    F1 => F1,
+    F2 => F2,
+    F3 => F3,
+    F4 => F4,
+    F5 => F5,
+    F6 => F6,
+    F7 => F7,
+    F8 => F8,
+    F9 => F9,
+    F10 => F10,
+    F11 => F11,
+    F12 => F12,
+    F13 => F13,
+    F14 => F14,
+    F15 => F15,
+    Escape => Escape,
+    Return => Enter,
+    Tab => Tab,
+    Back => Backspace,
+    Insert => Insert,
+    Delete => Delete,
+    Left => Left,
+    Right => Right,
+    Up => Up,
+    Down => Down,
+    PageUp => PageUp,
+    PageDown => PageDown,
+    Home => Home,
+    End => End,
+    Capital => CapsLock,
+    Scroll => ScrollLock,
+    Numlock => NumLock,
+    Snapshot => PrintScreen,
+    Pause => Pause,
+    Space => Space,
+    Apostrophe => Apostrophe,
+    Comma => Comma,
+    Minus => Minus,
+    Period => Period,
+    Slash => Slash,
+    Semicolon => Semicolon,
+    Equals => Equal,
+    LBracket => LeftBracket,
+    Backslash => Backslash,
+    RBracket => RightBracket,
+    Grave => GraveAccent,
+    LShift => LeftShift,
+    LControl => LeftControl,
+    LAlt => LeftAlt,
+    LWin => LeftSuper,
+    RShift => RightShift,
+    RControl => RightControl,
+    RAlt => RightAlt,
+    RWin => RightSuper,
+    Apps => Menu,
+    NavigateBackward => NavigateBackward,
+    NavigateForward => NavigateForward,
+}
+
+/// Whether pressing the key normally produces a character, in which case the key event is sent
+/// to Servo along with the character once winit reports it.
+pub fn is_printable(key: Key) -> bool {
+    match key {
+        Key::Escape | Key::Enter | Key::Tab | Key::Backspace | Key::Insert | Key::Delete
+        | Key::Left | Key::Right | Key::Up | Key::Down | Key::PageUp | Key::PageDown
+        | Key::Home | Key::End | Key::CapsLock | Key::ScrollLock | Key::NumLock
+        | Key::PrintScreen | Key::Pause | Key::F1 | Key::F2 | Key::F3 | Key::F4 | Key::F5
+        | Key::F6 | Key::F7 | Key::F8 | Key::F9 | Key::F10 | Key::F11 | Key::F12 | Key::F13
+        | Key::F14 | Key::F15 | Key::KpEnter | Key::LeftShift | Key::LeftControl
+        | Key::LeftAlt | Key::LeftSuper | Key::RightShift | Key::RightControl | Key::RightAlt
+        | Key::RightSuper | Key::Menu | Key::NavigateBackward | Key::NavigateForward => false,
+        _ => true,
+    }
+}
+
+pub fn servo_modifiers(modifiers: ModifiersState) -> constellation_msg::KeyModifiers {
+    let mut result = constellation_msg::KeyModifiers::empty();
+    if modifiers.shift {
+        result.insert(constellation_msg::SHIFT);
+    }
+    if modifiers.ctrl {
+        result.insert(constellation_msg::CONTROL);
+    }
+    if modifiers.alt {
+        result.insert(constellation_msg::ALT);
+    }
+    if modifiers.logo {
+        result.insert(constellation_msg::SUPER);
+    }
+    result
+}
+
+/// The reverse of `servo_modifiers`.
+pub fn winit_modifiers(modifiers: constellation_msg::KeyModifiers) -> ModifiersState {
+    ModifiersState {
+        shift: modifiers.contains(constellation_msg::SHIFT),
+        ctrl: modifiers.contains(constellation_msg::CONTROL),
+        alt: modifiers.contains(constellation_msg::ALT),
+        logo: modifiers.contains(constellation_msg::SUPER),
+    }
+}
+
+pub fn servo_key_state(state: ElementState) -> KeyState {
+    match state {
+        ElementState::Pressed => KeyState::Pressed,
+        ElementState::Released => KeyState::Released,
+    }
+}
+
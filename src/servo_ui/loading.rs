@@ -0,0 +1,131 @@
+use amethyst::assets::ProgressCounter;
+use amethyst::prelude::*;
+use serde_json::Value;
+use super::{PageKey, RpcRegistry, UiBindings, UiPages};
+
+/// Page shown while a LoadingState waits for its assets, relative to the resources directory.
+pub const LOADING_PAGE: &str = "ui/loading.html";
+
+/// Progress of the assets a LoadingState is waiting on, observable by pages as `loading`.
+pub struct LoadingProgress {
+    pub counter: Option<ProgressCounter>,
+    /// Set by the loading page when the player chooses to carry on despite failed assets.
+    pub continue_requested: bool,
+}
+
+impl LoadingProgress {
+    pub fn new() -> Self {
+        Self {
+            counter: None,
+            continue_requested: false,
+        }
+    }
+
+    /// Totals and errors as sent to the loading page.
+    pub fn snapshot(&self) -> Value {
+        match self.counter {
+            Some(ref counter) => {
+                let errors: Vec<Value> = counter
+                    .errors()
+                    .iter()
+                    .map(|error| {
+                        json!({
+                            "asset": error.asset_name,
+                            "error": error.error.to_string(),
+                        })
+                    })
+                    .collect();
+                json!({
+                    "active": true,
+                    "total": counter.num_assets(),
+                    "loaded": counter.num_finished(),
+                    "loading": counter.num_loading(),
+                    "failed": counter.num_failed(),
+                    "complete": counter.is_complete(),
+                    "errors": errors,
+                })
+            }
+            None => json!({ "active": false }),
+        }
+    }
+}
+
+/// Registers the `loading` binding streamed to the loading page and the `loading.continue` call
+/// it makes when assets failed.
+pub fn register_loading(bindings: &mut UiBindings, rpc: &mut RpcRegistry) {
+    bindings.observe("loading", |res| res.fetch::<LoadingProgress>(0).snapshot());
+    rpc.register("loading.continue", |res, _: ()| {
+        res.fetch_mut::<LoadingProgress>(0).continue_requested = true;
+        Ok(())
+    });
+}
+
+/// Shows the loading page while the assets started by `load` are loading, then switches to
+/// `next`, whose own page takes over.
+///
+/// If any asset fails the failures are listed on the loading page, and the switch waits until
+/// the player chooses to continue.
+pub struct LoadingState<F>
+where
+    F: FnOnce(&mut World, &mut ProgressCounter),
+{
+    load: Option<F>,
+    next: Option<Box<State>>,
+    page: PageKey,
+}
+
+impl<F> LoadingState<F>
+where
+    F: FnOnce(&mut World, &mut ProgressCounter),
+{
+    pub fn new(load: F, next: Box<State>) -> Self {
+        Self {
+            load: Some(load),
+            next: Some(next),
+            page: PageKey::new(),
+        }
+    }
+}
+
+impl<F> State for LoadingState<F>
+where
+    F: FnOnce(&mut World, &mut ProgressCounter),
+{
+    fn on_start(&mut self, world: &mut World) {
+        world.write_resource::<UiPages>().show(self.page, LOADING_PAGE);
+        let mut counter = ProgressCounter::new();
+        if let Some(load) = self.load.take() {
+            load(world, &mut counter);
+        }
+        let mut progress = world.write_resource::<LoadingProgress>();
+        progress.counter = Some(counter);
+        progress.continue_requested = false;
+    }
+
+    fn on_stop(&mut self, world: &mut World) {
+        world.write_resource::<UiPages>().close(self.page);
+        let mut progress = world.write_resource::<LoadingProgress>();
+        progress.counter = None;
+        progress.continue_requested = false;
+    }
+
+    fn update(&mut self, world: &mut World) -> Trans {
+        let done = {
+            let progress = world.read_resource::<LoadingProgress>();
+            match progress.counter {
+                Some(ref counter) => {
+                    counter.is_complete()
+                        && (counter.num_failed() == 0 || progress.continue_requested)
+                }
+                None => false,
+            }
+        };
+        match (done, self.next.take()) {
+            (true, Some(next)) => Trans::Switch(next),
+            (_, next) => {
+                self.next = next;
+                Trans::None
+            }
+        }
+    }
+}
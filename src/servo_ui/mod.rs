@@ -1,11 +1,96 @@
+#[cfg(test)]
+extern crate servo as libservo;
+
 pub mod bundle;
+pub mod config;
 pub mod system;
 pub mod pass;
 pub mod handle;
+pub mod gl_state;
+pub mod readback;
+pub mod stats;
+pub mod bridge;
+pub mod rpc;
+pub mod binding;
+pub mod pages;
+pub mod transitions;
+pub mod anchor;
+pub mod loading;
+pub mod keys;
+pub mod console;
+pub mod inspector;
+pub mod profiling;
+pub mod settings;
+pub mod input;
+pub mod navigation;
+pub mod cursor;
+pub mod touch;
+pub mod ime;
+pub mod drag;
+pub mod automation;
+pub mod recording;
+pub mod hotkeys;
 mod window;
 
 pub use self::bundle::ServoUiBundle;
+pub use self::config::{FrameUpload, ServoUiConfig};
 pub use self::system::ServoUiSystem;
 pub use self::pass::ServoUiPass;
 pub use self::handle::ServoHandle;
 pub use self::window::ServoWindow;
+pub use self::gl_state::{GlState, GlStateGuard};
+pub use self::readback::{PixelReadback, UiFrame};
+pub use self::stats::CompositeStats;
+pub use self::bridge::{UiMessage, UiOutbox};
+pub use self::rpc::{RpcRegistry, RpcSystem};
+pub use self::binding::{BindingSystem, UiBindings};
+pub use self::pages::{PageKey, PageRequest, UiPages, UiState};
+pub use self::transitions::{ui_transition, StateRegistry, TransitionRequest, UiTransitions};
+pub use self::anchor::{AnchorElement, AnchorSystem, UiAnchor};
+pub use self::loading::{LoadingProgress, LoadingState, LOADING_PAGE};
+pub use self::console::{CommandRegistry, ConsoleHistory};
+pub use self::inspector::{Inspector, INSPECTOR_PAGE};
+pub use self::profiling::{capture_servo_reports, MemoryReport, PerfStats, ReportParser,
+                          ServoReport, PERF_PAGE};
+pub use self::settings::{DisplaySettings, SettingsChange, SETTINGS_PAGE};
+pub use self::input::{InputRouter, Route, RoutedEvent, SwallowableKeys, UnhandledKey};
+pub use self::navigation::{GamepadAxis, GamepadButton, GamepadEvent, GamepadSource, NavAction,
+                           NavBindings, NavigationSystem, SyntheticGamepad};
+pub use self::cursor::{CursorMode, PointerLock};
+pub use self::touch::TouchTranslator;
+pub use self::ime::{CaretPosition, ImeCaret, ImeComposition, ImeEvent, ImeSystem};
+pub use self::drag::{Drag, DragEvent, DragSession, DragSystem};
+pub use self::automation::{AutomationId, AutomationSystem, UiAutomation, UiDriver, UiEngine};
+pub use self::recording::{InputEvent, InputRecorder, InputReplay, RecordedInput};
+pub use self::hotkeys::{ActionMap, BindingSource, Hotkey, HotkeyBinding, HotkeyConflict,
+                        HotkeySystem, UiAction};
+
+/// Browser ids for tests, which can't get them from a running Servo.
+#[cfg(test)]
+fn test_browsers(count: usize) -> Vec<self::libservo::BrowserId> {
+    use std::cell::Cell;
+    use self::libservo::msg::constellation_msg::{PipelineNamespace, PipelineNamespaceId};
+    // Each test runs on its own thread, which needs its own namespace, installed once
+    thread_local!(static INSTALLED: Cell<bool> = Cell::new(false));
+    INSTALLED.with(|installed| {
+        if !installed.get() {
+            PipelineNamespace::install(PipelineNamespaceId(1));
+            installed.set(true);
+        }
+    });
+    (0..count).map(|_| self::libservo::BrowserId::new()).collect()
+}
+
+/// A device id for events built in tests, the one replayed input comes from.
+#[cfg(test)]
+fn test_device() -> ::amethyst::winit::DeviceId {
+    recording::replay_device()
+}
+
+/// A window id for events built in tests. winit only makes them in its event loop, but they are
+/// plain integers on every platform winit 0.10 supports, so zeroed memory is a valid one. Tests
+/// only compare events and never hand them to winit.
+#[cfg(test)]
+fn test_window() -> ::amethyst::winit::WindowId {
+    unsafe { ::std::mem::zeroed() }
+}
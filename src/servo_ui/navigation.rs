@@ -0,0 +1,415 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use amethyst::ecs::System;
+use amethyst::shred::FetchMut;
+use super::UiOutbox;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum GamepadButton {
+    DPadUp,
+    DPadDown,
+    DPadLeft,
+    DPadRight,
+    /// Bottom face button (A on Xbox, cross on PlayStation).
+    South,
+    /// Right face button (B on Xbox, circle on PlayStation).
+    East,
+    North,
+    West,
+    LeftShoulder,
+    RightShoulder,
+    Start,
+    Select,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum GamepadAxis {
+    LeftStickX,
+    LeftStickY,
+}
+
+/// Input from a gamepad. Stick values are between -1 and 1, with positive Y pointing up.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GamepadEvent {
+    Pressed(GamepadButton),
+    Released(GamepadButton),
+    Axis(GamepadAxis, f32),
+}
+
+/// Where gamepad input comes from, polled once a frame by NavigationSystem.
+///
+/// Implement this over a gamepad library to drive menus with a real controller, or use
+/// SyntheticGamepad to feed input by hand.
+pub trait GamepadSource {
+    /// Events since the last poll, oldest first.
+    fn poll(&mut self) -> Vec<GamepadEvent>;
+}
+
+/// A GamepadSource fed by hand, for tests and scripted input. Clones share one queue, so a clone
+/// kept outside the NavigationSystem can push events to it.
+#[derive(Clone, Default)]
+pub struct SyntheticGamepad {
+    events: Arc<Mutex<Vec<GamepadEvent>>>,
+}
+
+impl SyntheticGamepad {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&self, event: GamepadEvent) {
+        match self.events.lock() {
+            Ok(mut events) => events.push(event),
+            Err(_) => eprintln!("ERROR: Synthetic gamepad lock poisoned, input is lost."),
+        }
+    }
+
+    /// Presses and releases a button.
+    pub fn tap(&self, button: GamepadButton) {
+        self.push(GamepadEvent::Pressed(button));
+        self.push(GamepadEvent::Released(button));
+    }
+}
+
+impl GamepadSource for SyntheticGamepad {
+    fn poll(&mut self) -> Vec<GamepadEvent> {
+        match self.events.lock() {
+            Ok(mut events) => events.drain(..).collect(),
+            Err(_) => vec![],
+        }
+    }
+}
+
+/// What a gamepad input does to the page's focus.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum NavAction {
+    Up,
+    Down,
+    Left,
+    Right,
+    /// Activates the focused element, as if clicked.
+    Confirm,
+    /// Backs out of the current menu.
+    Cancel,
+}
+
+impl NavAction {
+    fn direction(&self) -> Option<&'static str> {
+        match *self {
+            NavAction::Up => Some("up"),
+            NavAction::Down => Some("down"),
+            NavAction::Left => Some("left"),
+            NavAction::Right => Some("right"),
+            NavAction::Confirm | NavAction::Cancel => None,
+        }
+    }
+}
+
+/// Which gamepad buttons perform which navigation actions, and how the stick and held
+/// directions behave.
+#[derive(Clone, Debug)]
+pub struct NavBindings {
+    pub buttons: HashMap<GamepadButton, NavAction>,
+    /// Navigate with the left stick as well as the buttons bound to directions.
+    pub stick: bool,
+    /// How far the stick has to be pushed to count as a direction.
+    pub stick_threshold: f32,
+    /// How long a direction is held before focus starts moving repeatedly.
+    pub repeat_delay: Duration,
+    pub repeat_interval: Duration,
+}
+
+impl Default for NavBindings {
+    fn default() -> Self {
+        let mut buttons = HashMap::new();
+        buttons.insert(GamepadButton::DPadUp, NavAction::Up);
+        buttons.insert(GamepadButton::DPadDown, NavAction::Down);
+        buttons.insert(GamepadButton::DPadLeft, NavAction::Left);
+        buttons.insert(GamepadButton::DPadRight, NavAction::Right);
+        buttons.insert(GamepadButton::South, NavAction::Confirm);
+        buttons.insert(GamepadButton::East, NavAction::Cancel);
+        Self {
+            buttons: buttons,
+            stick: true,
+            stick_threshold: 0.5,
+            repeat_delay: Duration::from_millis(400),
+            repeat_interval: Duration::from_millis(120),
+        }
+    }
+}
+
+/// Turns gamepad input into focus navigation on the current page.
+///
+/// Sends `nav.move` with a direction, `nav.confirm` and `nav.cancel` messages, which
+/// `14.game-navigation.js` turns into moving focus to the nearest focusable element that way,
+/// clicking the focused element and backing out of the menu. Directions held on the d-pad or
+/// stick repeat after `repeat_delay`.
+pub struct NavigationSystem {
+    source: Box<GamepadSource>,
+    bindings: NavBindings,
+    held: HashSet<GamepadButton>,
+    stick: (f32, f32),
+    // Direction currently held, when it was first held and when it last moved focus
+    repeating: Option<(NavAction, Instant, Instant)>,
+}
+
+impl NavigationSystem {
+    pub fn new(source: Box<GamepadSource>, bindings: NavBindings) -> Self {
+        Self {
+            source: source,
+            bindings: bindings,
+            held: HashSet::new(),
+            stick: (0., 0.),
+            repeating: None,
+        }
+    }
+
+    /// The direction held on bound buttons, preferring the one already repeating, or on the
+    /// stick if no button is held.
+    fn held_direction(&self) -> Option<NavAction> {
+        let held: Vec<NavAction> = self.held
+            .iter()
+            .filter_map(|button| self.bindings.buttons.get(button))
+            .filter(|action| action.direction().is_some())
+            .cloned()
+            .collect();
+        let button = match self.repeating {
+            Some((action, _, _)) if held.contains(&action) => Some(action),
+            _ => held.first().cloned(),
+        };
+        if button.is_some() || !self.bindings.stick {
+            return button;
+        }
+        let (x, y) = self.stick;
+        let threshold = self.bindings.stick_threshold;
+        if x.abs() < threshold && y.abs() < threshold {
+            None
+        } else if x.abs() > y.abs() {
+            Some(if x > 0. {
+                NavAction::Right
+            } else {
+                NavAction::Left
+            })
+        } else {
+            Some(if y > 0. { NavAction::Up } else { NavAction::Down })
+        }
+    }
+
+    /// Handles the input polled since the last frame, and repeats held directions which are due
+    /// at `now`.
+    fn update(&mut self, outbox: &mut UiOutbox, now: Instant) {
+        for event in self.source.poll() {
+            match event {
+                GamepadEvent::Pressed(button) => {
+                    if !self.held.insert(button) {
+                        continue;
+                    }
+                    // Buttons act on press, so a press released within the same poll still
+                    // moves focus, and held directions only have to be tracked for repeating
+                    if let Some(&action) = self.bindings.buttons.get(&button) {
+                        send_action(outbox, action);
+                        if action.direction().is_some() {
+                            self.repeating = Some((action, now, now));
+                        }
+                    }
+                }
+                GamepadEvent::Released(button) => {
+                    self.held.remove(&button);
+                }
+                GamepadEvent::Axis(GamepadAxis::LeftStickX, value) => self.stick.0 = value,
+                GamepadEvent::Axis(GamepadAxis::LeftStickY, value) => self.stick.1 = value,
+            }
+        }
+
+        self.repeating = match (self.held_direction(), self.repeating) {
+            (None, _) => None,
+            (Some(action), Some((held, since, last))) if held == action => {
+                let repeat_at = if last == since {
+                    since + self.bindings.repeat_delay
+                } else {
+                    last + self.bindings.repeat_interval
+                };
+                if now >= repeat_at {
+                    send_action(outbox, action);
+                    Some((action, since, now))
+                } else {
+                    Some((action, since, last))
+                }
+            }
+            (Some(action), _) => {
+                send_action(outbox, action);
+                Some((action, now, now))
+            }
+        };
+    }
+}
+
+fn send_action(outbox: &mut UiOutbox, action: NavAction) {
+    match action.direction() {
+        Some(direction) => outbox.send_to_current("nav.move", json!({ "direction": direction })),
+        None if action == NavAction::Confirm => outbox.send_to_current("nav.confirm", json!({})),
+        None => outbox.send_to_current("nav.cancel", json!({})),
+    }
+}
+
+impl<'a> System<'a> for NavigationSystem {
+    type SystemData = FetchMut<'a, UiOutbox>;
+
+    fn run(&mut self, mut outbox: Self::SystemData) {
+        self.update(&mut outbox, Instant::now());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn system() -> (NavigationSystem, SyntheticGamepad) {
+        let gamepad = SyntheticGamepad::new();
+        let system = NavigationSystem::new(Box::new(gamepad.clone()), NavBindings::default());
+        (system, gamepad)
+    }
+
+    /// Names of the messages sent, with the direction for moves.
+    fn sent(outbox: &mut UiOutbox) -> Vec<String> {
+        outbox
+            .drain()
+            .into_iter()
+            .map(|(_, name, payload)| match payload["direction"].as_str() {
+                Some(direction) => format!("{} {}", name, direction),
+                None => name,
+            })
+            .collect()
+    }
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn buttons_confirm_and_cancel_once_per_press() {
+        let (mut system, gamepad) = system();
+        let mut outbox = UiOutbox::new();
+        let now = Instant::now();
+        gamepad.tap(GamepadButton::South);
+        gamepad.push(GamepadEvent::Pressed(GamepadButton::East));
+        gamepad.push(GamepadEvent::Pressed(GamepadButton::East));
+        system.update(&mut outbox, now);
+        assert_eq!(sent(&mut outbox), vec!["nav.confirm", "nav.cancel"]);
+        system.update(&mut outbox, now + ms(1000));
+        assert!(sent(&mut outbox).is_empty());
+    }
+
+    #[test]
+    fn tapped_direction_moves_once() {
+        let (mut system, gamepad) = system();
+        let mut outbox = UiOutbox::new();
+        let start = Instant::now();
+        gamepad.tap(GamepadButton::DPadRight);
+        system.update(&mut outbox, start);
+        assert_eq!(sent(&mut outbox), vec!["nav.move right"]);
+        system.update(&mut outbox, start + ms(1000));
+        assert!(sent(&mut outbox).is_empty());
+
+        gamepad.tap(GamepadButton::DPadLeft);
+        gamepad.tap(GamepadButton::DPadLeft);
+        system.update(&mut outbox, start + ms(1001));
+        assert_eq!(sent(&mut outbox), vec!["nav.move left", "nav.move left"]);
+    }
+
+    #[test]
+    fn pressing_a_second_direction_repeats_only_that_one() {
+        let (mut system, gamepad) = system();
+        let mut outbox = UiOutbox::new();
+        let start = Instant::now();
+        gamepad.push(GamepadEvent::Pressed(GamepadButton::DPadLeft));
+        system.update(&mut outbox, start);
+        gamepad.push(GamepadEvent::Pressed(GamepadButton::DPadUp));
+        system.update(&mut outbox, start + ms(100));
+        assert_eq!(sent(&mut outbox), vec!["nav.move left", "nav.move up"]);
+        system.update(&mut outbox, start + ms(500));
+        assert_eq!(sent(&mut outbox), vec!["nav.move up"]);
+    }
+
+    #[test]
+    fn held_direction_repeats_after_delay_then_at_interval() {
+        let (mut system, gamepad) = system();
+        let mut outbox = UiOutbox::new();
+        let start = Instant::now();
+        gamepad.push(GamepadEvent::Pressed(GamepadButton::DPadDown));
+        system.update(&mut outbox, start);
+        assert_eq!(sent(&mut outbox), vec!["nav.move down"]);
+        system.update(&mut outbox, start + ms(399));
+        assert!(sent(&mut outbox).is_empty());
+        system.update(&mut outbox, start + ms(400));
+        assert_eq!(sent(&mut outbox), vec!["nav.move down"]);
+        system.update(&mut outbox, start + ms(519));
+        assert!(sent(&mut outbox).is_empty());
+        system.update(&mut outbox, start + ms(520));
+        assert_eq!(sent(&mut outbox), vec!["nav.move down"]);
+
+        gamepad.push(GamepadEvent::Released(GamepadButton::DPadDown));
+        system.update(&mut outbox, start + ms(2000));
+        assert!(sent(&mut outbox).is_empty());
+    }
+
+    #[test]
+    fn changing_direction_moves_at_once_and_restarts_the_delay() {
+        let (mut system, gamepad) = system();
+        let mut outbox = UiOutbox::new();
+        let start = Instant::now();
+        gamepad.push(GamepadEvent::Pressed(GamepadButton::DPadLeft));
+        system.update(&mut outbox, start);
+        gamepad.push(GamepadEvent::Released(GamepadButton::DPadLeft));
+        gamepad.push(GamepadEvent::Pressed(GamepadButton::DPadUp));
+        system.update(&mut outbox, start + ms(300));
+        assert_eq!(sent(&mut outbox), vec!["nav.move left", "nav.move up"]);
+        system.update(&mut outbox, start + ms(600));
+        assert!(sent(&mut outbox).is_empty());
+        system.update(&mut outbox, start + ms(700));
+        assert_eq!(sent(&mut outbox), vec!["nav.move up"]);
+    }
+
+    #[test]
+    fn stick_picks_its_strongest_axis_past_the_threshold() {
+        let (mut system, gamepad) = system();
+        let mut outbox = UiOutbox::new();
+        let now = Instant::now();
+        gamepad.push(GamepadEvent::Axis(GamepadAxis::LeftStickX, 0.4));
+        system.update(&mut outbox, now);
+        assert!(sent(&mut outbox).is_empty());
+
+        gamepad.push(GamepadEvent::Axis(GamepadAxis::LeftStickX, 0.8));
+        system.update(&mut outbox, now);
+        assert_eq!(sent(&mut outbox), vec!["nav.move right"]);
+
+        gamepad.push(GamepadEvent::Axis(GamepadAxis::LeftStickY, -0.9));
+        system.update(&mut outbox, now);
+        assert_eq!(sent(&mut outbox), vec!["nav.move down"]);
+    }
+
+    #[test]
+    fn held_buttons_win_over_the_stick() {
+        let (mut system, gamepad) = system();
+        let mut outbox = UiOutbox::new();
+        gamepad.push(GamepadEvent::Axis(GamepadAxis::LeftStickX, -1.0));
+        gamepad.push(GamepadEvent::Pressed(GamepadButton::DPadRight));
+        system.update(&mut outbox, Instant::now());
+        assert_eq!(sent(&mut outbox), vec!["nav.move right"]);
+    }
+
+    #[test]
+    fn stick_is_ignored_when_disabled() {
+        let gamepad = SyntheticGamepad::new();
+        let bindings = NavBindings {
+            stick: false,
+            ..NavBindings::default()
+        };
+        let mut system = NavigationSystem::new(Box::new(gamepad.clone()), bindings);
+        let mut outbox = UiOutbox::new();
+        gamepad.push(GamepadEvent::Axis(GamepadAxis::LeftStickY, 1.0));
+        system.update(&mut outbox, Instant::now());
+        assert!(sent(&mut outbox).is_empty());
+    }
+}
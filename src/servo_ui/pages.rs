@@ -0,0 +1,200 @@
+extern crate servo as libservo;
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+use amethyst::prelude::*;
+use amethyst::renderer::Event;
+use self::libservo::BrowserId;
+use super::CursorMode;
+
+static NEXT_PAGE_KEY: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// Identifies one page owned by a state. Two states showing the same URL get separate browsers,
+/// so each keeps its own document.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct PageKey(usize);
+
+impl PageKey {
+    pub fn new() -> Self {
+        PageKey(NEXT_PAGE_KEY.fetch_add(1, Ordering::SeqCst))
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum PageRequest {
+    /// Shows the page, opening a browser for it first if it has none.
+    Show(PageKey, String),
+    /// Stops showing the page but keeps its browser alive, so showing it again is instant.
+    Hide(PageKey),
+    /// Closes the page's browser.
+    Close(PageKey),
+    /// Shows the page Servo was started with.
+    ShowDefault,
+    /// Navigates the page currently shown to a new URL.
+    Navigate(String),
+    /// Reloads the page currently shown.
+    Reload,
+    /// Shows the page over whatever is shown now, or if it is already shown, goes back to what
+    /// was shown before. The page's browser stays alive in between.
+    Toggle(PageKey, String),
+}
+
+/// Pages owned by states, and the requests to show, hide and close them which ServoUiSystem
+/// applies on its next run.
+pub struct UiPages {
+    requests: Vec<PageRequest>,
+    browsers: HashMap<PageKey, BrowserId>,
+    current: Option<BrowserId>,
+}
+
+impl UiPages {
+    pub fn new() -> Self {
+        Self {
+            requests: vec![],
+            browsers: HashMap::new(),
+            current: None,
+        }
+    }
+
+    pub fn show(&mut self, key: PageKey, url: &str) {
+        self.requests.push(PageRequest::Show(key, url.to_string()));
+    }
+
+    pub fn hide(&mut self, key: PageKey) {
+        self.requests.push(PageRequest::Hide(key));
+    }
+
+    pub fn close(&mut self, key: PageKey) {
+        self.requests.push(PageRequest::Close(key));
+    }
+
+    pub fn show_default(&mut self) {
+        self.requests.push(PageRequest::ShowDefault);
+    }
+
+    pub fn toggle(&mut self, key: PageKey, url: &str) {
+        self.requests.push(PageRequest::Toggle(key, url.to_string()));
+    }
+
+    pub fn navigate(&mut self, url: &str) {
+        self.requests.push(PageRequest::Navigate(url.to_string()));
+    }
+
+    pub fn reload(&mut self) {
+        self.requests.push(PageRequest::Reload);
+    }
+
+    /// The browser showing a page, once ServoUiSystem has opened it.
+    pub fn browser(&self, key: PageKey) -> Option<BrowserId> {
+        self.browsers.get(&key).cloned()
+    }
+
+    pub fn drain_requests(&mut self) -> Vec<PageRequest> {
+        self.requests.drain(..).collect()
+    }
+
+    pub fn set_browsers(&mut self, browsers: HashMap<PageKey, BrowserId>) {
+        self.browsers = browsers;
+    }
+
+    /// The browser currently shown, whether a state's page or the start page.
+    pub fn current(&self) -> Option<BrowserId> {
+        self.current
+    }
+
+    pub fn set_current(&mut self, browser: BrowserId) {
+        self.current = Some(browser);
+    }
+}
+
+/// Wraps a state to give it a UI page, or to hide every state page while it is on top.
+///
+/// The page is shown when the state starts or resumes, hidden but kept alive while the state is
+/// paused beneath another, and closed when the state stops. A state without a page shows the
+/// page Servo was started with.
+///
+/// States with a page free the cursor while they are on top, so menus can be clicked. Use
+/// `with_cursor` to change this, for example to lock the cursor for camera control once a menu
+/// is popped.
+///
+/// Events are passed on to the wrapped state's `handle_event` unfiltered, including the input the
+/// UI consumed, so states should take game input from `EventChannel<RoutedEvent>` instead.
+pub struct UiState<S: State> {
+    state: S,
+    page: Option<String>,
+    key: PageKey,
+    cursor: Option<CursorMode>,
+}
+
+impl<S: State> UiState<S> {
+    pub fn new(state: S, page: Option<&str>) -> Self {
+        Self {
+            state: state,
+            page: page.map(|page| page.to_string()),
+            key: PageKey::new(),
+            cursor: page.map(|_| CursorMode::Free),
+        }
+    }
+
+    /// Sets the cursor mode whenever this state starts or resumes, or leaves it as it is with
+    /// None.
+    pub fn with_cursor(mut self, cursor: Option<CursorMode>) -> Self {
+        self.cursor = cursor;
+        self
+    }
+
+    pub fn key(&self) -> PageKey {
+        self.key
+    }
+
+    fn show(&self, world: &mut World) {
+        {
+            let mut pages = world.write_resource::<UiPages>();
+            match self.page {
+                Some(ref url) => pages.show(self.key, url),
+                None => pages.show_default(),
+            }
+        }
+        if let Some(cursor) = self.cursor {
+            *world.write_resource::<CursorMode>() = cursor;
+        }
+    }
+}
+
+impl<S: State> State for UiState<S> {
+    fn on_start(&mut self, world: &mut World) {
+        self.show(world);
+        self.state.on_start(world);
+    }
+
+    fn on_stop(&mut self, world: &mut World) {
+        self.state.on_stop(world);
+        if self.page.is_some() {
+            world.write_resource::<UiPages>().close(self.key);
+        }
+    }
+
+    fn on_pause(&mut self, world: &mut World) {
+        self.state.on_pause(world);
+        if self.page.is_some() {
+            world.write_resource::<UiPages>().hide(self.key);
+        }
+    }
+
+    fn on_resume(&mut self, world: &mut World) {
+        self.show(world);
+        self.state.on_resume(world);
+    }
+
+    fn handle_event(&mut self, world: &mut World, event: Event) -> Trans {
+        self.state.handle_event(world, event)
+    }
+
+    fn fixed_update(&mut self, world: &mut World) -> Trans {
+        self.state.fixed_update(world)
+    }
+
+    fn update(&mut self, world: &mut World) -> Trans {
+        self.state.update(world)
+    }
+}
@@ -0,0 +1,374 @@
+#[cfg(unix)]
+extern crate libc;
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::sync::mpsc::Receiver;
+use std::time::Duration;
+
+/// Page drawing the performance overlay's graphs, relative to the resources directory. It is
+/// shown in a frame over whichever page is current.
+pub const PERF_PAGE: &str = "ui/perf.html";
+
+// Weight of the newest sample in the moving averages
+const SMOOTHING: f32 = 0.1;
+
+/// Frame and UI timings shown by the performance overlay, observable by pages as `perf`.
+///
+/// Times are in milliseconds and smoothed with an exponential moving average, except for Servo's
+/// own figures, which are taken from its time and memory profilers as they report. Those are only
+/// filled in when the profilers are turned on with `ServoUiBundle::with_servo_profiler`.
+#[derive(Clone, Debug, Serialize)]
+pub struct PerfStats {
+    pub frame_ms: f32,
+    /// Time spent in each UI system's run, by system name.
+    pub systems_ms: BTreeMap<String, f32>,
+    /// Time between Servo preparing to composite and presenting, summed over a frame.
+    pub composite_ms: f32,
+    pub composites_per_second: u32,
+    /// GPU memory used by the UI render target, depth buffer and readback buffers, estimated from
+    /// the surface size at 4 bytes per pixel. The driver's real usage isn't queried.
+    pub texture_bytes: u64,
+    /// Mean time of each of Servo's profiler categories, like `LayoutPerform` and
+    /// `ScriptEvaluate`, over its last report period.
+    pub servo_ms: BTreeMap<String, f32>,
+    /// Memory used by the JS heaps of every page, from Servo's last memory report.
+    pub js_heap_bytes: Option<u64>,
+    /// Memory Servo allocated explicitly, from its last memory report.
+    pub servo_explicit_bytes: Option<u64>,
+    /// Resident memory of the whole process, from Servo's last memory report.
+    pub resident_bytes: Option<u64>,
+}
+
+impl PerfStats {
+    pub fn new() -> Self {
+        Self {
+            frame_ms: 0.,
+            systems_ms: BTreeMap::new(),
+            composite_ms: 0.,
+            composites_per_second: 0,
+            texture_bytes: 0,
+            servo_ms: BTreeMap::new(),
+            js_heap_bytes: None,
+            servo_explicit_bytes: None,
+            resident_bytes: None,
+        }
+    }
+
+    pub fn record_frame(&mut self, frame: Duration) {
+        self.frame_ms = smooth(self.frame_ms, millis(frame));
+    }
+
+    pub fn record_system(&mut self, name: &str, time: Duration) {
+        let average = self.systems_ms.entry(name.to_string()).or_insert(0.);
+        *average = smooth(*average, millis(time));
+    }
+
+    pub fn record_composite(&mut self, time: Duration) {
+        self.composite_ms = smooth(self.composite_ms, millis(time));
+    }
+
+    pub fn record_servo_report(&mut self, report: ServoReport) {
+        match report {
+            ServoReport::Time(times) => self.servo_ms = times,
+            ServoReport::Memory(memory) => {
+                self.js_heap_bytes = Some(memory.js_heap_bytes);
+                self.servo_explicit_bytes = memory.explicit_bytes;
+                self.resident_bytes = memory.resident_bytes;
+            }
+        }
+    }
+}
+
+/// One report from Servo's profilers.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ServoReport {
+    /// Mean milliseconds spent in each profiler category, weighted by the number of events.
+    Time(BTreeMap<String, f32>),
+    Memory(MemoryReport),
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MemoryReport {
+    pub explicit_bytes: Option<u64>,
+    pub resident_bytes: Option<u64>,
+    /// Sum of the `js` reports of every page.
+    pub js_heap_bytes: u64,
+}
+
+// Width of the category column in Servo's time profiler table
+const CATEGORY_WIDTH: usize = 35;
+
+enum ReportBlock {
+    // Time and number of events seen so far for each category
+    Time(BTreeMap<String, (f64, u64)>),
+    // The report so far, and the indent of the js report being counted, if inside one
+    Memory(MemoryReport, Option<usize>),
+}
+
+/// Picks the reports of Servo's time and memory profilers out of what Servo prints to stdout, one
+/// line at a time.
+pub struct ReportParser {
+    block: Option<ReportBlock>,
+}
+
+impl ReportParser {
+    pub fn new() -> Self {
+        Self { block: None }
+    }
+
+    /// Returns the report this line finishes, if any.
+    pub fn line(&mut self, line: &str) -> Option<ServoReport> {
+        match self.block.take() {
+            None => {
+                if line.contains("_category_") {
+                    self.block = Some(ReportBlock::Time(BTreeMap::new()));
+                } else if line.trim() == "Begin memory reports" {
+                    self.block = Some(ReportBlock::Memory(MemoryReport::default(), None));
+                }
+                None
+            }
+            Some(ReportBlock::Time(mut times)) => {
+                if line.trim().is_empty() {
+                    let means = times
+                        .into_iter()
+                        .map(|(category, (total, events))| {
+                            (category, (total / events.max(1) as f64) as f32)
+                        })
+                        .collect();
+                    return Some(ServoReport::Time(means));
+                }
+                if let Some((category, mean, events)) = parse_time_row(line) {
+                    let entry = times.entry(category).or_insert((0., 0));
+                    entry.0 += mean * events as f64;
+                    entry.1 += events;
+                }
+                self.block = Some(ReportBlock::Time(times));
+                None
+            }
+            Some(ReportBlock::Memory(mut report, mut js_indent)) => {
+                if line.trim() == "End memory reports" {
+                    return Some(ServoReport::Memory(report));
+                }
+                if let Some((indent, bytes, name)) = parse_memory_row(line) {
+                    if js_indent.map(|js_indent| indent <= js_indent) == Some(true) {
+                        js_indent = None;
+                    }
+                    match name {
+                        "explicit" => report.explicit_bytes = Some(bytes),
+                        "resident" => report.resident_bytes = Some(bytes),
+                        // Parts of a js report are already counted in it
+                        "js" if js_indent.is_none() => {
+                            report.js_heap_bytes += bytes;
+                            js_indent = Some(indent);
+                        }
+                        _ => {}
+                    }
+                }
+                self.block = Some(ReportBlock::Memory(report, js_indent));
+                None
+            }
+        }
+    }
+}
+
+/// Parses a row of the time profiler's table into the category, its mean time in milliseconds
+/// and its number of events. Subcategories are listed under their own names.
+fn parse_time_row(line: &str) -> Option<(String, f64, u64)> {
+    if line.len() <= CATEGORY_WIDTH || !line.is_char_boundary(CATEGORY_WIDTH) {
+        return None;
+    }
+    let (category, rest) = line.split_at(CATEGORY_WIDTH);
+    let category = category
+        .trim_left_matches(|c| c == '|' || c == '+' || c == ' ')
+        .trim();
+    // Metadata columns come first, then mean, median, min, max and the number of events
+    let fields: Vec<&str> = rest.split_whitespace().collect();
+    if category.is_empty() || fields.len() < 5 {
+        return None;
+    }
+    let events = fields[fields.len() - 1].parse().ok()?;
+    let mean = fields[fields.len() - 5].parse().ok()?;
+    Some((category.to_string(), mean, events))
+}
+
+/// Parses a line of a memory report like `|    12.50 MiB -- explicit [3]` into how far it is
+/// indented, its size in bytes and its name.
+fn parse_memory_row(line: &str) -> Option<(usize, u64, &str)> {
+    let separator = line.find(" MiB -- ")?;
+    let size: f64 = line[..separator]
+        .trim_left_matches('|')
+        .trim()
+        .parse()
+        .ok()?;
+    let mut name = line[separator + " MiB -- ".len()..].trim();
+    // Repeated reports are merged and counted in brackets
+    if name.ends_with(']') {
+        if let Some(count) = name.rfind(" [") {
+            name = &name[..count];
+        }
+    }
+    // Sizes are right aligned to the same width, so the separator moves right with the indent
+    Some((separator, (size * 1024. * 1024.) as u64, name))
+}
+
+/// Sends the reports Servo's profilers print to stdout down the returned channel, as they are
+/// printed. Stdout is replaced by a pipe whose output is still copied to the original stdout.
+#[cfg(unix)]
+pub fn capture_servo_reports() -> Result<Receiver<ServoReport>, String> {
+    use std::io;
+    use std::os::unix::io::FromRawFd;
+    use std::sync::mpsc;
+    use std::thread;
+
+    let (pipe, stdout) = unsafe {
+        let mut fds = [0; 2];
+        if libc::pipe(fds.as_mut_ptr()) != 0 {
+            return Err(format!(
+                "Can't create a pipe: {}",
+                io::Error::last_os_error()
+            ));
+        }
+        let stdout = libc::dup(libc::STDOUT_FILENO);
+        if stdout < 0 || libc::dup2(fds[1], libc::STDOUT_FILENO) < 0 {
+            let error = io::Error::last_os_error();
+            libc::close(fds[0]);
+            libc::close(fds[1]);
+            if stdout >= 0 {
+                libc::close(stdout);
+            }
+            return Err(format!("Can't redirect stdout: {}", error));
+        }
+        libc::close(fds[1]);
+        (File::from_raw_fd(fds[0]), File::from_raw_fd(stdout))
+    };
+    let (sender, receiver) = mpsc::channel();
+    thread::Builder::new()
+        .name("Servo profiler reports".to_string())
+        .spawn(move || {
+            copy_reports(pipe, stdout, |report| {
+                // The game may have stopped listening, but stdout still has to be copied
+                let _ = sender.send(report);
+            })
+        })
+        .map_err(|e| e.to_string())?;
+    Ok(receiver)
+}
+
+#[cfg(not(unix))]
+pub fn capture_servo_reports() -> Result<Receiver<ServoReport>, String> {
+    Err("Servo's reports can only be read from stdout on Unix".to_string())
+}
+
+// Stops only when the pipe closes, since anything printed after would block once it fills
+fn copy_reports<F: Fn(ServoReport)>(pipe: File, mut stdout: File, send: F) {
+    let mut pipe = BufReader::new(pipe);
+    let mut parser = ReportParser::new();
+    let mut line = vec![];
+    loop {
+        line.clear();
+        match pipe.read_until(b'\n', &mut line) {
+            Ok(0) => return,
+            Ok(_) => {}
+            Err(e) => {
+                eprintln!(
+                    "ERROR: Failed to read stdout, Servo reports are lost: {}",
+                    e
+                );
+                continue;
+            }
+        }
+        let _ = stdout.write_all(&line);
+        if let Some(report) = parser.line(&String::from_utf8_lossy(&line)) {
+            send(report);
+        }
+    }
+}
+
+pub fn millis(duration: Duration) -> f32 {
+    duration.as_secs() as f32 * 1000. + duration.subsec_nanos() as f32 / 1_000_000.
+}
+
+fn smooth(average: f32, sample: f32) -> f32 {
+    average + (sample - average) * SMOOTHING
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(lines: &[&str]) -> Vec<ServoReport> {
+        let mut parser = ReportParser::new();
+        lines.iter().filter_map(|line| parser.line(line)).collect()
+    }
+
+    fn time_row(category: &str, mean: f64, events: u64) -> String {
+        format!(
+            "{:35}{:14} {:9} {:30} {:15.4} {:15.4} {:15.4} {:15.4} {:15}",
+            category, "N/A", "N/A", "N/A", mean, mean, mean, mean, events
+        )
+    }
+
+    #[test]
+    fn reads_mean_times_weighted_by_events() {
+        let header = format!(
+            "{:35} {:14} {:9} {:30} {:15}",
+            "_category_", "_incremental?_", "_iframe?_", "            _url_", "    _mean (ms)_"
+        );
+        let lines = vec![
+            "Servo starting".to_string(),
+            header,
+            time_row("LayoutPerform", 2.0, 1),
+            time_row("+ LayoutStyleRecalc", 0.5, 4),
+            time_row("LayoutPerform", 5.0, 3),
+            "".to_string(),
+            "Other output".to_string(),
+        ];
+        let lines: Vec<&str> = lines.iter().map(|line| line.as_str()).collect();
+        let mut expected = BTreeMap::new();
+        expected.insert("LayoutPerform".to_string(), 4.25);
+        expected.insert("LayoutStyleRecalc".to_string(), 0.5);
+        assert_eq!(parse(&lines), vec![ServoReport::Time(expected)]);
+    }
+
+    #[test]
+    fn sums_the_js_reports_of_every_page() {
+        let reports = parse(&[
+            "Begin memory reports",
+            "|",
+            "|   100.00 MiB -- explicit",
+            "|       40.00 MiB -- url(file:///ui/hud.html)",
+            "|          30.00 MiB -- js",
+            "|             20.00 MiB -- gc-heap",
+            "|             10.00 MiB -- malloc-heap",
+            "|          10.00 MiB -- layout-thread",
+            "|       12.50 MiB -- url(file:///ui/menu.html)",
+            "|          12.50 MiB -- js [2]",
+            "|",
+            "|   250.00 MiB -- resident",
+            "End memory reports",
+        ]);
+        assert_eq!(
+            reports,
+            vec![ServoReport::Memory(MemoryReport {
+                explicit_bytes: Some(100 * 1024 * 1024),
+                resident_bytes: Some(250 * 1024 * 1024),
+                js_heap_bytes: 42 * 1024 * 1024 + 512 * 1024,
+            })]
+        );
+    }
+
+    #[test]
+    fn reports_reach_the_stats() {
+        let mut stats = PerfStats::new();
+        stats.record_servo_report(ServoReport::Memory(MemoryReport {
+            explicit_bytes: None,
+            resident_bytes: Some(7),
+            js_heap_bytes: 3,
+        }));
+        assert_eq!(stats.js_heap_bytes, Some(3));
+        assert_eq!(stats.servo_explicit_bytes, None);
+        assert_eq!(stats.resident_bytes, Some(7));
+    }
+}
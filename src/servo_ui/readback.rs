@@ -0,0 +1,133 @@
+extern crate servo as libservo;
+
+use std::ptr;
+use std::slice;
+use self::libservo::gl;
+
+/// Reads composited frames out of the bound framebuffer through a pair of pixel buffer objects.
+///
+/// Each call to `read` starts an asynchronous transfer into one buffer and maps the other, so the
+/// pixels returned are one frame behind but the CPU never stalls waiting on the GPU. Once Servo
+/// stops compositing, `flush` maps the last read, so the final frame isn't left in the buffer.
+pub struct PixelReadback {
+    buffers: [gl::GLuint; 2],
+    current: usize,
+    // Whether the buffer not being written holds a read which hasn't been mapped yet
+    primed: bool,
+    width: u32,
+    height: u32,
+}
+
+impl PixelReadback {
+    pub fn new(gl: &gl::Gl, width: u32, height: u32) -> Self {
+        let buffers = gl.gen_buffers(2);
+        let mut readback = Self {
+            buffers: [buffers[0], buffers[1]],
+            current: 0,
+            primed: false,
+            width: 0,
+            height: 0,
+        };
+        readback.resize(gl, width, height);
+        readback
+    }
+
+    pub fn size(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    /// Reallocates both buffers for a new frame size. The next call to `read` returns nothing
+    /// since there is no previous frame of the right size to map.
+    pub fn resize(&mut self, gl: &gl::Gl, width: u32, height: u32) {
+        let bytes = (width * height * 4) as gl::GLsizeiptr;
+        for buffer in self.buffers.iter() {
+            gl.bind_buffer(gl::PIXEL_PACK_BUFFER, *buffer);
+            gl.buffer_data_untyped(gl::PIXEL_PACK_BUFFER, bytes, ptr::null(), gl::STREAM_READ);
+        }
+        gl.bind_buffer(gl::PIXEL_PACK_BUFFER, 0);
+        self.width = width;
+        self.height = height;
+        self.primed = false;
+    }
+
+    /// Queues a read of the bound framebuffer and returns the RGBA pixels of the previous read,
+    /// with the rows flipped so the first row is the top of the page.
+    pub fn read(&mut self, gl: &gl::Gl) -> Option<Vec<u8>> {
+        let (width, height) = (self.width as usize, self.height as usize);
+        let next = (self.current + 1) % 2;
+
+        // Rows are tightly packed, and gfx expects its own alignment back afterwards
+        let alignment = gl.get_integer_v(gl::PACK_ALIGNMENT);
+        gl.bind_buffer(gl::PIXEL_PACK_BUFFER, self.buffers[self.current]);
+        gl.pixel_store_i(gl::PACK_ALIGNMENT, 1);
+        gl.read_pixels_into_pbo(
+            0,
+            0,
+            width as gl::GLsizei,
+            height as gl::GLsizei,
+            gl::RGBA,
+            gl::UNSIGNED_BYTE,
+        );
+
+        let pixels = if self.primed {
+            self.map(gl, self.buffers[next])
+        } else {
+            None
+        };
+
+        gl.bind_buffer(gl::PIXEL_PACK_BUFFER, 0);
+        gl.pixel_store_i(gl::PACK_ALIGNMENT, alignment);
+        self.primed = true;
+        self.current = next;
+        pixels
+    }
+
+    /// Returns the pixels of the last read if `read` hasn't returned them yet, waiting for the
+    /// transfer to finish. Meant for when no new frame is coming to push them out.
+    pub fn flush(&mut self, gl: &gl::Gl) -> Option<Vec<u8>> {
+        if !self.primed {
+            return None;
+        }
+        let last = (self.current + 1) % 2;
+        let pixels = self.map(gl, self.buffers[last]);
+        gl.bind_buffer(gl::PIXEL_PACK_BUFFER, 0);
+        self.primed = false;
+        pixels
+    }
+
+    /// Maps a buffer and copies its pixels out with the rows flipped. Leaves it bound.
+    fn map(&self, gl: &gl::Gl, buffer: gl::GLuint) -> Option<Vec<u8>> {
+        let (width, height) = (self.width as usize, self.height as usize);
+        gl.bind_buffer(gl::PIXEL_PACK_BUFFER, buffer);
+        let mapped = gl.map_buffer_range(
+            gl::PIXEL_PACK_BUFFER,
+            0,
+            (width * height * 4) as gl::GLsizeiptr,
+            gl::MAP_READ_BIT,
+        ) as *const u8;
+        if mapped.is_null() {
+            return None;
+        }
+        let stride = width * 4;
+        let source = unsafe { slice::from_raw_parts(mapped, stride * height) };
+        let mut pixels = Vec::with_capacity(stride * height);
+        for row in source.chunks(stride).rev() {
+            pixels.extend_from_slice(row);
+        }
+        gl.unmap_buffer(gl::PIXEL_PACK_BUFFER);
+        Some(pixels)
+    }
+
+    pub fn delete(&mut self, gl: &gl::Gl) {
+        gl.delete_buffers(&self.buffers);
+        self.buffers = [0, 0];
+        self.primed = false;
+    }
+}
+
+/// A composited frame read back from Servo, ready to upload as RGBA8 texture data.
+pub struct UiFrame {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
@@ -0,0 +1,638 @@
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::mem;
+use std::path::Path;
+use std::time::Instant;
+use amethyst::winit::{DeviceEvent, DeviceId, ElementState, Event, KeyboardInput, ModifiersState,
+                      MouseButton, MouseScrollDelta, Touch, TouchPhase, WindowEvent, WindowId};
+use ron;
+use serde_json;
+use super::keys;
+
+// Recording the input reaching ServoUiSystem, and replaying it deterministically.
+//
+// Recordings hold one event per line, as JSON, or as RON for files ending in `.ron`, so a
+// recording cut short by a crash still loads up to its last line. Each event has the frame it
+// arrived on, counted from ServoUiSystem's first run, and the milliseconds since then.
+//
+// Replays are driven by a virtual clock which counts frames instead of reading the system clock,
+// so each event reaches ServoUiSystem on the frame it was recorded on however fast the machine
+// runs. Live input is ignored until the replay ends.
+//
+// The virtual clock starts on ServoUiSystem's first run, as recording does, so replays need no
+// live input and run headless. Replayed events come from the synthetic `replay_device()`, and
+// game systems can tell them apart by `RoutedEvent::replayed`.
+
+/// Input which can be recorded, the window and device events ServoUiSystem makes use of.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum InputEvent {
+    Resized { width: u32, height: u32 },
+    Focused(bool),
+    CursorMoved { x: f64, y: f64, modifiers: Modifiers },
+    CursorEntered,
+    CursorLeft,
+    MouseInput {
+        button: RecordedButton,
+        pressed: bool,
+        modifiers: Modifiers,
+    },
+    MouseWheel {
+        dx: f32,
+        dy: f32,
+        /// Whether the deltas are in lines rather than pixels.
+        lines: bool,
+        phase: RecordedPhase,
+        modifiers: Modifiers,
+    },
+    KeyboardInput {
+        scancode: u32,
+        /// Name of the virtual key as given by `keys::key_name`, None for keys without one.
+        key: Option<String>,
+        pressed: bool,
+        modifiers: Modifiers,
+    },
+    ReceivedCharacter(char),
+    Touch {
+        id: u64,
+        phase: RecordedPhase,
+        x: f64,
+        y: f64,
+    },
+    MouseMotion { dx: f64, dy: f64 },
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Modifiers {
+    #[serde(default)]
+    pub shift: bool,
+    #[serde(default)]
+    pub ctrl: bool,
+    #[serde(default)]
+    pub alt: bool,
+    #[serde(default)]
+    pub logo: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RecordedButton {
+    Left,
+    Right,
+    Middle,
+    Other(u8),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RecordedPhase {
+    Started,
+    Moved,
+    Ended,
+    Cancelled,
+}
+
+/// An event and when it was recorded.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RecordedInput {
+    pub frame: u64,
+    pub time_ms: u64,
+    pub event: InputEvent,
+}
+
+impl From<ModifiersState> for Modifiers {
+    fn from(modifiers: ModifiersState) -> Self {
+        Self {
+            shift: modifiers.shift,
+            ctrl: modifiers.ctrl,
+            alt: modifiers.alt,
+            logo: modifiers.logo,
+        }
+    }
+}
+
+impl From<Modifiers> for ModifiersState {
+    fn from(modifiers: Modifiers) -> Self {
+        ModifiersState {
+            shift: modifiers.shift,
+            ctrl: modifiers.ctrl,
+            alt: modifiers.alt,
+            logo: modifiers.logo,
+        }
+    }
+}
+
+impl From<TouchPhase> for RecordedPhase {
+    fn from(phase: TouchPhase) -> Self {
+        match phase {
+            TouchPhase::Started => RecordedPhase::Started,
+            TouchPhase::Moved => RecordedPhase::Moved,
+            TouchPhase::Ended => RecordedPhase::Ended,
+            TouchPhase::Cancelled => RecordedPhase::Cancelled,
+        }
+    }
+}
+
+impl From<RecordedPhase> for TouchPhase {
+    fn from(phase: RecordedPhase) -> Self {
+        match phase {
+            RecordedPhase::Started => TouchPhase::Started,
+            RecordedPhase::Moved => TouchPhase::Moved,
+            RecordedPhase::Ended => TouchPhase::Ended,
+            RecordedPhase::Cancelled => TouchPhase::Cancelled,
+        }
+    }
+}
+
+impl InputEvent {
+    /// The recordable part of a winit event, None for events which aren't recorded.
+    pub fn from_event(event: &Event) -> Option<InputEvent> {
+        let event = match event {
+            &Event::WindowEvent { ref event, .. } => event,
+            &Event::DeviceEvent {
+                event: DeviceEvent::MouseMotion { delta: (dx, dy) },
+                ..
+            } => return Some(InputEvent::MouseMotion { dx: dx, dy: dy }),
+            _ => return None,
+        };
+        Some(match event {
+            &WindowEvent::Resized(width, height) => InputEvent::Resized {
+                width: width,
+                height: height,
+            },
+            &WindowEvent::Focused(focused) => InputEvent::Focused(focused),
+            &WindowEvent::CursorMoved {
+                position: (x, y),
+                modifiers,
+                ..
+            } => InputEvent::CursorMoved {
+                x: x,
+                y: y,
+                modifiers: modifiers.into(),
+            },
+            &WindowEvent::CursorEntered { .. } => InputEvent::CursorEntered,
+            &WindowEvent::CursorLeft { .. } => InputEvent::CursorLeft,
+            &WindowEvent::MouseInput {
+                state,
+                button,
+                modifiers,
+                ..
+            } => InputEvent::MouseInput {
+                button: match button {
+                    MouseButton::Left => RecordedButton::Left,
+                    MouseButton::Right => RecordedButton::Right,
+                    MouseButton::Middle => RecordedButton::Middle,
+                    MouseButton::Other(button) => RecordedButton::Other(button),
+                },
+                pressed: state == ElementState::Pressed,
+                modifiers: modifiers.into(),
+            },
+            &WindowEvent::MouseWheel {
+                delta,
+                phase,
+                modifiers,
+                ..
+            } => {
+                let (dx, dy, lines) = match delta {
+                    MouseScrollDelta::LineDelta(dx, dy) => (dx, dy, true),
+                    MouseScrollDelta::PixelDelta(dx, dy) => (dx, dy, false),
+                };
+                InputEvent::MouseWheel {
+                    dx: dx,
+                    dy: dy,
+                    lines: lines,
+                    phase: phase.into(),
+                    modifiers: modifiers.into(),
+                }
+            }
+            &WindowEvent::KeyboardInput { input, .. } => InputEvent::KeyboardInput {
+                scancode: input.scancode,
+                key: input
+                    .virtual_keycode
+                    .and_then(keys::key_name)
+                    .map(|name| name.to_string()),
+                pressed: input.state == ElementState::Pressed,
+                modifiers: input.modifiers.into(),
+            },
+            &WindowEvent::ReceivedCharacter(ch) => InputEvent::ReceivedCharacter(ch),
+            &WindowEvent::Touch(ref touch) => InputEvent::Touch {
+                id: touch.id,
+                phase: touch.phase.into(),
+                x: touch.location.0,
+                y: touch.location.1,
+            },
+            _ => return None,
+        })
+    }
+
+    /// Rebuilds the winit event, as coming from `device` in `window`.
+    pub fn to_event(&self, window: WindowId, device: DeviceId) -> Event {
+        let device_id = device;
+        let event = match self.clone() {
+            InputEvent::MouseMotion { dx, dy } => {
+                return Event::DeviceEvent {
+                    device_id: device_id,
+                    event: DeviceEvent::MouseMotion { delta: (dx, dy) },
+                }
+            }
+            InputEvent::Resized { width, height } => WindowEvent::Resized(width, height),
+            InputEvent::Focused(focused) => WindowEvent::Focused(focused),
+            InputEvent::CursorMoved { x, y, modifiers } => WindowEvent::CursorMoved {
+                device_id: device_id,
+                position: (x, y),
+                modifiers: modifiers.into(),
+            },
+            InputEvent::CursorEntered => WindowEvent::CursorEntered {
+                device_id: device_id,
+            },
+            InputEvent::CursorLeft => WindowEvent::CursorLeft {
+                device_id: device_id,
+            },
+            InputEvent::MouseInput {
+                button,
+                pressed,
+                modifiers,
+            } => WindowEvent::MouseInput {
+                device_id: device_id,
+                state: element_state(pressed),
+                button: match button {
+                    RecordedButton::Left => MouseButton::Left,
+                    RecordedButton::Right => MouseButton::Right,
+                    RecordedButton::Middle => MouseButton::Middle,
+                    RecordedButton::Other(button) => MouseButton::Other(button),
+                },
+                modifiers: modifiers.into(),
+            },
+            InputEvent::MouseWheel {
+                dx,
+                dy,
+                lines,
+                phase,
+                modifiers,
+            } => WindowEvent::MouseWheel {
+                device_id: device_id,
+                delta: if lines {
+                    MouseScrollDelta::LineDelta(dx, dy)
+                } else {
+                    MouseScrollDelta::PixelDelta(dx, dy)
+                },
+                phase: phase.into(),
+                modifiers: modifiers.into(),
+            },
+            InputEvent::KeyboardInput {
+                scancode,
+                key,
+                pressed,
+                modifiers,
+            } => WindowEvent::KeyboardInput {
+                device_id: device_id,
+                input: KeyboardInput {
+                    scancode: scancode,
+                    state: element_state(pressed),
+                    virtual_keycode: key.and_then(|name| keys::key_from_name(&name)),
+                    modifiers: modifiers.into(),
+                },
+            },
+            InputEvent::ReceivedCharacter(ch) => WindowEvent::ReceivedCharacter(ch),
+            InputEvent::Touch { id, phase, x, y } => WindowEvent::Touch(Touch {
+                device_id: device_id,
+                phase: phase.into(),
+                location: (x, y),
+                id: id,
+            }),
+        };
+        Event::WindowEvent {
+            window_id: window,
+            event: event,
+        }
+    }
+}
+
+fn element_state(pressed: bool) -> ElementState {
+    if pressed {
+        ElementState::Pressed
+    } else {
+        ElementState::Released
+    }
+}
+
+/// The device replayed events come from. winit 0.10 can't make device ids, but on every
+/// platform it supports they are plain integers, and zero is one no real device is given (X11's
+/// input devices start at 2, and raw input handles on Windows are never null).
+pub fn replay_device() -> DeviceId {
+    unsafe { mem::zeroed() }
+}
+
+fn is_ron(path: &Path) -> bool {
+    path.extension().map_or(false, |extension| extension == "ron")
+}
+
+/// Writes the events ServoUiSystem sees to a file as they arrive.
+pub struct InputRecorder {
+    writer: BufWriter<File>,
+    ron: bool,
+    started: Instant,
+    frame: u64,
+}
+
+impl InputRecorder {
+    pub fn create(path: &Path) -> Result<Self, String> {
+        let file = File::create(path)
+            .map_err(|e| format!("Failed to create {}: {}", path.display(), e))?;
+        Ok(Self {
+            writer: BufWriter::new(file),
+            ron: is_ron(path),
+            started: Instant::now(),
+            frame: 0,
+        })
+    }
+
+    /// Records one frame's events, flushing them so nothing is lost if the game crashes.
+    pub fn record_frame(&mut self, events: &[Event]) {
+        let elapsed = self.started.elapsed();
+        let time_ms = elapsed.as_secs() * 1000 + (elapsed.subsec_nanos() / 1_000_000) as u64;
+        let mut recorded = false;
+        for event in events.iter().filter_map(InputEvent::from_event) {
+            let input = RecordedInput {
+                frame: self.frame,
+                time_ms: time_ms,
+                event: event,
+            };
+            let line = if self.ron {
+                ron::ser::to_string(&input).map_err(|e| e.to_string())
+            } else {
+                serde_json::to_string(&input).map_err(|e| e.to_string())
+            };
+            let written = line.and_then(|line| {
+                writeln!(self.writer, "{}", line).map_err(|e| e.to_string())
+            });
+            if let Err(e) = written {
+                eprintln!("ERROR: Failed to record input: {}", e);
+            }
+            recorded = true;
+        }
+        if recorded {
+            if let Err(e) = self.writer.flush() {
+                eprintln!("ERROR: Failed to record input: {}", e);
+            }
+        }
+        self.frame += 1;
+    }
+}
+
+/// Feeds recorded events back to ServoUiSystem on the frames they were recorded on.
+pub struct InputReplay {
+    events: VecDeque<RecordedInput>,
+    frame: u64,
+}
+
+impl InputReplay {
+    pub fn new(events: Vec<RecordedInput>) -> Self {
+        Self {
+            events: events.into(),
+            frame: 0,
+        }
+    }
+
+    /// Loads a recording written by InputRecorder. Errors give the line which failed to parse.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let file =
+            File::open(path).map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+        let ron = is_ron(path);
+        let mut events = vec![];
+        for (number, line) in BufReader::new(file).lines().enumerate() {
+            let line = line.map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let input = if ron {
+                ron::de::from_str(&line).map_err(|e| e.to_string())
+            } else {
+                serde_json::from_str(&line).map_err(|e| e.to_string())
+            };
+            events.push(input.map_err(|e| {
+                format!("Invalid event on line {} of {}: {}", number + 1, path.display(), e)
+            })?);
+        }
+        Ok(Self::new(events))
+    }
+
+    /// The events recorded on the current frame, as coming from `device` in `window`, and
+    /// advances to the next frame.
+    pub fn next_frame(&mut self, window: WindowId, device: DeviceId) -> Vec<Event> {
+        let mut events = vec![];
+        while self.events
+            .front()
+            .map_or(false, |input| input.frame <= self.frame)
+        {
+            if let Some(input) = self.events.pop_front() {
+                events.push(input.event.to_event(window, device));
+            }
+        }
+        self.frame += 1;
+        events
+    }
+
+    /// Runs one frame of the replay: drops the live input from `live`, keeping other events such
+    /// as wakeups, and returns the recorded events due this frame, from `replay_device()`.
+    pub fn replay_frame(&mut self, live: &mut Vec<Event>, window: WindowId) -> Vec<Event> {
+        live.retain(|event| InputEvent::from_event(event).is_none());
+        self.next_frame(window, replay_device())
+    }
+
+    /// The current frame of the virtual clock.
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.events.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::path::PathBuf;
+    use std::process;
+    use amethyst::winit::VirtualKeyCode;
+    use super::super::{test_device, test_window};
+    use super::*;
+
+    fn modifiers() -> Modifiers {
+        Modifiers {
+            shift: true,
+            ctrl: false,
+            alt: true,
+            logo: false,
+        }
+    }
+
+    fn inputs() -> Vec<InputEvent> {
+        vec![
+            InputEvent::Resized {
+                width: 800,
+                height: 600,
+            },
+            InputEvent::Focused(true),
+            InputEvent::CursorMoved {
+                x: 12.5,
+                y: 40.,
+                modifiers: modifiers(),
+            },
+            InputEvent::CursorEntered,
+            InputEvent::CursorLeft,
+            InputEvent::MouseInput {
+                button: RecordedButton::Other(4),
+                pressed: true,
+                modifiers: modifiers(),
+            },
+            InputEvent::MouseWheel {
+                dx: 0.,
+                dy: -3.,
+                lines: true,
+                phase: RecordedPhase::Moved,
+                modifiers: Modifiers::default(),
+            },
+            InputEvent::KeyboardInput {
+                scancode: 30,
+                key: keys::key_name(VirtualKeyCode::A).map(|name| name.to_string()),
+                pressed: false,
+                modifiers: modifiers(),
+            },
+            InputEvent::ReceivedCharacter('é'),
+            InputEvent::Touch {
+                id: 3,
+                phase: RecordedPhase::Cancelled,
+                x: 1.,
+                y: 2.,
+            },
+            InputEvent::MouseMotion { dx: -1.5, dy: 2. },
+        ]
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("servo-ui-{}-{}", process::id(), name))
+    }
+
+    #[test]
+    fn events_survive_a_round_trip_through_winit() {
+        for input in inputs() {
+            let event = input.to_event(test_window(), test_device());
+            assert_eq!(InputEvent::from_event(&event), Some(input));
+        }
+    }
+
+    fn recorded(frame: u64, event: InputEvent) -> RecordedInput {
+        RecordedInput {
+            frame: frame,
+            time_ms: frame * 16,
+            event: event,
+        }
+    }
+
+    #[test]
+    fn replays_from_the_first_frame_without_live_input() {
+        let mut replay = InputReplay::new(vec![
+            recorded(0, InputEvent::Focused(true)),
+            recorded(2, InputEvent::CursorLeft),
+        ]);
+        let mut frames = vec![];
+        for _ in 0..3 {
+            let events = replay.replay_frame(&mut vec![], test_window());
+            frames.push(events);
+        }
+        assert!(replay.is_finished());
+
+        let inputs: Vec<Vec<InputEvent>> = frames
+            .iter()
+            .map(|events| events.iter().filter_map(InputEvent::from_event).collect())
+            .collect();
+        assert_eq!(
+            inputs,
+            vec![
+                vec![InputEvent::Focused(true)],
+                vec![],
+                vec![InputEvent::CursorLeft],
+            ]
+        );
+        match frames[2][0] {
+            Event::WindowEvent {
+                event: WindowEvent::CursorLeft { device_id },
+                ..
+            } => assert_eq!(device_id, replay_device()),
+            ref event => panic!("Unexpected event {:?}", event),
+        }
+    }
+
+    #[test]
+    fn replaying_drops_live_input_only() {
+        let mut replay = InputReplay::new(vec![recorded(5, InputEvent::CursorLeft)]);
+        let mut live = vec![
+            Event::Awakened,
+            InputEvent::CursorEntered.to_event(test_window(), test_device()),
+        ];
+        assert!(replay.replay_frame(&mut live, test_window()).is_empty());
+        assert_eq!(live.len(), 1);
+        match live[0] {
+            Event::Awakened => {}
+            ref event => panic!("Unexpected event {:?}", event),
+        }
+    }
+
+    fn recording_round_trip(name: &str) {
+        let path = temp_path(name);
+        let events: Vec<Event> = inputs()
+            .iter()
+            .map(|input| input.to_event(test_window(), test_device()))
+            .collect();
+        {
+            let mut recorder = InputRecorder::create(&path).unwrap();
+            recorder.record_frame(&events[..3]);
+            recorder.record_frame(&[]);
+            recorder.record_frame(&events[3..]);
+        }
+        let loaded = InputReplay::load(&path);
+        fs::remove_file(&path).unwrap();
+
+        let mut replay = loaded.unwrap();
+        let mut frames = vec![];
+        while !replay.is_finished() {
+            let frame: Vec<InputEvent> = replay
+                .next_frame(test_window(), test_device())
+                .iter()
+                .filter_map(InputEvent::from_event)
+                .collect();
+            frames.push(frame);
+        }
+        let expected = inputs();
+        assert_eq!(
+            frames,
+            vec![expected[..3].to_vec(), vec![], expected[3..].to_vec()]
+        );
+    }
+
+    #[test]
+    fn json_recordings_replay_on_their_frames() {
+        recording_round_trip("recording.json");
+    }
+
+    #[test]
+    fn ron_recordings_replay_on_their_frames() {
+        recording_round_trip("recording.ron");
+    }
+
+    #[test]
+    fn loading_reports_the_bad_line() {
+        let path = temp_path("bad.json");
+        fs::write(
+            &path,
+            "{\"frame\":0,\"time_ms\":0,\"event\":\"CursorLeft\"}\n\nnot json\n",
+        ).unwrap();
+        let loaded = InputReplay::load(&path);
+        fs::remove_file(&path).unwrap();
+        match loaded {
+            Ok(_) => panic!("Loaded an invalid recording"),
+            Err(e) => assert!(e.contains("line 3"), "{}", e),
+        }
+    }
+}
@@ -0,0 +1,143 @@
+use std::collections::HashMap;
+use std::time::Instant;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json::{self, Value};
+use amethyst::prelude::World;
+use amethyst::shred::{Resources, RunNow};
+use amethyst::shrev::{EventChannel, ReaderId};
+use super::{PerfStats, UiMessage, UiOutbox};
+
+// Request/response calls from page JavaScript into Rust, on top of UiMessages.
+//
+// `game.call(method, args)` sends an `rpc.call` message with a fresh id and returns a promise.
+// RpcSystem looks the method up in the RpcRegistry, runs the handler with the world's resources
+// and answers with an `rpc.reply` message carrying either a result or an error, which resolves
+// or rejects the promise.
+
+type Handler = Box<Fn(&Resources, Value) -> Result<Value, String> + Send + Sync>;
+
+/// Named handlers callable from pages through `game.call`.
+pub struct RpcRegistry {
+    handlers: HashMap<String, Handler>,
+}
+
+impl RpcRegistry {
+    pub fn new() -> Self {
+        Self {
+            handlers: HashMap::new(),
+        }
+    }
+
+    /// Registers a handler under `method`, replacing any previous handler with the same name.
+    /// Arguments are deserialized from the JavaScript value passed to `game.call`, and the
+    /// result is serialized back to resolve the promise. An `Err` rejects it with that message.
+    pub fn register<A, R, F>(&mut self, method: &str, handler: F)
+    where
+        A: DeserializeOwned,
+        R: Serialize,
+        F: Fn(&Resources, A) -> Result<R, String> + Send + Sync + 'static,
+    {
+        let method_name = method.to_string();
+        self.handlers.insert(
+            method.to_string(),
+            Box::new(move |res, args| {
+                let args = serde_json::from_value(args)
+                    .map_err(|e| format!("Invalid arguments for '{}': {}", method_name, e))?;
+                let result = handler(res, args)?;
+                serde_json::to_value(result)
+                    .map_err(|e| format!("Failed to serialize result of '{}': {}", method_name, e))
+            }),
+        );
+    }
+
+    pub fn unregister(&mut self, method: &str) {
+        self.handlers.remove(method);
+    }
+
+    pub fn methods(&self) -> Vec<&str> {
+        self.handlers.keys().map(|k| k.as_str()).collect()
+    }
+
+    /// Runs the handler for `method`.
+    pub fn call(&self, res: &Resources, method: &str, args: Value) -> Result<Value, String> {
+        match self.handlers.get(method) {
+            Some(handler) => handler(res, args),
+            None => Err(format!("No handler registered for '{}'", method)),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct RpcCall {
+    id: u64,
+    method: String,
+    #[serde(default)]
+    args: Value,
+}
+
+/// Answers `rpc.call` messages from pages using the handlers in the RpcRegistry.
+///
+/// Runs as a thread local system with the whole of `Resources`, so handlers can fetch any
+/// resource. Handlers must not fetch `UiOutbox` mutably while replying, it is fetched once all
+/// calls for the frame have been handled.
+pub struct RpcSystem {
+    reader_id: ReaderId<UiMessage>,
+}
+
+impl RpcSystem {
+    pub fn new(world: &mut World) -> Self {
+        Self {
+            reader_id: world
+                .write_resource::<EventChannel<UiMessage>>()
+                .register_reader(),
+        }
+    }
+
+    /// Runs the calls pages made since the last run and queues their replies.
+    fn answer_calls(&mut self, res: &Resources) {
+        let calls: Vec<_> = res.fetch::<EventChannel<UiMessage>>(0)
+            .read(&mut self.reader_id)
+            .filter(|message| message.name == "rpc.call")
+            .map(|message| {
+                (
+                    message.browser,
+                    serde_json::from_value::<RpcCall>(message.payload.clone()),
+                )
+            })
+            .collect();
+        if calls.is_empty() {
+            return;
+        }
+
+        let mut replies = vec![];
+        {
+            let registry = res.fetch::<RpcRegistry>(0);
+            for (browser, call) in calls {
+                match call {
+                    Ok(call) => {
+                        let reply = match registry.call(res, &call.method, call.args) {
+                            Ok(result) => json!({ "id": call.id, "result": result }),
+                            Err(error) => json!({ "id": call.id, "error": error }),
+                        };
+                        replies.push((browser, reply));
+                    }
+                    Err(e) => eprintln!("ERROR: Malformed RPC call from page: {}", e),
+                }
+            }
+        }
+
+        let mut outbox = res.fetch_mut::<UiOutbox>(0);
+        for (browser, reply) in replies {
+            outbox.send(browser, "rpc.reply", reply);
+        }
+    }
+}
+
+impl<'a> RunNow<'a> for RpcSystem {
+    fn run_now(&mut self, res: &'a Resources) {
+        let started = Instant::now();
+        self.answer_calls(res);
+        res.fetch_mut::<PerfStats>(0).record_system("rpc", started.elapsed());
+    }
+}
@@ -0,0 +1,197 @@
+extern crate glutin;
+
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use amethyst::config::Config;
+use amethyst::renderer::DisplayConfig;
+use ron;
+use serde::Serialize;
+use serde_json::Value;
+use self::glutin::GlWindow;
+use super::{RpcRegistry, UiBindings};
+
+/// Page editing the DisplaySettings, relative to the resources directory.
+pub const SETTINGS_PAGE: &str = "ui/settings.html";
+
+// Smallest and largest UI scale the settings page can set
+const MIN_UI_SCALE: f32 = 0.5;
+const MAX_UI_SCALE: f32 = 3.0;
+
+/// Settings stored alongside the display config which amethyst doesn't know about.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct UiSettingsFile {
+    ui_scale: f32,
+}
+
+/// The display config the game was started with and the UI scale, observable by pages as
+/// `settings` and changed through the `settings.set` call.
+///
+/// Resolution, fullscreen and UI scale apply immediately. Vsync and multisampling are fixed
+/// when the GL context is created, so changing them only sets `restart_required`. Every change
+/// is written back to the file the settings were loaded from.
+#[derive(Clone, Debug)]
+pub struct DisplaySettings {
+    pub display: DisplayConfig,
+    /// Multiplies the window's DPI factor, making page content larger or smaller.
+    pub ui_scale: f32,
+    /// Set once a setting has changed which only takes effect when the game is restarted.
+    pub restart_required: bool,
+    path: Option<PathBuf>,
+}
+
+impl DisplaySettings {
+    /// Settings which aren't saved anywhere.
+    pub fn new(display: DisplayConfig) -> Self {
+        Self {
+            display: display,
+            ui_scale: 1.0,
+            restart_required: false,
+            path: None,
+        }
+    }
+
+    /// Loads the display config at `path` and the UI settings next to it, falling back to the
+    /// defaults with a warning for either file that is missing or invalid.
+    pub fn load<P: AsRef<Path>>(path: P) -> Self {
+        let path = path.as_ref();
+        let display = DisplayConfig::load_no_fallback(path).unwrap_or_else(|e| {
+            eprintln!(
+                "WARNING: Failed to load display config {}, using defaults: {}",
+                path.display(),
+                e
+            );
+            DisplayConfig::default()
+        });
+        let ui_path = ui_settings_path(path);
+        let ui_scale = match load_ui_settings(&ui_path) {
+            Ok(ui) => ui.ui_scale,
+            Err(e) => {
+                if ui_path.exists() {
+                    eprintln!(
+                        "WARNING: Failed to load UI settings {}, using defaults: {}",
+                        ui_path.display(),
+                        e
+                    );
+                }
+                1.0
+            }
+        };
+        Self {
+            display: display,
+            ui_scale: ui_scale,
+            restart_required: false,
+            path: Some(path.to_path_buf()),
+        }
+    }
+
+    /// Writes the settings back to the files they were loaded from.
+    pub fn save(&self) -> Result<(), String> {
+        let path = match self.path {
+            Some(ref path) => path,
+            None => return Ok(()),
+        };
+        write_ron(path, &self.display)?;
+        write_ron(
+            &ui_settings_path(path),
+            &UiSettingsFile {
+                ui_scale: self.ui_scale,
+            },
+        )
+    }
+
+    /// Settings as sent to the settings page.
+    pub fn snapshot(&self) -> Value {
+        json!({
+            "dimensions": self.display.dimensions,
+            "fullscreen": self.display.fullscreen,
+            "vsync": self.display.vsync,
+            "multisampling": self.display.multisampling,
+            "ui_scale": self.ui_scale,
+            "restart_required": self.restart_required,
+        })
+    }
+
+    /// Applies the fields set in `change` to the settings and, where possible, to the window.
+    pub fn apply(&mut self, window: &GlWindow, change: SettingsChange) -> Result<(), String> {
+        if let Some(multisampling) = change.multisampling {
+            if !multisampling.is_power_of_two() || multisampling > 16 {
+                return Err(format!("Invalid multisampling level {}", multisampling));
+            }
+        }
+        if let Some(scale) = change.ui_scale {
+            if !(scale >= MIN_UI_SCALE && scale <= MAX_UI_SCALE) {
+                return Err(format!(
+                    "UI scale must be between {} and {}",
+                    MIN_UI_SCALE, MAX_UI_SCALE
+                ));
+            }
+        }
+        if let Some((width, height)) = change.dimensions {
+            if width == 0 || height == 0 {
+                return Err(format!("Invalid resolution {}x{}", width, height));
+            }
+            window.set_inner_size(width, height);
+            self.display.dimensions = Some((width, height));
+        }
+        if let Some(fullscreen) = change.fullscreen {
+            if fullscreen {
+                window.set_fullscreen(Some(window.get_current_monitor()));
+            } else {
+                window.set_fullscreen(None);
+            }
+            self.display.fullscreen = fullscreen;
+        }
+        if let Some(vsync) = change.vsync {
+            self.restart_required |= vsync != self.display.vsync;
+            self.display.vsync = vsync;
+        }
+        if let Some(multisampling) = change.multisampling {
+            self.restart_required |= multisampling != self.display.multisampling;
+            self.display.multisampling = multisampling;
+        }
+        if let Some(scale) = change.ui_scale {
+            self.ui_scale = scale;
+        }
+        self.save()
+    }
+}
+
+/// Arguments of `settings.set`. Fields left out are unchanged.
+#[derive(Debug, Default, Deserialize)]
+pub struct SettingsChange {
+    pub dimensions: Option<(u32, u32)>,
+    pub fullscreen: Option<bool>,
+    pub vsync: Option<bool>,
+    pub multisampling: Option<u16>,
+    pub ui_scale: Option<f32>,
+}
+
+fn ui_settings_path(display_path: &Path) -> PathBuf {
+    display_path.with_file_name("ui_settings.ron")
+}
+
+fn load_ui_settings(path: &Path) -> Result<UiSettingsFile, String> {
+    let file = File::open(path).map_err(|e| e.to_string())?;
+    ron::de::from_reader(file).map_err(|e| e.to_string())
+}
+
+fn write_ron<T: Serialize>(path: &Path, value: &T) -> Result<(), String> {
+    let text = ron::ser::to_string_pretty(value, ron::ser::PrettyConfig::default())
+        .map_err(|e| e.to_string())?;
+    File::create(path)
+        .and_then(|mut file| file.write_all(text.as_bytes()))
+        .map_err(|e| format!("Failed to save {}: {}", path.display(), e))
+}
+
+/// Registers the `settings` binding and the `settings.set` call used by the settings page.
+pub fn register_settings(bindings: &mut UiBindings, rpc: &mut RpcRegistry) {
+    bindings.observe("settings", |res| {
+        res.fetch::<DisplaySettings>(0).snapshot()
+    });
+    rpc.register("settings.set", |res, change: SettingsChange| {
+        let window = res.fetch::<Arc<GlWindow>>(0);
+        res.fetch_mut::<DisplaySettings>(0).apply(&window, change)
+    });
+}
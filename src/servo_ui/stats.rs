@@ -0,0 +1,50 @@
+use std::time::{Duration, Instant};
+
+/// Counts how often Servo actually composites compared to how often the UI system runs, so the
+/// saving from only pumping Servo while the page is animating or has new content can be seen.
+pub struct CompositeStats {
+    /// Composites in the last complete one second window.
+    pub composites_per_second: u32,
+    /// Frames in the last complete one second window where Servo was left idle and the previous
+    /// UI texture was reused.
+    pub idle_frames_per_second: u32,
+    /// Composites since startup.
+    pub total_composites: u64,
+    /// Frames since startup where Servo was left idle.
+    pub total_idle_frames: u64,
+    window_start: Instant,
+    window_composites: u32,
+    window_idle_frames: u32,
+}
+
+impl CompositeStats {
+    pub fn new() -> Self {
+        Self {
+            composites_per_second: 0,
+            idle_frames_per_second: 0,
+            total_composites: 0,
+            total_idle_frames: 0,
+            window_start: Instant::now(),
+            window_composites: 0,
+            window_idle_frames: 0,
+        }
+    }
+
+    /// Records one run of the UI system, along with how many composites happened during it and
+    /// whether Servo was pumped at all.
+    pub fn record_frame(&mut self, composites: u32, pumped: bool) {
+        self.total_composites += composites as u64;
+        self.window_composites += composites;
+        if !pumped {
+            self.total_idle_frames += 1;
+            self.window_idle_frames += 1;
+        }
+        if self.window_start.elapsed() >= Duration::from_secs(1) {
+            self.composites_per_second = self.window_composites;
+            self.idle_frames_per_second = self.window_idle_frames;
+            self.window_composites = 0;
+            self.window_idle_frames = 0;
+            self.window_start = Instant::now();
+        }
+    }
+}
@@ -1,24 +1,53 @@
 extern crate genmesh;
 
-use amethyst::ecs::{RunningTime, System};
+use std::sync::mpsc::Receiver;
+use std::time::Instant;
+use amethyst::ecs::{Entity, RunningTime, System, WriteStorage};
 use amethyst::core::cgmath::Matrix4;
 use amethyst::prelude::World;
-use super::ServoHandle;
+use super::{capture_servo_reports, CaretPosition, CompositeStats, CursorMode, DisplaySettings,
+            FrameUpload, ImeCaret, ImeComposition, InputEvent, InputRecorder, InputReplay,
+            InputRouter, PageKey, PerfStats, PointerLock, Route, RoutedEvent, ServoHandle,
+            ServoReport, ServoUiConfig, SwallowableKeys, UiMessage, UiOutbox, UiPages,
+            UnhandledKey, INSPECTOR_PAGE, PERF_PAGE};
 use amethyst::renderer::{Material, MaterialDefaults, MeshHandle, PosTex, Texture, TextureData,
                          TextureHandle, TextureMetadata};
 use amethyst::core::transform::GlobalTransform;
-use amethyst::winit::Event;
+use amethyst::winit::{DeviceEvent, ElementState, Event, VirtualKeyCode, WindowEvent};
+use serde_json::Value;
 use amethyst::shrev::{EventChannel, ReaderId};
-use amethyst::shred::Fetch;
+use amethyst::shred::{Fetch, FetchMut};
 use amethyst::assets::{AssetStorage, Loader};
 
 pub struct ServoUiSystem {
     reader_id: ReaderId<Event>,
     servo: ServoHandle,
+    offscreen_ready: bool,
+    // Shows the UI texture, its material is pointed at each new texture made for readback
+    ui_entity: Entity,
+    console_key: Option<VirtualKeyCode>,
+    inspector_key: Option<VirtualKeyCode>,
+    inspector_page: PageKey,
+    perf_key: Option<VirtualKeyCode>,
+    router: InputRouter,
+    pointer_lock: PointerLock,
+    swallowable_keys: SwallowableKeys,
+    // UI scale last passed to Servo
+    ui_scale: f32,
+    // Start of the previous run, for measuring frame time
+    last_run: Option<Instant>,
+    recorder: Option<InputRecorder>,
+    replay: Option<InputReplay>,
+    servo_reports: Option<Receiver<ServoReport>>,
+    // Set when the console key is pressed, so the character it types isn't sent to the page
+    swallow_character: bool,
+    // Caret the platform candidate window was last moved to
+    ime_spot: Option<CaretPosition>,
 }
 
 impl ServoUiSystem {
-    pub fn new(world: &mut World) -> Self {
+    /// Sets up the UI texture and starts Servo, failing if Servo can't be started.
+    pub fn new(world: &mut World, config: ServoUiConfig) -> Result<Self, String> {
         let target_handle: TextureHandle = {
             let tex_storage = world.read_resource();
             let loader = world.read_resource::<Loader>();
@@ -35,7 +64,37 @@ impl ServoUiSystem {
             );
             loader.load_from_data(texture_data, (), &tex_storage)
         };
-        world.add_resource(ServoTarget::new(target_handle));
+        world.add_resource(ServoTarget::new(target_handle.clone()));
+        let recorder = config
+            .record_input
+            .as_ref()
+            .and_then(|path| match InputRecorder::create(path) {
+                Ok(recorder) => Some(recorder),
+                Err(e) => {
+                    eprintln!("ERROR: Input won't be recorded: {}", e);
+                    None
+                }
+            });
+        let replay = config
+            .replay_input
+            .as_ref()
+            .and_then(|path| match InputReplay::load(path) {
+                Ok(replay) => Some(replay),
+                Err(e) => {
+                    eprintln!("ERROR: Input won't be replayed: {}", e);
+                    None
+                }
+            });
+        let servo_reports = config
+            .servo_profiler_period
+            .and_then(|_| match capture_servo_reports() {
+                Ok(reports) => Some(reports),
+                Err(e) => {
+                    eprintln!("WARNING: Servo's profiler reports won't reach the overlay: {}", e);
+                    None
+                }
+            });
+        world.add_resource(CompositeStats::new());
         let mat_defaults = world.read_resource::<MaterialDefaults>().0.clone();
         let mesh_handle: MeshHandle = world.read_resource::<Loader>().load_from_data(
             vec![
@@ -67,35 +126,152 @@ impl ServoUiSystem {
             (),
             &world.read_resource(),
         );
-        world
+        let ui_entity = world
             .create_entity()
             .with(GlobalTransform(Matrix4::from_translation(
                 [0., 0., 0.].into(),
             )))
             .with(Material {
+                albedo: target_handle,
                 ..mat_defaults.clone()
             })
-            .with(mesh_handle);
-        Self {
+            .with(mesh_handle)
+            .build();
+        Ok(Self {
             reader_id: world
                 .write_resource::<EventChannel<Event>>()
                 .register_reader(),
-            servo: ServoHandle::start_servo(world),
+            servo: ServoHandle::start_servo(world, &config)?,
+            offscreen_ready: false,
+            ui_entity: ui_entity,
+            console_key: config.console_key,
+            inspector_key: config.inspector_key,
+            inspector_page: PageKey::new(),
+            perf_key: config.perf_key,
+            router: InputRouter::new(config.hit_test_alpha),
+            pointer_lock: PointerLock::new(),
+            swallowable_keys: config.swallowable_keys.clone(),
+            ui_scale: 1.0,
+            last_run: None,
+            recorder: recorder,
+            replay: replay,
+            servo_reports: servo_reports,
+            swallow_character: false,
+            ime_spot: None,
+        })
+    }
+
+    /// Toggles the developer console, the inspector or the performance overlay when their key is
+    /// pressed. Returns true if the event belonged to one of the keys and shouldn't reach the
+    /// page.
+    fn handle_debug_keys(&mut self, event: &WindowEvent, pages: &mut UiPages) -> bool {
+        match event {
+            &WindowEvent::KeyboardInput { input, .. }
+                if input.virtual_keycode.is_some() && input.virtual_keycode == self.inspector_key =>
+            {
+                if input.state == ElementState::Pressed {
+                    // In its own browser, so it survives the current page navigating
+                    pages.toggle(self.inspector_page, INSPECTOR_PAGE);
+                }
+                true
+            }
+            &WindowEvent::KeyboardInput { input, .. }
+                if input.virtual_keycode.is_some() && input.virtual_keycode == self.console_key =>
+            {
+                if input.state == ElementState::Pressed {
+                    self.swallow_character = true;
+                    self.servo.send_messages(vec![
+                        (None, "console.toggle".to_string(), Value::Null),
+                    ]);
+                }
+                true
+            }
+            &WindowEvent::KeyboardInput { input, .. }
+                if input.virtual_keycode.is_some() && input.virtual_keycode == self.perf_key =>
+            {
+                if input.state == ElementState::Pressed {
+                    match self.servo.page_url(PERF_PAGE) {
+                        Ok(url) => self.servo.send_messages(vec![
+                            (None, "perf.toggle".to_string(), json!({ "url": url })),
+                        ]),
+                        Err(e) => eprintln!("ERROR: Can't show the performance overlay: {}", e),
+                    }
+                }
+                true
+            }
+            &WindowEvent::ReceivedCharacter(_) if self.swallow_character => {
+                self.swallow_character = false;
+                true
+            }
+            _ => false,
         }
     }
-}
 
-impl<'a> System<'a> for ServoUiSystem {
-    type SystemData = (
-        Fetch<'a, EventChannel<Event>>,
-        Fetch<'a, ServoTarget>,
-        Fetch<'a, AssetStorage<Texture>>,
-    );
-    fn running_time(&self) -> RunningTime {
-        RunningTime::Average
+    /// Keeps cursor moves from the page while it holds a pointer lock, and exits the lock on
+    /// Escape or when the window loses focus. Returns true if the event shouldn't reach the page,
+    /// and sets `lock_changed` if the lock was exited.
+    fn handle_pointer_lock(&mut self, event: &WindowEvent, lock_changed: &mut bool) -> bool {
+        if !self.pointer_lock.is_locked() {
+            return false;
+        }
+        match event {
+            &WindowEvent::CursorMoved { .. } => true,
+            &WindowEvent::KeyboardInput { input, .. }
+                if input.virtual_keycode == Some(VirtualKeyCode::Escape) =>
+            {
+                if input.state == ElementState::Pressed {
+                    *lock_changed |= self.pointer_lock.release();
+                }
+                true
+            }
+            &WindowEvent::Focused(false) => {
+                *lock_changed |= self.pointer_lock.release();
+                false
+            }
+            _ => false,
+        }
+    }
+
+    /// Publishes presses of keys pages may not swallow as soon as they are sent to the page,
+    /// rather than waiting to see whether it handles them.
+    fn pass_key_through(&self, event: &WindowEvent, unhandled: &mut EventChannel<UnhandledKey>) {
+        if let &WindowEvent::KeyboardInput { input, .. } = event {
+            match input.virtual_keycode {
+                Some(key)
+                    if input.state == ElementState::Pressed
+                        && !self.swallowable_keys.may_swallow(key) =>
+                {
+                    unhandled.single_write(UnhandledKey {
+                        key: key,
+                        character: None,
+                        modifiers: input.modifiers,
+                    })
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// While replaying, drops the live input from `events` and returns the recorded events due
+    /// this frame.
+    fn replay_input(&mut self, events: &mut Vec<Event>) -> Vec<Event> {
+        let (replayed, finished) = match self.replay {
+            Some(ref mut replay) => {
+                let window = self.servo.window.window.id();
+                (replay.replay_frame(events, window), replay.is_finished())
+            }
+            None => return vec![],
+        };
+        if finished {
+            eprintln!("Input replay finished, live input resumes");
+            self.replay = None;
+        }
+        replayed
     }
 
-    fn run(&mut self, (events, target, tex_storage): Self::SystemData) {
+    /// Points Servo's framebuffer at the GL texture behind the target handle once it has been
+    /// loaded.
+    fn bind_shared_target(&mut self, target: &ServoTarget, tex_storage: &AssetStorage<Texture>) {
         match self.servo.window.has_target() {
             Ok(false) => match tex_storage.get(&target.handle) {
                 Some(t) => {
@@ -111,21 +287,252 @@ impl<'a> System<'a> for ServoUiSystem {
             },
             _ => {}
         }
-        for event in events.read(&mut self.reader_id) {
-            match event {
-                &Event::Awakened => {
-                    self.servo.update();
+    }
+
+    /// Copies the last frame read back from Servo into the target texture. A new dynamic texture
+    /// is only made when the frame size changes, and the target handle and the UI material are
+    /// pointed at it.
+    fn upload_frame(
+        &mut self,
+        target: &mut ServoTarget,
+        loader: &Loader,
+        tex_storage: &AssetStorage<Texture>,
+        materials: &mut WriteStorage<Material>,
+    ) {
+        if !self.offscreen_ready {
+            match self.servo.window.setup_offscreen_framebuffer() {
+                Ok(()) => println!("Setup offscreen framebuffer for readback"),
+                Err(e) => eprintln!("Failed to setup offscreen framebuffer: {:?}", e),
+            }
+            // Don't retry every frame if the framebuffer can't be created
+            self.offscreen_ready = true;
+        } else {
+            // Before Servo composites at the new size
+            self.servo.window.fit_offscreen_framebuffer();
+        }
+        let frame = match self.servo.window.take_frame() {
+            Some(frame) => frame,
+            None => return,
+        };
+        // A new texture is made for each frame through the loader, since writing into amethyst's
+        // texture would need Servo's GL context to share gfx's resources, which this mode avoids
+        let texture_data = TextureData::U8(
+            frame.pixels,
+            TextureMetadata {
+                sampler: None,
+                mip_levels: Some(1),
+                size: Some((frame.width as u16, frame.height as u16)),
+                dynamic: true,
+                format: None,
+                channel: None,
+            },
+        );
+        target.handle = loader.load_from_data(texture_data, (), tex_storage);
+        if let Some(material) = materials.get_mut(self.ui_entity) {
+            material.albedo = target.handle.clone();
+        }
+    }
+}
+
+impl<'a> System<'a> for ServoUiSystem {
+    type SystemData = (
+        Fetch<'a, EventChannel<Event>>,
+        FetchMut<'a, ServoTarget>,
+        Fetch<'a, AssetStorage<Texture>>,
+        Fetch<'a, Loader>,
+        FetchMut<'a, CompositeStats>,
+        FetchMut<'a, EventChannel<UiMessage>>,
+        FetchMut<'a, UiOutbox>,
+        FetchMut<'a, UiPages>,
+        FetchMut<'a, PerfStats>,
+        Fetch<'a, DisplaySettings>,
+        FetchMut<'a, EventChannel<RoutedEvent>>,
+        Fetch<'a, CursorMode>,
+        FetchMut<'a, EventChannel<UnhandledKey>>,
+        WriteStorage<'a, Material>,
+        Fetch<'a, ImeCaret>,
+        FetchMut<'a, ImeComposition>,
+    );
+    fn running_time(&self) -> RunningTime {
+        RunningTime::Average
+    }
+
+    fn run(
+        &mut self,
+        (
+            events,
+            mut target,
+            tex_storage,
+            loader,
+            mut stats,
+            mut messages,
+            mut outbox,
+            mut pages,
+            mut perf,
+            settings,
+            mut routed,
+            cursor_mode,
+            mut unhandled,
+            mut materials,
+            caret,
+            mut composition,
+        ): Self::SystemData,
+    ) {
+        let started = Instant::now();
+        if let Some(last_run) = self.last_run {
+            perf.record_frame(started.duration_since(last_run));
+        }
+        if let Some(ref reports) = self.servo_reports {
+            for report in reports.try_iter() {
+                perf.record_servo_report(report);
+            }
+        }
+        self.last_run = Some(started);
+
+        match self.servo.window.upload {
+            FrameUpload::SharedTexture => self.bind_shared_target(&target, &tex_storage),
+            FrameUpload::Readback => {
+                self.upload_frame(&mut target, &loader, &tex_storage, &mut materials)
+            }
+        }
+        if settings.ui_scale != self.ui_scale {
+            self.ui_scale = settings.ui_scale;
+            self.servo.set_ui_scale(settings.ui_scale);
+        }
+        let page_requests = pages.drain_requests();
+        if !page_requests.is_empty() {
+            self.servo.apply_page_requests(page_requests);
+            pages.set_browsers(self.servo.pages.clone());
+        }
+        pages.set_current(self.servo.browser);
+
+        let mut lock_changed = false;
+        let inbox = self.servo.window.take_messages();
+        for message in inbox.iter() {
+            self.router.handle_message(message);
+            lock_changed |= self.pointer_lock.handle_message(&message.name);
+        }
+        messages.iter_write(inbox);
+        let outgoing = outbox.drain();
+        if !outgoing.is_empty() {
+            self.servo.send_messages(outgoing);
+        }
+
+        let mut awakened = false;
+        let mut forwarded = false;
+        let mut events: Vec<Event> = events.read(&mut self.reader_id).cloned().collect();
+        let replayed = self.replay_input(&mut events);
+        // Replayed events follow the live ones left
+        let live_count = events.len();
+        events.extend(replayed);
+        if let Some(ref mut recorder) = self.recorder {
+            recorder.record_frame(&events);
+        }
+        for (index, event) in events.into_iter().enumerate() {
+            let ui_consumed = match event {
+                Event::Awakened => {
+                    // Servo has new messages (usually a new display list), but several wakeups
+                    // in one frame only need one pump
+                    awakened = true;
+                    false
                 }
-                &Event::WindowEvent {
+                Event::WindowEvent {
                     window_id: _window_id,
                     ref event,
                 } => {
-                    self.servo.forward_events(vec![event.clone()]);
-                    // Send the resize through to servo
+                    if self.handle_debug_keys(event, &mut pages)
+                        || self.handle_pointer_lock(event, &mut lock_changed)
+                        || composition.swallows(event)
+                    {
+                        true
+                    } else {
+                        let route = {
+                            let window = &self.servo.window;
+                            self.router.route(event, |x, y| window.alpha_at(x, y))
+                        };
+                        if route != Route::Game {
+                            // Includes resizes, which Servo needs to see
+                            self.servo.forward_events(vec![event.clone()]);
+                            forwarded = true;
+                        }
+                        if route == Route::Ui {
+                            self.pass_key_through(event, &mut unhandled);
+                        }
+                        route == Route::Ui
+                    }
                 }
-                _ => {}
+                Event::DeviceEvent {
+                    event: DeviceEvent::MouseMotion { delta },
+                    ..
+                } if self.pointer_lock.is_locked() =>
+                {
+                    self.pointer_lock.add_motion(delta);
+                    true
+                }
+                _ => false,
+            };
+            routed.single_write(RoutedEvent {
+                event: event,
+                ui_consumed: ui_consumed,
+                replayed: index >= live_count,
+            });
+        }
+        composition.end_frame();
+        self.pointer_lock
+            .apply(*cursor_mode, &self.servo.window.window);
+        if caret.position != self.ime_spot {
+            self.ime_spot = caret.position;
+            if let Some(position) = caret.position {
+                self.servo
+                    .window
+                    .window
+                    .set_ime_spot(position.x as i32, position.y as i32);
             }
         }
+        let mut pointer_messages = vec![];
+        if lock_changed {
+            pointer_messages.push((
+                None,
+                "pointer.lockchange".to_string(),
+                json!({ "locked": self.pointer_lock.is_locked() }),
+            ));
+        }
+        if let Some((dx, dy)) = self.pointer_lock.take_motion() {
+            pointer_messages.push((
+                None,
+                "pointer.motion".to_string(),
+                json!({ "dx": dx, "dy": dy }),
+            ));
+        }
+        if !pointer_messages.is_empty() {
+            self.servo.send_messages(pointer_messages);
+        }
+        if self.router.take_blur_request() {
+            self.servo
+                .send_messages(vec![(None, "input.blur".to_string(), Value::Null)]);
+        }
+        // Only composite while the page is animating or has new content, otherwise the last UI
+        // texture is reused as is
+        let pumped = awakened || forwarded || self.servo.window.is_animating();
+        if awakened || self.servo.window.is_animating() {
+            self.servo.update();
+        } else {
+            // Nothing composites this frame to push the last frame out of the pixel buffers
+            self.servo.window.flush_readback();
+        }
+        // Keys pages may not swallow were published when pressed
+        let unhandled_keys: Vec<UnhandledKey> = self.servo
+            .window
+            .take_unhandled_keys()
+            .into_iter()
+            .filter(|key| self.swallowable_keys.may_swallow(key.key))
+            .collect();
+        unhandled.iter_write(unhandled_keys);
+        stats.record_frame(self.servo.window.take_composite_count(), pumped);
+        perf.record_composite(self.servo.window.take_composite_time());
+        perf.composites_per_second = stats.composites_per_second;
+        perf.texture_bytes = self.servo.window.texture_bytes();
+        perf.record_system("servo_ui", started.elapsed());
     }
 }
 
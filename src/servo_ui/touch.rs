@@ -0,0 +1,313 @@
+extern crate glutin;
+extern crate servo as libservo;
+
+use std::collections::HashMap;
+use self::glutin::TouchPhase;
+use self::libservo::compositing::windowing::{MouseWindowEvent, WindowEvent};
+use self::libservo::euclid::TypedPoint2D;
+use self::libservo::script_traits::{MouseButton, TouchEventType, TouchId};
+use self::libservo::style_traits::DevicePixel;
+
+// Furthest a finger can move between touching and lifting for it to still count as a tap
+const TAP_DISTANCE: f32 = 20.0;
+
+/// Turns winit touches into Servo touch events, one touch id per finger.
+///
+/// With mouse emulation, a finger touching down while no other finger is down drives the mouse
+/// instead, moving and pressing the left button, and a tap clicks. Fingers joining it are still
+/// sent as touches. With pinch zoom, moving two fingers apart or together zooms the page rather
+/// than sending their moves.
+///
+/// Takes the touch fields rather than winit events, so synthetic touches are easy to feed in.
+pub struct TouchTranslator {
+    emulate_mouse: bool,
+    pinch_zoom: bool,
+    touches: HashMap<u64, TypedPoint2D<f32, DevicePixel>>,
+    // Finger driving the emulated mouse and where it touched down
+    mouse_finger: Option<(u64, TypedPoint2D<f32, DevicePixel>)>,
+    // Distance between the two fingers as of the last pinch event
+    pinch_distance: Option<f32>,
+}
+
+impl TouchTranslator {
+    pub fn new(emulate_mouse: bool, pinch_zoom: bool) -> Self {
+        Self {
+            emulate_mouse: emulate_mouse,
+            pinch_zoom: pinch_zoom,
+            touches: HashMap::new(),
+            mouse_finger: None,
+            pinch_distance: None,
+        }
+    }
+
+    pub fn translate(
+        &mut self,
+        phase: TouchPhase,
+        id: u64,
+        location: (f64, f64),
+    ) -> Vec<WindowEvent> {
+        let point = TypedPoint2D::new(location.0 as f32, location.1 as f32);
+        match phase {
+            TouchPhase::Started => self.touch_started(id, point),
+            TouchPhase::Moved => self.touch_moved(id, point),
+            TouchPhase::Ended | TouchPhase::Cancelled => {
+                self.touch_ended(id, point, phase == TouchPhase::Ended)
+            }
+        }
+    }
+
+    fn touch_started(
+        &mut self,
+        id: u64,
+        point: TypedPoint2D<f32, DevicePixel>,
+    ) -> Vec<WindowEvent> {
+        self.touches.insert(id, point);
+        self.update_pinch();
+        if self.emulate_mouse && self.touches.len() == 1 {
+            self.mouse_finger = Some((id, point));
+            return vec![
+                WindowEvent::MouseWindowMoveEventClass(point),
+                WindowEvent::MouseWindowEventClass(MouseWindowEvent::MouseDown(
+                    MouseButton::Left,
+                    point,
+                )),
+            ];
+        }
+        vec![WindowEvent::Touch(TouchEventType::Down, TouchId(id as i32), point)]
+    }
+
+    fn touch_moved(
+        &mut self,
+        id: u64,
+        point: TypedPoint2D<f32, DevicePixel>,
+    ) -> Vec<WindowEvent> {
+        match self.touches.get_mut(&id) {
+            Some(touch) => *touch = point,
+            None => return vec![],
+        }
+        if let Some(previous) = self.pinch_distance {
+            let distance = self.finger_distance();
+            self.pinch_distance = Some(distance);
+            return if previous > 0. && distance > 0. {
+                vec![WindowEvent::PinchZoom(distance / previous)]
+            } else {
+                vec![]
+            };
+        }
+        match self.mouse_finger {
+            Some((finger, _)) if finger == id => {
+                vec![WindowEvent::MouseWindowMoveEventClass(point)]
+            }
+            _ => vec![
+                WindowEvent::Touch(TouchEventType::Move, TouchId(id as i32), point),
+            ],
+        }
+    }
+
+    fn touch_ended(
+        &mut self,
+        id: u64,
+        point: TypedPoint2D<f32, DevicePixel>,
+        ended: bool,
+    ) -> Vec<WindowEvent> {
+        if self.touches.remove(&id).is_none() {
+            return vec![];
+        }
+        self.update_pinch();
+        match self.mouse_finger {
+            Some((finger, start)) if finger == id => {
+                self.mouse_finger = None;
+                let mut events = vec![WindowEvent::MouseWindowEventClass(
+                    MouseWindowEvent::MouseUp(MouseButton::Left, point),
+                )];
+                if ended && (point - start).length() < TAP_DISTANCE {
+                    events.push(WindowEvent::MouseWindowEventClass(
+                        MouseWindowEvent::Click(MouseButton::Left, point),
+                    ));
+                }
+                events
+            }
+            _ => {
+                let kind = if ended {
+                    TouchEventType::Up
+                } else {
+                    TouchEventType::Cancel
+                };
+                vec![WindowEvent::Touch(kind, TouchId(id as i32), point)]
+            }
+        }
+    }
+
+    /// Starts pinching when exactly two fingers are down, and stops otherwise.
+    fn update_pinch(&mut self) {
+        self.pinch_distance = if self.pinch_zoom && self.touches.len() == 2 {
+            Some(self.finger_distance())
+        } else {
+            None
+        };
+    }
+
+    fn finger_distance(&self) -> f32 {
+        let mut points = self.touches.values();
+        match (points.next(), points.next()) {
+            (Some(a), Some(b)) => (*a - *b).length(),
+            _ => 0.,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Servo's WindowEvent has neither PartialEq nor a Debug showing its fields
+    fn describe(event: &WindowEvent) -> String {
+        match event {
+            &WindowEvent::Touch(ref kind, TouchId(id), point) => {
+                format!("touch {:?} {} at {},{}", kind, id, point.x, point.y)
+            }
+            &WindowEvent::MouseWindowMoveEventClass(point) => {
+                format!("mouse move to {},{}", point.x, point.y)
+            }
+            &WindowEvent::MouseWindowEventClass(ref event) => match event {
+                &MouseWindowEvent::MouseDown(MouseButton::Left, _) => "mouse down".to_string(),
+                &MouseWindowEvent::MouseUp(MouseButton::Left, _) => "mouse up".to_string(),
+                &MouseWindowEvent::Click(MouseButton::Left, _) => "click".to_string(),
+                _ => "other mouse event".to_string(),
+            },
+            &WindowEvent::PinchZoom(magnification) => format!("pinch {}", magnification),
+            _ => "other event".to_string(),
+        }
+    }
+
+    fn translate(
+        touches: &mut TouchTranslator,
+        phase: TouchPhase,
+        id: u64,
+        x: f64,
+        y: f64,
+    ) -> Vec<String> {
+        touches
+            .translate(phase, id, (x, y))
+            .iter()
+            .map(describe)
+            .collect()
+    }
+
+    #[test]
+    fn each_finger_keeps_its_touch_id() {
+        let mut touches = TouchTranslator::new(false, false);
+        assert_eq!(
+            translate(&mut touches, TouchPhase::Started, 5, 10., 20.),
+            vec!["touch Down 5 at 10,20"]
+        );
+        assert_eq!(
+            translate(&mut touches, TouchPhase::Started, 9, 30., 40.),
+            vec!["touch Down 9 at 30,40"]
+        );
+        assert_eq!(
+            translate(&mut touches, TouchPhase::Moved, 9, 35., 40.),
+            vec!["touch Move 9 at 35,40"]
+        );
+        assert_eq!(
+            translate(&mut touches, TouchPhase::Ended, 5, 10., 20.),
+            vec!["touch Up 5 at 10,20"]
+        );
+        assert_eq!(
+            translate(&mut touches, TouchPhase::Cancelled, 9, 35., 40.),
+            vec!["touch Cancel 9 at 35,40"]
+        );
+        // Fingers which aren't down are ignored
+        assert!(translate(&mut touches, TouchPhase::Moved, 9, 0., 0.).is_empty());
+        assert!(translate(&mut touches, TouchPhase::Ended, 9, 0., 0.).is_empty());
+    }
+
+    #[test]
+    fn emulated_mouse_clicks_on_a_tap() {
+        let mut touches = TouchTranslator::new(true, false);
+        assert_eq!(
+            translate(&mut touches, TouchPhase::Started, 1, 100., 100.),
+            vec!["mouse move to 100,100", "mouse down"]
+        );
+        assert_eq!(
+            translate(&mut touches, TouchPhase::Moved, 1, 110., 100.),
+            vec!["mouse move to 110,100"]
+        );
+        let just_inside = 100. + TAP_DISTANCE as f64 - 1.;
+        assert_eq!(
+            translate(&mut touches, TouchPhase::Ended, 1, just_inside, 100.),
+            vec!["mouse up", "click"]
+        );
+    }
+
+    #[test]
+    fn emulated_mouse_drags_without_clicking() {
+        let mut touches = TouchTranslator::new(true, false);
+        translate(&mut touches, TouchPhase::Started, 1, 100., 100.);
+        let tap_distance = 100. + TAP_DISTANCE as f64;
+        assert_eq!(
+            translate(&mut touches, TouchPhase::Ended, 1, 100., tap_distance),
+            vec!["mouse up"]
+        );
+
+        // A cancelled touch never clicks, however short
+        translate(&mut touches, TouchPhase::Started, 2, 100., 100.);
+        assert_eq!(
+            translate(&mut touches, TouchPhase::Cancelled, 2, 100., 100.),
+            vec!["mouse up"]
+        );
+    }
+
+    #[test]
+    fn fingers_joining_the_emulated_mouse_are_touches() {
+        let mut touches = TouchTranslator::new(true, false);
+        translate(&mut touches, TouchPhase::Started, 1, 0., 0.);
+        assert_eq!(
+            translate(&mut touches, TouchPhase::Started, 2, 50., 50.),
+            vec!["touch Down 2 at 50,50"]
+        );
+        assert_eq!(
+            translate(&mut touches, TouchPhase::Moved, 1, 5., 0.),
+            vec!["mouse move to 5,0"]
+        );
+        assert_eq!(
+            translate(&mut touches, TouchPhase::Ended, 2, 50., 50.),
+            vec!["touch Up 2 at 50,50"]
+        );
+    }
+
+    #[test]
+    fn pinch_only_with_exactly_two_fingers() {
+        let mut touches = TouchTranslator::new(false, true);
+        translate(&mut touches, TouchPhase::Started, 1, 0., 0.);
+        translate(&mut touches, TouchPhase::Started, 2, 100., 0.);
+        assert_eq!(
+            translate(&mut touches, TouchPhase::Moved, 2, 200., 0.),
+            vec!["pinch 2"]
+        );
+
+        // A third finger stops the pinch
+        assert_eq!(
+            translate(&mut touches, TouchPhase::Started, 3, 50., 50.),
+            vec!["touch Down 3 at 50,50"]
+        );
+        assert_eq!(
+            translate(&mut touches, TouchPhase::Moved, 2, 300., 0.),
+            vec!["touch Move 2 at 300,0"]
+        );
+
+        // Lifting it leaves two fingers, which pinch again from where they are
+        translate(&mut touches, TouchPhase::Ended, 3, 50., 50.);
+        assert_eq!(
+            translate(&mut touches, TouchPhase::Moved, 2, 150., 0.),
+            vec!["pinch 0.5"]
+        );
+
+        // As does lifting one of the pinching fingers
+        translate(&mut touches, TouchPhase::Ended, 1, 0., 0.);
+        assert_eq!(
+            translate(&mut touches, TouchPhase::Moved, 2, 120., 0.),
+            vec!["touch Move 2 at 120,0"]
+        );
+    }
+}
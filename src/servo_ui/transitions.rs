@@ -0,0 +1,174 @@
+use std::collections::{HashMap, VecDeque};
+use amethyst::prelude::*;
+use amethyst::shred::Resources;
+use super::RpcRegistry;
+
+/// States pages can push or switch to, looked up by name, and how many states are on the stack
+/// above the root state.
+pub struct StateRegistry {
+    factories: HashMap<String, Box<Fn() -> Box<State> + Send + Sync>>,
+    depth: usize,
+}
+
+impl StateRegistry {
+    pub fn new() -> Self {
+        Self {
+            factories: HashMap::new(),
+            depth: 0,
+        }
+    }
+
+    /// States pushed above the root state. Pushes and pops returned by ui_transition are counted,
+    /// so games which push or pop states themselves as well should keep this up to date with
+    /// `set_depth`.
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    pub fn set_depth(&mut self, depth: usize) {
+        self.depth = depth;
+    }
+
+    /// Registers a function building the state `name` each time a page asks for it.
+    pub fn register<F>(&mut self, name: &str, factory: F)
+    where
+        F: Fn() -> Box<State> + Send + Sync + 'static,
+    {
+        self.factories.insert(name.to_string(), Box::new(factory));
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.factories.contains_key(name)
+    }
+
+    pub fn build(&self, name: &str) -> Option<Box<State>> {
+        self.factories.get(name).map(|factory| factory())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TransitionRequest {
+    Push(String),
+    Pop,
+    Switch(String),
+    Quit,
+}
+
+/// Transitions requested by pages, waiting for the active state to return them.
+pub struct UiTransitions {
+    requests: VecDeque<TransitionRequest>,
+}
+
+impl UiTransitions {
+    pub fn new() -> Self {
+        Self {
+            requests: VecDeque::new(),
+        }
+    }
+
+    pub fn request(&mut self, request: TransitionRequest) {
+        self.requests.push_back(request);
+    }
+
+    pub fn next(&mut self) -> Option<TransitionRequest> {
+        self.requests.pop_front()
+    }
+}
+
+/// Takes the next transition requested by a page and turns it into a `Trans` for the active
+/// state to return. Returns `Trans::None` when nothing is queued.
+///
+/// States should call this from `handle_event` and `update` so page requests are acted on.
+pub fn ui_transition(world: &mut World) -> Trans {
+    let request = world.write_resource::<UiTransitions>().next();
+    let build = |name: &str| {
+        let state = world.read_resource::<StateRegistry>().build(name);
+        if state.is_none() {
+            eprintln!("ERROR: No state registered as '{}'", name);
+        }
+        state
+    };
+    let depth = world.read_resource::<StateRegistry>().depth();
+    let (trans, depth) = match request {
+        Some(TransitionRequest::Push(name)) => match build(&name) {
+            Some(state) => (Trans::Push(state), depth + 1),
+            None => (Trans::None, depth),
+        },
+        Some(TransitionRequest::Switch(name)) => {
+            (build(&name).map(Trans::Switch).unwrap_or(Trans::None), depth)
+        }
+        // Several pops queued in one frame may outnumber the states above the root
+        Some(TransitionRequest::Pop) if depth == 0 => (Trans::None, depth),
+        Some(TransitionRequest::Pop) => (Trans::Pop, depth - 1),
+        Some(TransitionRequest::Quit) => (Trans::Quit, depth),
+        None => (Trans::None, depth),
+    };
+    world.write_resource::<StateRegistry>().set_depth(depth);
+    trans
+}
+
+#[derive(Deserialize)]
+struct StateName {
+    state: String,
+}
+
+fn queue_named(
+    res: &Resources,
+    name: String,
+    request: fn(String) -> TransitionRequest,
+) -> Result<(), String> {
+    if !res.fetch::<StateRegistry>(0).contains(&name) {
+        return Err(format!("No state registered as '{}'", name));
+    }
+    res.fetch_mut::<UiTransitions>(0).request(request(name));
+    Ok(())
+}
+
+/// Registers the `state.push`, `state.switch`, `state.pop` and `state.quit` calls, so pages can
+/// request transitions with for example `game.call("state.push", {state: "options"})`.
+pub fn register_transition_calls(rpc: &mut RpcRegistry) {
+    rpc.register("state.push", |res, args: StateName| {
+        queue_named(res, args.state, TransitionRequest::Push)
+    });
+    rpc.register("state.switch", |res, args: StateName| {
+        queue_named(res, args.state, TransitionRequest::Switch)
+    });
+    // Popping the root state would quit the game, so pages can only pop states above it
+    rpc.register("state.pop", |res, _: ()| {
+        if res.fetch::<StateRegistry>(0).depth() > 0 {
+            res.fetch_mut::<UiTransitions>(0)
+                .request(TransitionRequest::Pop);
+        }
+        Ok(())
+    });
+    rpc.register("state.quit", |res, _: ()| {
+        res.fetch_mut::<UiTransitions>(0)
+            .request(TransitionRequest::Quit);
+        Ok(())
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+    use super::*;
+
+    #[test]
+    fn pages_cant_pop_the_root_state() {
+        let mut res = Resources::new();
+        res.add(StateRegistry::new());
+        res.add(UiTransitions::new());
+        let mut rpc = RpcRegistry::new();
+        register_transition_calls(&mut rpc);
+
+        assert!(rpc.call(&res, "state.pop", Value::Null).is_ok());
+        assert_eq!(res.fetch_mut::<UiTransitions>(0).next(), None);
+
+        res.fetch_mut::<StateRegistry>(0).set_depth(1);
+        assert!(rpc.call(&res, "state.pop", Value::Null).is_ok());
+        assert_eq!(
+            res.fetch_mut::<UiTransitions>(0).next(),
+            Some(TransitionRequest::Pop)
+        );
+    }
+}
@@ -3,7 +3,9 @@ extern crate glutin;
 extern crate servo as libservo;
 
 use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::rc::Rc;
+use std::time::{Duration, Instant};
 use std::ops::{Deref, DerefMut};
 use self::libservo::compositing::compositor_thread::EventLoopWaker;
 use self::libservo::{gl, BrowserId};
@@ -21,7 +23,11 @@ use self::libservo::servo_url::ServoUrl;
 use self::libservo::msg::constellation_msg::{self, Key};
 use self::libservo::ipc_channel::ipc::IpcSender;
 use amethyst::winit::EventsLoopProxy;
+use serde_json::Value;
 use amethyst::renderer::Texture;
+use super::gl_state::GlStateGuard;
+use super::readback::{PixelReadback, UiFrame};
+use super::{bridge, keys, FrameUpload, UiMessage, UnhandledKey};
 
 pub struct ServoWindow {
     pub waker: EventsLoopProxy,
@@ -31,6 +37,34 @@ pub struct ServoWindow {
     pub dimensions: Arc<Mutex<(u32, u32)>>,
     pub target_texture: Arc<Mutex<Option<u32>>>,
     pub frame_buffer: Arc<Mutex<Option<u32>>>,
+    // Colour and depth renderbuffers of the offscreen framebuffer, resized with the window
+    pub offscreen_buffers: Arc<Mutex<Option<(u32, u32)>>>,
+    // State gfx had bound before the current composite, restored in present
+    pub gl_state: Arc<Mutex<Option<GlStateGuard>>>,
+    // Compare GL state before and after each composite and report leaks
+    pub debug_gl_state: bool,
+    pub upload: FrameUpload,
+    // Pixel buffers composited frames are read back through, for hit testing and, when upload is
+    // FrameUpload::Readback, for uploading
+    pub readback: Arc<Mutex<Option<PixelReadback>>>,
+    // Most recent frame read back, taken by ServoUiSystem for upload
+    pub frame: Arc<Mutex<Option<UiFrame>>>,
+    // Alpha channel of the most recent frame read back, with its width and height, rows from the
+    // top
+    pub hit_alpha: Arc<Mutex<Option<(u32, u32, Vec<u8>)>>>,
+    // Last animation state reported by Servo, Animating means it needs pumping every frame
+    pub animation_state: Arc<Mutex<AnimationState>>,
+    // Composites presented since the count was last taken
+    pub composites: Arc<AtomicUsize>,
+    // When the current composite was prepared, and the time spent compositing since last taken
+    pub composite_started: Arc<Mutex<Option<Instant>>>,
+    pub composite_time: Arc<Mutex<Duration>>,
+    // Multiplies the window's DPI factor to scale page content, set from DisplaySettings
+    pub ui_scale: Arc<Mutex<f32>>,
+    // Messages sent by page JavaScript, waiting to be published by ServoUiSystem
+    pub inbox: Arc<Mutex<Vec<UiMessage>>>,
+    // Key presses pages didn't handle, waiting to be published by ServoUiSystem
+    pub unhandled_keys: Arc<Mutex<Vec<UnhandledKey>>>,
 }
 
 impl ServoWindow where {
@@ -129,15 +163,17 @@ impl ServoWindow where {
             0,
         );
         match self.gl.check_frame_buffer_status(gl::FRAMEBUFFER) {
-            gl::FRAMEBUFFER_COMPLETE => match self.frame_buffer.lock() {
-                Ok(mut fb) => {
+            gl::FRAMEBUFFER_COMPLETE => match (self.frame_buffer.lock(), self.readback.lock()) {
+                (Ok(mut fb), Ok(mut readback)) => {
                     self.gl.bind_framebuffer(gl::FRAMEBUFFER, 0);
                     self.gl.bind_renderbuffer(gl::RENDERBUFFER, 0);
                     self.gl.bind_texture(gl::TEXTURE_2D, 0);
                     *fb = Some(frame_buffer);
+                    // Only read back for hit testing
+                    *readback = Some(PixelReadback::new(&*self.gl, width, height));
                     Ok(())
                 }
-                Err(_) => {
+                _ => {
                     self.gl.delete_framebuffers(&[frame_buffer]);
                     self.gl.delete_renderbuffers(&[depth_buffer]);
                     Err(0)
@@ -151,6 +187,280 @@ impl ServoWindow where {
         }
     }
 
+    /// Creates a framebuffer with its own colour renderbuffer for Servo to composite into, along
+    /// with the pixel buffers used to read each frame back. Used instead of setup_framebuffer when
+    /// frames are uploaded through FrameUpload::Readback.
+    pub fn setup_offscreen_framebuffer(&self) -> Result<(), u32> {
+        let (width, height) = self.get_dimensions();
+
+        let frame_buffer = self.gl.gen_framebuffers(1)[0];
+        self.gl.bind_framebuffer(gl::FRAMEBUFFER, frame_buffer);
+        let renderbuffers = self.gl.gen_renderbuffers(2);
+        let (color_buffer, depth_buffer) = (renderbuffers[0], renderbuffers[1]);
+        self.gl.bind_renderbuffer(gl::RENDERBUFFER, color_buffer);
+        self.gl
+            .renderbuffer_storage(gl::RENDERBUFFER, gl::RGBA8, width as i32, height as i32);
+        self.gl.framebuffer_renderbuffer(
+            gl::FRAMEBUFFER,
+            gl::COLOR_ATTACHMENT0,
+            gl::RENDERBUFFER,
+            color_buffer,
+        );
+        self.gl.bind_renderbuffer(gl::RENDERBUFFER, depth_buffer);
+        self.gl.renderbuffer_storage(
+            gl::RENDERBUFFER,
+            gl::DEPTH_COMPONENT,
+            width as i32,
+            height as i32,
+        );
+        self.gl.framebuffer_renderbuffer(
+            gl::FRAMEBUFFER,
+            gl::DEPTH_ATTACHMENT,
+            gl::RENDERBUFFER,
+            depth_buffer,
+        );
+        let status = self.gl.check_frame_buffer_status(gl::FRAMEBUFFER);
+        self.gl.bind_framebuffer(gl::FRAMEBUFFER, 0);
+        self.gl.bind_renderbuffer(gl::RENDERBUFFER, 0);
+        if status != gl::FRAMEBUFFER_COMPLETE {
+            self.gl.delete_framebuffers(&[frame_buffer]);
+            self.gl.delete_renderbuffers(&renderbuffers);
+            return Err(status);
+        }
+        match (
+            self.frame_buffer.lock(),
+            self.offscreen_buffers.lock(),
+            self.readback.lock(),
+        ) {
+            (Ok(mut fb), Ok(mut buffers), Ok(mut readback)) => {
+                *fb = Some(frame_buffer);
+                *buffers = Some((color_buffer, depth_buffer));
+                *readback = Some(PixelReadback::new(&*self.gl, width, height));
+                Ok(())
+            }
+            _ => {
+                self.gl.delete_framebuffers(&[frame_buffer]);
+                self.gl.delete_renderbuffers(&renderbuffers);
+                Err(0)
+            }
+        }
+    }
+
+    /// Reallocates the offscreen framebuffer's renderbuffers and the pixel buffers if the window
+    /// has been resized since they were made. Does nothing before setup_offscreen_framebuffer.
+    pub fn fit_offscreen_framebuffer(&self) {
+        let (width, height) = self.get_dimensions();
+        let buffers = match self.offscreen_buffers.lock() {
+            Ok(buffers) => *buffers,
+            Err(_) => None,
+        };
+        let (color_buffer, depth_buffer) = match buffers {
+            Some(buffers) => buffers,
+            None => return,
+        };
+        let mut readback = match self.readback.lock() {
+            Ok(readback) => readback,
+            Err(_) => {
+                eprintln!("ERROR: Readback lock poisoned.");
+                return;
+            }
+        };
+        match *readback {
+            Some(ref mut readback) if readback.size() != (width, height) => {
+                let _state = GlStateGuard::new(self.gl.clone(), false);
+                self.gl.bind_renderbuffer(gl::RENDERBUFFER, color_buffer);
+                self.gl
+                    .renderbuffer_storage(gl::RENDERBUFFER, gl::RGBA8, width as i32, height as i32);
+                self.gl.bind_renderbuffer(gl::RENDERBUFFER, depth_buffer);
+                self.gl.renderbuffer_storage(
+                    gl::RENDERBUFFER,
+                    gl::DEPTH_COMPONENT,
+                    width as i32,
+                    height as i32,
+                );
+                readback.resize(&*self.gl, width, height);
+            }
+            _ => {}
+        }
+    }
+
+    /// Reads the composited frame out of the bound framebuffer, keeping it for hit testing and
+    /// take_frame.
+    fn read_back_frame(&self) {
+        self.keep_frame(|readback, gl| readback.read(gl));
+    }
+
+    /// Maps the last frame read back if it's still waiting in a pixel buffer, keeping it like
+    /// read_back_frame. Called once Servo has stopped compositing, as no later read pushes it
+    /// out.
+    pub fn flush_readback(&self) {
+        let _state = GlStateGuard::new(self.gl.clone(), false);
+        self.keep_frame(|readback, gl| readback.flush(gl));
+    }
+
+    fn keep_frame<F>(&self, read: F)
+    where
+        F: FnOnce(&mut PixelReadback, &gl::Gl) -> Option<Vec<u8>>,
+    {
+        let pixels = match self.readback.lock() {
+            Ok(mut guard) => match *guard {
+                Some(ref mut readback) => {
+                    read(readback, &*self.gl).map(|pixels| (readback.size(), pixels))
+                }
+                None => None,
+            },
+            Err(_) => {
+                eprintln!("ERROR: Readback lock poisoned.");
+                None
+            }
+        };
+        let ((width, height), pixels) = match pixels {
+            Some(pixels) => pixels,
+            None => return,
+        };
+        let alpha = pixels.chunks(4).map(|pixel| pixel[3]).collect();
+        match self.hit_alpha.lock() {
+            Ok(mut hit_alpha) => *hit_alpha = Some((width, height, alpha)),
+            Err(_) => eprintln!("ERROR: Hit test lock poisoned."),
+        }
+        if self.upload == FrameUpload::Readback {
+            match self.frame.lock() {
+                Ok(mut frame) => {
+                    *frame = Some(UiFrame {
+                        width: width,
+                        height: height,
+                        pixels: pixels,
+                    });
+                }
+                Err(_) => {
+                    eprintln!("ERROR: Frame lock poisoned.");
+                }
+            }
+        }
+    }
+
+    /// Takes the most recent frame read back from Servo, if there is one which has not already
+    /// been taken.
+    pub fn take_frame(&self) -> Option<UiFrame> {
+        match self.frame.lock() {
+            Ok(mut frame) => frame.take(),
+            Err(_) => {
+                eprintln!("ERROR: Frame lock poisoned.");
+                None
+            }
+        }
+    }
+
+    /// Whether Servo has asked to be pumped every frame because the page is animating.
+    pub fn is_animating(&self) -> bool {
+        match self.animation_state.lock() {
+            Ok(state) => match *state {
+                AnimationState::Animating => true,
+                AnimationState::Idle => false,
+            },
+            Err(_) => {
+                eprintln!("ERROR: Animation state lock poisoned, assuming the page is animating.");
+                true
+            }
+        }
+    }
+
+    /// Returns the number of composites presented since the last call.
+    pub fn take_composite_count(&self) -> u32 {
+        self.composites.swap(0, Ordering::SeqCst) as u32
+    }
+
+    /// Alpha of the last frame read back at a window position, measured from the top left, or
+    /// None if no frame has been read back yet. Reads no GL state, so it never waits on the GPU.
+    pub fn alpha_at(&self, x: u32, y: u32) -> Option<u8> {
+        match self.hit_alpha.lock() {
+            Ok(hit_alpha) => match *hit_alpha {
+                Some((width, height, ref alpha)) if x < width && y < height => {
+                    alpha.get((y * width + x) as usize).cloned()
+                }
+                _ => None,
+            },
+            Err(_) => None,
+        }
+    }
+
+    /// Estimated GPU memory used by the framebuffer Servo composites into, the pixel buffers
+    /// frames are read back through and, with FrameUpload::Readback, the uploaded texture. Zero
+    /// until the framebuffer exists.
+    pub fn texture_bytes(&self) -> u64 {
+        let has_framebuffer = match self.frame_buffer.lock() {
+            Ok(fb) => fb.is_some(),
+            Err(_) => false,
+        };
+        if !has_framebuffer {
+            return 0;
+        }
+        let (width, height) = self.get_dimensions();
+        let surface = width as u64 * height as u64 * 4;
+        match self.upload {
+            // Colour, depth and two pixel buffers for hit testing
+            FrameUpload::SharedTexture => surface * 4,
+            // Colour, depth, two pixel buffers and the uploaded texture
+            FrameUpload::Readback => surface * 5,
+        }
+    }
+
+    /// Returns the time spent between preparing and presenting composites since the last call.
+    pub fn take_composite_time(&self) -> Duration {
+        match self.composite_time.lock() {
+            Ok(mut time) => ::std::mem::replace(&mut *time, Duration::from_secs(0)),
+            Err(_) => {
+                eprintln!("ERROR: Composite time lock poisoned.");
+                Duration::from_secs(0)
+            }
+        }
+    }
+
+    fn start_composite_timer(&self) {
+        if let Ok(mut started) = self.composite_started.lock() {
+            *started = Some(Instant::now());
+        }
+    }
+
+    fn stop_composite_timer(&self) {
+        let started = match self.composite_started.lock() {
+            Ok(mut started) => started.take(),
+            Err(_) => None,
+        };
+        if let (Some(started), Ok(mut time)) = (started, self.composite_time.lock()) {
+            *time += started.elapsed();
+        }
+    }
+
+    /// Queues a message to be published as if the page had sent it.
+    pub fn queue_message(&self, message: UiMessage) {
+        match self.inbox.lock() {
+            Ok(mut inbox) => inbox.push(message),
+            Err(_) => {
+                eprintln!("ERROR: Message inbox lock poisoned, messages from pages are lost.");
+            }
+        }
+    }
+
+    /// Takes the messages pages have sent since the last call.
+    pub fn take_messages(&self) -> Vec<UiMessage> {
+        match self.inbox.lock() {
+            Ok(mut inbox) => inbox.drain(..).collect(),
+            Err(_) => {
+                eprintln!("ERROR: Message inbox lock poisoned, messages from pages are lost.");
+                vec![]
+            }
+        }
+    }
+
+    /// Key presses pages didn't handle since the last call.
+    pub fn take_unhandled_keys(&self) -> Vec<UnhandledKey> {
+        match self.unhandled_keys.lock() {
+            Ok(mut unhandled) => unhandled.drain(..).collect(),
+            Err(_) => vec![],
+        }
+    }
+
     /// Binds the framebuffer which has been marked using set_texture and setup_framebuffer to the
     /// render target. Will fail with Err(0) if the lock is poisoned, or the framebuffer has not
     /// been set up. Will fail with an appropriate GLenum if the framebuffer check fails. In the
@@ -174,6 +484,30 @@ impl ServoWindow where {
     pub fn disable_fb(&self) {
         self.gl.bind_framebuffer(gl::FRAMEBUFFER, 0);
     }
+
+    /// Snapshots the GL state gfx depends on so it can be restored once Servo has composited.
+    pub fn save_gl_state(&self) {
+        match self.gl_state.lock() {
+            Ok(mut state) => {
+                *state = Some(GlStateGuard::new(self.gl.clone(), self.debug_gl_state));
+            }
+            Err(_) => {
+                eprintln!("ERROR: GL state lock poisoned, gfx state will not be restored after composite.");
+            }
+        }
+    }
+
+    /// Restores the GL state saved by save_gl_state, if any.
+    pub fn restore_gl_state(&self) {
+        match self.gl_state.lock() {
+            Ok(mut state) => {
+                state.take();
+            }
+            Err(_) => {
+                eprintln!("ERROR: GL state lock poisoned, gfx state could not be restored after composite.");
+            }
+        }
+    }
 }
 
 struct WinitEventLoopWaker {
@@ -244,7 +578,16 @@ impl WindowMethods for ServoWindow {
         size
     }
 
-    fn set_animation_state(&self, _state: AnimationState) {}
+    fn set_animation_state(&self, state: AnimationState) {
+        match self.animation_state.lock() {
+            Ok(mut animation_state) => {
+                *animation_state = state;
+            }
+            Err(_) => {
+                eprintln!("ERROR: Animation state lock poisoned.");
+            }
+        }
+    }
 
     fn set_inner_size(&self, _: BrowserId, _size: Size2D<u32>) {}
 
@@ -253,6 +596,8 @@ impl WindowMethods for ServoWindow {
     fn set_fullscreen_state(&self, _: BrowserId, _state: bool) {}
 
     fn prepare_for_composite(&self, _width: usize, _height: usize) -> bool {
+        self.start_composite_timer();
+        self.save_gl_state();
         match self.enable_fb() {
             Ok(()) => {
                 println!("Successfully bound framebuffer");
@@ -260,13 +605,18 @@ impl WindowMethods for ServoWindow {
             }
             Err(()) => {
                 println!("Failed to enable framebuffer");
+                self.restore_gl_state();
                 false
             }
         }
     }
 
     fn present(&self) {
+        self.read_back_frame();
         self.disable_fb();
+        self.restore_gl_state();
+        self.composites.fetch_add(1, Ordering::SeqCst);
+        self.stop_composite_timer();
         println!("Unbound framebuffer");
     }
 
@@ -280,9 +630,24 @@ impl WindowMethods for ServoWindow {
 
     fn status(&self, _: BrowserId, _status: Option<String>) {}
 
-    fn load_start(&self, _: BrowserId) {}
+    fn load_start(&self, browser: BrowserId) {
+        // Queued before anything the new document sends, so per page state can be dropped here
+        // without losing what the new document sets up while it loads
+        self.queue_message(UiMessage {
+            browser: browser,
+            name: "page.started".to_string(),
+            payload: Value::Null,
+        });
+    }
 
-    fn load_end(&self, _: BrowserId) {}
+    fn load_end(&self, browser: BrowserId) {
+        // Published like a message from the page, so systems tracking per page state can reset
+        self.queue_message(UiMessage {
+            browser: browser,
+            name: "page.loaded".to_string(),
+            payload: Value::Null,
+        });
+    }
 
     fn history_changed(&self, _: BrowserId, _history: Vec<LoadData>, _current: usize) {}
 
@@ -295,24 +660,62 @@ impl WindowMethods for ServoWindow {
 
     fn set_favicon(&self, _: BrowserId, _: ServoUrl) {}
 
-    /// Helper function to handle keyboard events.
+    /// Called for key presses the page didn't handle.
     fn handle_key(
         &self,
         _: Option<BrowserId>,
-        _ch: Option<char>,
-        _key: Key,
-        _mods: constellation_msg::KeyModifiers,
+        ch: Option<char>,
+        key: Key,
+        mods: constellation_msg::KeyModifiers,
     ) {
+        let key = match keys::virtual_key(key) {
+            Some(key) => key,
+            None => return,
+        };
+        match self.unhandled_keys.lock() {
+            Ok(mut unhandled) => unhandled.push(UnhandledKey {
+                key: key,
+                character: ch,
+                modifiers: keys::winit_modifiers(mods),
+            }),
+            Err(_) => eprintln!("ERROR: Unhandled key lock poisoned, keys from pages are lost."),
+        }
     }
 
-    fn allow_navigation(&self, _: BrowserId, _: ServoUrl, _response_chan: IpcSender<bool>) {}
+    fn allow_navigation(
+        &self,
+        browser: BrowserId,
+        url: ServoUrl,
+        response_chan: IpcSender<bool>,
+    ) {
+        // Navigations to the message scheme carry messages from the page and never navigate
+        let allow = match bridge::parse_messages(browser, &url) {
+            Some(messages) => {
+                for message in messages {
+                    self.queue_message(message);
+                }
+                false
+            }
+            None => true,
+        };
+        if let Err(e) = response_chan.send(allow) {
+            eprintln!("ERROR: Failed to answer navigation request: {:?}", e);
+        }
+    }
 
     fn supports_clipboard(&self) -> bool {
         true
     }
 
     fn hidpi_factor(&self) -> TypedScale<f32, DeviceIndependentPixel, DevicePixel> {
-        TypedScale::new(self.window.hidpi_factor())
+        let ui_scale = match self.ui_scale.lock() {
+            Ok(scale) => *scale,
+            Err(_) => {
+                eprintln!("ERROR: UI scale lock poisoned.");
+                1.0
+            }
+        };
+        TypedScale::new(self.window.hidpi_factor() * ui_scale)
     }
 
     fn handle_panic(&self, _: BrowserId, _reason: String, _backtrace: Option<String>) {